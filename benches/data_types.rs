@@ -14,7 +14,7 @@ extern crate test;
 
 
 use rand::{Rand, Rng};
-use serde_mtproto::{MtProtoSized, to_bytes, to_writer, from_bytes};
+use serde_mtproto::{MtProtoSized, native, to_bytes, to_writer, from_bytes, from_bytes_into};
 use test::Bencher;
 
 
@@ -25,7 +25,7 @@ fn random_string<R: Rng>(rng: &mut R, words_count: (usize, usize)) -> String {
 }
 
 
-#[derive(Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[derive(Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized, MtProtoSerialize, MtProtoDeserialize)]
 #[id = "0xd594ba98"]
 struct Struct {
     bar: bool,
@@ -73,6 +73,36 @@ fn struct_deserialize(b: &mut Bencher) {
     });
 }
 
+#[bench]
+fn struct_serialize_native(b: &mut Bencher) {
+    let struct_ = Struct {
+        bar: false,
+        s: "Hello, world!".to_owned(),
+        group: (-500, 0xffff_ffff_ffff, -64),
+    };
+    let mut v = vec![0; struct_.size_hint().unwrap() + 4];
+
+    b.iter(|| {
+        native::to_writer(v.as_mut_slice(), &struct_).unwrap();
+    });
+}
+
+#[bench]
+fn struct_deserialize_native(b: &mut Bencher) {
+    let struct_serialized = [
+        152, 186, 148, 213,
+        55, 151, 121, 188,
+        13, 72, 101, 108, 108, 111, 44, 32, 119, 111, 114, 108, 100, 33, 0, 0,
+        12, 254, 255, 255,
+        255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0,
+        192, 255, 255, 255,
+    ];
+
+    b.iter(|| {
+        native::from_bytes::<Struct>(&struct_serialized).unwrap();
+    });
+}
+
 #[bench]
 fn random_struct_serialize(b: &mut Bencher) {
     let random_struct: Struct = rand::random();
@@ -93,9 +123,41 @@ fn random_struct_deserialize(b: &mut Bencher) {
     });
 }
 
+#[bench]
+fn random_struct_deserialize_into(b: &mut Bencher) {
+    let random_struct: Struct = rand::random();
+    let random_struct_serialized = to_bytes(&random_struct).unwrap();
+    let mut place: Struct = rand::random();
+
+    b.iter(|| {
+        from_bytes_into(&mut place, &random_struct_serialized, None).unwrap();
+    });
+}
+
+#[bench]
+fn random_struct_vec_deserialize(b: &mut Bencher) {
+    let random_structs: Vec<Struct> = (0..16).map(|_| rand::random()).collect();
+    let random_structs_serialized = to_bytes(&random_structs).unwrap();
+
+    b.iter(|| {
+        from_bytes::<Vec<Struct>>(&random_structs_serialized, None).unwrap();
+    });
+}
+
+#[bench]
+fn random_struct_vec_deserialize_into(b: &mut Bencher) {
+    let random_structs: Vec<Struct> = (0..16).map(|_| rand::random()).collect();
+    let random_structs_serialized = to_bytes(&random_structs).unwrap();
+    let mut place = Vec::new();
+
+    b.iter(|| {
+        from_bytes_into::<Vec<Struct>>(&mut place, &random_structs_serialized, None).unwrap();
+    });
+}
+
 
 #[derive(Rand, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
-#[id = "200c5e59"]
+#[id = "0x200c5e59"]
 struct Nothing;
 
 #[bench]
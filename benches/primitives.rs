@@ -8,7 +8,7 @@ extern crate test;
 extern crate serde_mtproto;
 
 
-use serde_mtproto::{to_bytes, to_writer, from_bytes};
+use serde_mtproto::{IntVector, PackedInts, to_bytes, to_writer, from_bytes};
 use test::Bencher;
 
 
@@ -77,3 +77,49 @@ bench_primitive! {
     (::extprim::u128::u128, ::extprim::u128::u128),
         two_u128_tuple_serialize => [u8; 32], two_u128_tuple_deserialize;
 }
+
+
+macro_rules! bench_packed_ints {
+    ($($ty:ty = $make:expr, $ser:ident, $de:ident;)*) => {
+        $(
+            #[bench]
+            fn $ser(b: &mut Bencher) {
+                let value: $ty = $make;
+                let mut v = vec![0; to_bytes(&value).unwrap().len()];
+
+                b.iter(|| {
+                    to_writer(v.as_mut_slice(), &value).unwrap();
+                });
+            }
+
+            #[bench]
+            fn $de(b: &mut Bencher) {
+                let value: $ty = $make;
+                let value_serialized = to_bytes(&value).unwrap();
+
+                b.iter(|| {
+                    from_bytes::<$ty>(&value_serialized, None).unwrap();
+                });
+            }
+        )*
+    };
+}
+
+
+// Element-wise vs. bulk encodings with identical MTProto representation
+bench_packed_ints! {
+    Vec<i32> = (0..1024).map(|_| rand::random()).collect(),
+        i32_vec1024_serialize, i32_vec1024_deserialize;
+    IntVector<i32> = IntVector::new((0..1024).map(|_| rand::random()).collect()),
+        i32_int_vector1024_serialize, i32_int_vector1024_deserialize;
+
+    Vec<i64> = (0..1024).map(|_| rand::random()).collect(),
+        i64_vec1024_serialize, i64_vec1024_deserialize;
+    IntVector<i64> = IntVector::new((0..1024).map(|_| rand::random()).collect()),
+        i64_int_vector1024_serialize, i64_int_vector1024_deserialize;
+
+    [u32; 4] = rand::random(),
+        u32_array4_serialize, u32_array4_deserialize;
+    PackedInts<u32, 4> = PackedInts::new(rand::random()),
+        u32_packed_ints4_serialize, u32_packed_ints4_deserialize;
+}
@@ -0,0 +1,33 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_mtproto;
+extern crate serde_mtproto_fuzz;
+
+use serde_mtproto::{ByteBuf, from_bytes, to_bytes};
+use serde_mtproto_fuzz::{EncryptedMessage, Message, Point};
+
+
+fuzz_target!(|data: &[u8]| {
+    // Deserialization must never panic, whatever the input is
+    let _ = from_bytes::<bool>(data, None);
+    let _ = from_bytes::<String>(data, None);
+    let _ = from_bytes::<ByteBuf>(data, None);
+    let _ = from_bytes::<Vec<i64>>(data, None);
+    let _ = from_bytes::<Point>(data, None);
+
+    // Anything that was deserialized successfully must survive a round trip.
+    // Comparing bytes directly is not possible since padding bytes are not validated.
+    if let Ok(message) = from_bytes::<Message>(data, None) {
+        let serialized = to_bytes(&message).expect("failed to serialize a deserialized value");
+        let deserialized: Message = from_bytes(&serialized, None).expect("failed to deserialize");
+        assert_eq!(deserialized, message);
+    }
+
+    // Unsized byte sequences read up to the end of input
+    if let Ok(message) = from_bytes::<EncryptedMessage>(data, None) {
+        let serialized = to_bytes(&message).expect("failed to serialize a deserialized value");
+        let deserialized: EncryptedMessage = from_bytes(&serialized, None).expect("failed to deserialize");
+        assert_eq!(deserialized, message);
+    }
+});
@@ -0,0 +1,24 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_mtproto;
+extern crate serde_mtproto_fuzz;
+
+use serde_mtproto::{Boxed, BoxedWithSize, Raw, WithRaw, WithSize, from_bytes};
+use serde_mtproto_fuzz::{Message, Point};
+
+
+fuzz_target!(|data: &[u8]| {
+    let _ = from_bytes::<Boxed<Point>>(data, None);
+    let _ = from_bytes::<WithSize<Message>>(data, None);
+    let _ = from_bytes::<BoxedWithSize<Message>>(data, None);
+
+    // Captured bytes must always be a prefix of the input
+    if let Ok(raw) = from_bytes::<Raw<Boxed<Message>>>(data, None) {
+        assert!(data.starts_with(raw.as_bytes()));
+    }
+
+    if let Ok(with_raw) = from_bytes::<WithRaw<Message>>(data, None) {
+        assert!(data.starts_with(with_raw.raw_bytes()));
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_mtproto;
+extern crate serde_mtproto_fuzz;
+
+use serde_mtproto::{MtProtoSized, from_bytes, to_bytes};
+use serde_mtproto_fuzz::Message;
+
+
+fuzz_target!(|message: Message| {
+    let serialized = match to_bytes(&message) {
+        Ok(serialized) => serialized,
+        // Some generated values are legitimately unserializable (e.g. too long strings)
+        Err(_) => return,
+    };
+
+    assert_eq!(message.size_hint().expect("failed to compute size hint"), serialized.len());
+
+    let deserialized: Message = from_bytes(&serialized, None).expect("failed to deserialize");
+    assert_eq!(deserialized, message);
+});
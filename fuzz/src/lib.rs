@@ -0,0 +1,47 @@
+//! Representative schema types shared by fuzz targets.
+
+extern crate arbitrary;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_mtproto;
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+
+use serde::de::{Deserializer, DeserializeSeed};
+use serde_mtproto::{Boxed, BoxedWithSize, ByteBuf, UnsizedByteBuf, UnsizedByteBufSeed, WithSize};
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[mtproto_identifiable(arbitrary)]
+#[id = "0xdeadbeef"]
+pub struct Message {
+    pub msg_id: u64,
+    pub seq_no: u32,
+    pub flag: bool,
+    pub text: String,
+    pub point: Boxed<Point>,
+    pub points: Vec<BoxedWithSize<Point>>,
+    pub sized: WithSize<(i64, u16)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[mtproto_identifiable(arbitrary)]
+#[id = "0xb01dface"]
+pub struct Point(pub i32, pub i32, pub i32);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct EncryptedMessage {
+    pub auth_key_id: i64,
+    pub msg_key: [u32; 4],
+    pub raw_info: ByteBuf,
+    #[serde(deserialize_with = "deserialize_encrypted_data")]
+    pub encrypted_data: UnsizedByteBuf,
+}
+
+fn deserialize_encrypted_data<'de, D>(deserializer: D) -> Result<UnsizedByteBuf, D::Error>
+    where D: Deserializer<'de>
+{
+    UnsizedByteBufSeed::to_end().deserialize(deserializer)
+}
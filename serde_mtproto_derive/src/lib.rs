@@ -1,4 +1,4 @@
-//! This crate provides Serde MTProto's two derive macros.
+//! This crate provides Serde MTProto's derive macros.
 //!
 //! ```
 //! # #[macro_use] extern crate serde_mtproto_derive;
@@ -40,49 +40,493 @@
 //!
 //! # fn main() {}
 //! ```
+//!
+//! # Type ids
+//!
+//! Ids can be written either as hexadecimal strings like `#[id = "0xbeefdead"]` or as integer
+//! literals like `#[id = 0xbeefdead]`. Each variant of an enum must have a distinct id.
+//! `#[derive(MtProtoIdentifiable)]` sorts ids at compile time so that `variant_for_id()` finds
+//! the variant of an id with a binary search, however many variants an enum has.
+//!
+//! ```
+//! # #[macro_use] extern crate serde_mtproto_derive;
+//! #[derive(MtProtoIdentifiable, MtProtoSized)]
+//! enum Bool {
+//!     #[id = 0xbc799737]
+//!     False,
+//!     #[id = 0x997275b5]
+//!     True,
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! # Unknown constructors
+//!
+//! A single enum variant can be marked with `#[mtproto(other)]` instead of `#[id = "0x..."]` to
+//! keep values with type ids which are not known at compile time. It must have exactly two
+//! fields: the type id (`u32`) and raw bytes of the value (`Vec<u8>` or anything which
+//! implements `From<Vec<u8>>` and dereferences to `[u8]`), either named `id` and `raw` or as a
+//! tuple variant in this order.
+//!
+//! `Boxed` and `BoxedWithSize` then deserialize unknown constructors into this variant instead of
+//! failing with `ErrorKind::InvalidTypeId` and serialize it back byte-exactly. The raw bytes
+//! extend to the declared size of `BoxedWithSize` or of another enclosing sized value. Without
+//! one the extent of an unknown value can't be determined, so it still fails with
+//! `ErrorKind::InvalidTypeId` instead of swallowing the following values.
+//!
+//! ```
+//! # #[macro_use] extern crate serde_mtproto_derive;
+//! #[derive(MtProtoIdentifiable, MtProtoSized)]
+//! enum MessageMedia {
+//!     #[id = "0x3ded6320"]
+//!     Empty,
+//!     #[id = "0x7912b71f"]
+//!     Geo {
+//!         lat: f64,
+//!         long: f64,
+//!     },
+//!     #[mtproto(other)]
+//!     Unknown {
+//!         id: u32,
+//!         raw: Vec<u8>,
+//!     },
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! # Sizing fields
+//!
+//! `#[derive(MtProtoSized)]` follows Serde attributes of fields, so that the computed size matches
+//! the serialized representation:
+//!
+//! * fields with `#[serde(skip)]` or `#[serde(skip_serializing)]` are not counted;
+//! * fields with `#[serde(skip_serializing_if = "predicate")]` are counted only if the predicate
+//!   returns `false`.
+//!
+//! The same can be requested explicitly with `#[mtproto_sized(skip)]`. Fields can also be sized
+//! with `#[mtproto_sized(with = "module")]` by `module::size_hint`, like the adapters in
+//! `serde_mtproto::with` provide, or with `#[mtproto_sized(size_with = "function")]` where
+//! `function` has the `fn(&T) -> serde_mtproto::Result<usize>` signature. These take precedence
+//! over Serde attributes. Fields with `#[serde(with = "...")]` or
+//! `#[serde(serialize_with = "...")]` must use one of them, otherwise the derive fails since the
+//! size can't be inferred.
+//!
+//! ```
+//! # extern crate serde;
+//! # extern crate serde_mtproto;
+//! # #[macro_use] extern crate serde_derive;
+//! # #[macro_use] extern crate serde_mtproto_derive;
+//! use std::time::SystemTime;
+//!
+//! #[derive(Serialize, MtProtoSized)]
+//! struct Message {
+//!     id: u32,
+//!     #[serde(with = "serde_mtproto::with::unix_time")]
+//!     #[mtproto_sized(with = "serde_mtproto::with::unix_time")]
+//!     date: SystemTime,
+//!     #[serde(skip)]
+//!     cached_text: Option<String>,
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! # Native serialization
+//!
+//! `#[derive(MtProtoSerialize, MtProtoDeserialize)]` implement `serde_mtproto::MtProtoSerialize`
+//! and `serde_mtproto::MtProtoDeserialize` which encode values directly, without going through
+//! Serde, using the same `#[id = ...]` and `#[mtproto(other)]` attributes. Boxed values always
+//! carry their ids, including the ones of enum variants. Fields are boxed unless marked with
+//! `#[mtproto(bare)]`, and fields with `#[mtproto(serde)]` go through their Serde
+//! implementations. Like with `Boxed`, unknown constructors are only kept by the
+//! `#[mtproto(other)]` variant within a declared size (see `Deserializer::with_declared_size`).
+//! See the `serde_mtproto::native` module for details.
+//!
+//! ```
+//! # extern crate serde_mtproto;
+//! # #[macro_use] extern crate serde_mtproto_derive;
+//! #[derive(MtProtoSerialize, MtProtoDeserialize)]
+//! #[id = 0xbeefdead]
+//! struct Message {
+//!     message_id: u32,
+//!     #[mtproto(bare)]
+//!     user_ids: Vec<u32>,
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! # TL schemas
+//!
+//! `#[derive(TlDescribe)]` implements `serde_mtproto::TlDescribe`, which describes TL
+//! constructors of a type so that `serde_mtproto::Schema` can write them as a `.tl` schema.
+//! Ids come from `#[id = ...]`, fields with `#[mtproto(bare)]` are described as bare and
+//! `#[mtproto(other)]` variants are skipped. Names, functions and conditional fields are set up
+//! with `#[tl(...)]` attributes, see the `serde_mtproto::schema` module for details.
+//!
+//! ```
+//! # extern crate serde_mtproto;
+//! # #[macro_use] extern crate serde_mtproto_derive;
+//! #[derive(TlDescribe)]
+//! #[id = 0x0d91a548]
+//! #[tl(name = "users.getUsers", function, result = "Vec<i64>")]
+//! struct GetUsers {
+//!     id: Vec<i64>,
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! # Generators for property-based testing
+//!
+//! `#[mtproto_identifiable(arbitrary)]` and `#[mtproto_identifiable(proptest)]` make
+//! `#[derive(MtProtoIdentifiable)]` also implement `arbitrary::Arbitrary` and
+//! `proptest::arbitrary::Arbitrary` respectively. Generated values are always built from one of
+//! the variants that have an id, so their type ids are always valid. All fields must implement
+//! the corresponding trait and the crate which derives must depend on `arbitrary` and/or
+//! `proptest` directly.
+//!
+//! ```ignore
+//! #[derive(Debug, MtProtoIdentifiable)]
+//! #[mtproto_identifiable(arbitrary, proptest)]
+//! enum Attachment {
+//!     #[id = "0xdef19e00"]
+//!     Nothing,
+//!     #[id = "0xbadf00d0"]
+//!     Link {
+//!         url: String,
+//!     },
+//! }
+//! ```
 
 extern crate proc_macro;
+extern crate proc_macro2;
 #[macro_use]
 extern crate quote;
+#[macro_use]
 extern crate syn;
 
 
+mod mt_proto_deserialize;
 mod mt_proto_identifiable;
+mod mt_proto_serialize;
 mod mt_proto_sized;
+mod tl_describe;
+
 
+use std::collections::HashMap;
 
 use proc_macro::TokenStream;
+use proc_macro2::Span;
 
+use mt_proto_deserialize::impl_mt_proto_deserialize;
 use mt_proto_identifiable::impl_mt_proto_identifiable;
+use mt_proto_serialize::impl_mt_proto_serialize;
 use mt_proto_sized::impl_mt_proto_sized;
+use tl_describe::impl_tl_describe;
 
 
-#[proc_macro_derive(MtProtoIdentifiable, attributes(id))]
+#[proc_macro_derive(MtProtoIdentifiable, attributes(id, mtproto, mtproto_identifiable))]
 pub fn mt_proto_identifiable(input: TokenStream) -> TokenStream {
-    // Construct a string representation of the type definition
-    let s = input.to_string();
+    let ast = parse_macro_input!(input as syn::DeriveInput);
 
-    // Parse the string representation
-    let ast = syn::parse_derive_input(&s).unwrap();
+    impl_mt_proto_identifiable(&ast)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
 
-    // Build the impl
-    let gen = impl_mt_proto_identifiable(&ast);
+#[proc_macro_derive(MtProtoSized, attributes(mtproto, mtproto_sized, serde))]
+pub fn mt_proto_sized(input: TokenStream) -> TokenStream {
+    let mut ast = parse_macro_input!(input as syn::DeriveInput);
 
-    // Return the generated impl
-    gen.parse().unwrap()
+    impl_mt_proto_sized(&mut ast)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
 }
 
-#[proc_macro_derive(MtProtoSized, attributes(mtproto_sized))]
-pub fn mt_proto_sized(input: TokenStream) -> TokenStream {
-    // Construct a string representation of the type definition
-    let s = input.to_string();
+#[proc_macro_derive(MtProtoSerialize, attributes(id, mtproto))]
+pub fn mt_proto_serialize(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as syn::DeriveInput);
+
+    impl_mt_proto_serialize(&ast)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(MtProtoDeserialize, attributes(id, mtproto))]
+pub fn mt_proto_deserialize(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as syn::DeriveInput);
+
+    impl_mt_proto_deserialize(&ast)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(TlDescribe, attributes(id, mtproto, tl))]
+pub fn tl_describe(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as syn::DeriveInput);
+
+    impl_tl_describe(&ast)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+
+/// Variants of an enum which have ids, in declaration order, and the `#[mtproto(other)]`
+/// variant if there is one.
+#[derive(Default)]
+struct EnumVariants<'a> {
+    identified: Vec<(&'a syn::Variant, u32)>,
+    other: Option<&'a syn::Variant>,
+}
+
+/// Collect ids of enum variants, making sure that they are all distinct.
+fn enum_variants<'a>(data: &'a syn::DataEnum) -> syn::Result<EnumVariants<'a>> {
+    let mut variants = EnumVariants::default();
+    let mut variant_names_by_id = HashMap::new();
+
+    for variant in &data.variants {
+        if is_other_variant(&variant.attrs)? {
+            if variants.other.is_some() {
+                return Err(syn::Error::new_spanned(&variant.ident,
+                    "Only one enum variant can be marked with #[mtproto(other)]"));
+            }
+
+            is_named_other_variant(variant)?;
+            variants.other = Some(variant);
+
+            continue;
+        }
+
+        let (id, id_span) = get_id_from_attrs(&variant.attrs, &variant.ident)?;
+
+        if let Some(first_variant_name) = variant_names_by_id.insert(id, &variant.ident) {
+            return Err(syn::Error::new(id_span, format!(
+                "variant `{}` has the same id 0x{:08x} as variant `{}`",
+                variant.ident, id, first_variant_name)));
+        }
+
+        variants.identified.push((variant, id));
+    }
+
+    Ok(variants)
+}
+
+/// Get the id of a struct or an enum variant named `name` from its `#[id = ...]` attribute,
+/// along with the span of the id to point to in errors.
+fn get_id_from_attrs(attrs: &[syn::Attribute], name: &syn::Ident) -> syn::Result<(u32, Span)> {
+    let mut found_id = None;
+
+    for attr in attrs {
+        if !attr.path.is_ident("id") {
+            continue;
+        }
+
+        let lit = match attr.parse_meta()? {
+            syn::Meta::NameValue(name_value) => name_value.lit,
+            meta_item => {
+                return Err(syn::Error::new_spanned(meta_item,
+                    "expected an id like #[id = 0xdeadbeef] or #[id = \"0xdeadbeef\"]"));
+            },
+        };
+
+        if found_id.is_some() {
+            return Err(syn::Error::new_spanned(attr, format!("duplicate #[id] attribute on `{}`", name)));
+        }
+
+        let id = match lit {
+            syn::Lit::Int(ref value) => value.base10_parse::<u32>()?,
+            syn::Lit::Str(ref value) => {
+                let value = value.value();
+                let parsed = if value.starts_with("0x") {
+                    u32::from_str_radix(&value[2..], 16).ok()
+                } else {
+                    None
+                };
+
+                match parsed {
+                    Some(id) => id,
+                    None => {
+                        return Err(syn::Error::new_spanned(lit,
+                            "expected a 32-bit hexadecimal id like \"0xdeadbeef\""));
+                    },
+                }
+            },
+            _ => {
+                return Err(syn::Error::new_spanned(lit,
+                    "expected an integer literal or a hexadecimal string as an id"));
+            },
+        };
+
+        found_id = Some((id, lit.span()));
+    }
+
+    found_id.ok_or_else(|| syn::Error::new_spanned(name, format!(
+        "missing #[id = 0x...] attribute on `{}`, it is required on top of structs and each \
+         enum variant",
+        name)))
+}
+
+/// Check whether an enum variant is marked with `#[mtproto(other)]`.
+fn is_other_variant(attrs: &[syn::Attribute]) -> syn::Result<bool> {
+    let mut other = false;
+
+    for meta_item in attr_meta_items(attrs, "mtproto")? {
+        match meta_item {
+            syn::Meta::Path(ref path) if path.is_ident("other") => other = true,
+            _ => return Err(syn::Error::new_spanned(meta_item, "#[mtproto(...)] only supports `other` option")),
+        }
+    }
+
+    Ok(other)
+}
+
+/// Pattern which matches the `#[mtproto(other)]` variant and binds its id and raw bytes fields
+/// using the given bindings (e.g. `ref id` or `_`).
+fn other_variant_pattern(item_name: &syn::Ident,
+                         variant: &syn::Variant,
+                         id_binding: proc_macro2::TokenStream,
+                         raw_binding: proc_macro2::TokenStream)
+                        -> syn::Result<proc_macro2::TokenStream> {
+    let variant_name = &variant.ident;
+
+    if is_named_other_variant(variant)? {
+        Ok(quote! { #item_name::#variant_name { id: #id_binding, raw: #raw_binding } })
+    } else {
+        Ok(quote! { #item_name::#variant_name(#id_binding, #raw_binding) })
+    }
+}
+
+/// Expression which constructs the `#[mtproto(other)]` variant from `id` and `raw` variables.
+fn other_variant_constructor(item_name: &syn::Ident,
+                             variant: &syn::Variant)
+                            -> syn::Result<proc_macro2::TokenStream> {
+    let variant_name = &variant.ident;
+
+    if is_named_other_variant(variant)? {
+        Ok(quote! { #item_name::#variant_name { id: id, raw: ::std::convert::From::from(raw) } })
+    } else {
+        Ok(quote! { #item_name::#variant_name(id, ::std::convert::From::from(raw)) })
+    }
+}
+
+fn is_named_other_variant(variant: &syn::Variant) -> syn::Result<bool> {
+    match variant.fields {
+        syn::Fields::Named(ref fields) if fields.named.len() == 2 => {
+            let is_id_and_raw = fields.named.iter()
+                .map(|f| f.ident.as_ref().unwrap())
+                .zip(&["id", "raw"])
+                .all(|(ident, &name)| ident == name);
+
+            if !is_id_and_raw {
+                return Err(syn::Error::new_spanned(fields,
+                    "#[mtproto(other)] variant must have `id` and `raw` fields in this order"));
+            }
+
+            Ok(true)
+        },
+
+        syn::Fields::Unnamed(ref fields) if fields.unnamed.len() == 2 => Ok(false),
+
+        _ => Err(syn::Error::new_spanned(variant,
+            "#[mtproto(other)] variant must have exactly two fields: type id and raw bytes")),
+    }
+}
+
+/// How a field is encoded by `#[derive(MtProtoSerialize, MtProtoDeserialize)]`.
+#[derive(Clone, Copy)]
+enum FieldEncoding {
+    /// With the type id, if the field type has one.
+    Boxed,
+    /// Without the type id, requested by `#[mtproto(bare)]`.
+    Bare,
+    /// Through Serde traits, requested by `#[mtproto(serde)]`.
+    Serde,
+}
+
+fn field_encoding(field: &syn::Field) -> syn::Result<FieldEncoding> {
+    let mut encoding = None;
+
+    for meta_item in attr_meta_items(&field.attrs, "mtproto")? {
+        let requested = match meta_item {
+            syn::Meta::Path(ref path) if path.is_ident("bare") => FieldEncoding::Bare,
+            syn::Meta::Path(ref path) if path.is_ident("serde") => FieldEncoding::Serde,
+            _ => {
+                return Err(syn::Error::new_spanned(meta_item,
+                    "#[mtproto(...)] only supports `bare` and `serde` options on fields"));
+            },
+        };
+
+        if encoding.is_some() {
+            return Err(syn::Error::new_spanned(meta_item,
+                "only one of #[mtproto(bare)] and #[mtproto(serde)] can be used on a field"));
+        }
+
+        encoding = Some(requested);
+    }
+
+    Ok(encoding.unwrap_or(FieldEncoding::Boxed))
+}
+
+/// Bindings for fields of a struct or an enum variant in patterns, named so that they can't
+/// collide with variables of generated code.
+fn field_bindings(fields: &syn::Fields) -> Vec<syn::Ident> {
+    (0..fields.iter().count())
+        .map(|i| syn::Ident::new(&format!("__field_{}", i), Span::call_site()))
+        .collect()
+}
+
+/// Pattern which binds all fields of a struct or an enum variant at `path` by reference to
+/// `bindings`.
+fn fields_pattern(path: &proc_macro2::TokenStream,
+                  fields: &syn::Fields,
+                  bindings: &[syn::Ident])
+                 -> proc_macro2::TokenStream {
+    match *fields {
+        syn::Fields::Named(ref fields) => {
+            let names = fields.named.iter().map(|f| &f.ident);
+            quote! { #path { #(#names: ref #bindings),* } }
+        },
+        syn::Fields::Unnamed(_) => quote! { #path(#(ref #bindings),*) },
+        syn::Fields::Unit => quote! { #path },
+    }
+}
+
+/// Add `bound` to every type parameter.
+fn with_bound(generics: &syn::Generics, bound: syn::TypeParamBound) -> syn::Generics {
+    let mut generics = generics.clone();
+
+    for ty_param in generics.type_params_mut() {
+        ty_param.bounds.push(bound.clone());
+    }
+
+    generics
+}
+
+/// Options inside all `#[namespace(...)]` attributes.
+fn attr_meta_items(attrs: &[syn::Attribute], namespace: &str) -> syn::Result<Vec<syn::Meta>> {
+    let mut meta_items = Vec::new();
 
-    // Parse the string representation
-    let mut ast = syn::parse_derive_input(&s).unwrap();
+    for attr in attrs {
+        if !attr.path.is_ident(namespace) {
+            continue;
+        }
 
-    // Build the impl
-    let gen = impl_mt_proto_sized(&mut ast);
+        match attr.parse_meta()? {
+            syn::Meta::List(list) => {
+                for nested_meta_item in list.nested {
+                    match nested_meta_item {
+                        syn::NestedMeta::Meta(meta_item) => meta_items.push(meta_item),
+                        syn::NestedMeta::Lit(lit) => {
+                            return Err(syn::Error::new_spanned(lit,
+                                format!("unexpected literal in #[{}(...)]", namespace)));
+                        },
+                    }
+                }
+            },
+            meta_item => {
+                return Err(syn::Error::new_spanned(meta_item,
+                    format!("expected #[{}(...)]", namespace)));
+            },
+        }
+    }
 
-    // Return the generated impl
-    gen.parse().unwrap()
+    Ok(meta_items)
 }
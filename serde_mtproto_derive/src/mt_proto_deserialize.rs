@@ -0,0 +1,163 @@
+use proc_macro2::{Span, TokenStream};
+use syn;
+
+use {EnumVariants, FieldEncoding, enum_variants, field_encoding, get_id_from_attrs,
+     other_variant_constructor, with_bound};
+
+
+pub fn impl_mt_proto_deserialize(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
+    let generics = with_bound(&ast.generics, parse_quote!(_serde_mtproto::MtProtoDeserialize));
+    let (item_impl_generics, item_ty_generics, item_where_clause) = generics.split_for_impl();
+
+    let item_name = &ast.ident;
+    let dummy_const = syn::Ident::new(&format!("_IMPL_MT_PROTO_DESERIALIZE_FOR_{}", item_name), Span::call_site());
+
+    let (deserialize_boxed_body, deserialize_bare_body) = match ast.data {
+        syn::Data::Struct(ref data) => {
+            let (id, _) = get_id_from_attrs(&ast.attrs, item_name)?;
+            let constructor = deserialize_fields(&quote! { #item_name }, &data.fields)?;
+
+            (
+                quote! {
+                    let __type_id = <u32 as _serde_mtproto::MtProtoDeserialize>::deserialize_bare(__de)?;
+
+                    if __type_id != #id {
+                        return Err(_serde_mtproto::ErrorKind::InvalidTypeId {
+                            found: __type_id,
+                            expected: &[#id],
+                        }.into());
+                    }
+
+                    <Self as _serde_mtproto::MtProtoDeserialize>::deserialize_bare(__de)
+                },
+                quote! {
+                    Ok(#constructor)
+                },
+            )
+        },
+
+        syn::Data::Enum(ref data) => {
+            let EnumVariants { identified, other } = enum_variants(data)?;
+            let mut arms = TokenStream::new();
+
+            for &(variant, id) in &identified {
+                let variant_name = &variant.ident;
+                let constructor = deserialize_fields(&quote! { #item_name::#variant_name }, &variant.fields)?;
+
+                arms.extend(quote! {
+                    #id => Ok(#constructor),
+                });
+            }
+
+            let ids = identified.iter().map(|&(_, id)| id);
+            let invalid_type_id = quote! {
+                Err(_serde_mtproto::ErrorKind::InvalidTypeId {
+                    found: __type_id,
+                    expected: &[#(#ids),*],
+                }.into())
+            };
+
+            arms.extend(match other {
+                Some(variant) => {
+                    let constructor = other_variant_constructor(item_name, variant)?;
+
+                    // Raw bytes of an unknown constructor only extend to the declared size
+                    quote! {
+                        __type_id => match __de.read_declared_remaining_bytes()? {
+                            Some(raw) => {
+                                let id = __type_id;
+                                Ok(#constructor)
+                            },
+                            None => #invalid_type_id,
+                        },
+                    }
+                },
+                None => quote! {
+                    __type_id => #invalid_type_id,
+                },
+            });
+
+            let deserialize_bare_body = match (identified.len(), other) {
+                (1, None) => {
+                    let (variant, _) = identified[0];
+                    let variant_name = &variant.ident;
+                    let constructor = deserialize_fields(&quote! { #item_name::#variant_name }, &variant.fields)?;
+
+                    quote! {
+                        Ok(#constructor)
+                    }
+                },
+                _ => {
+                    // The constructor of a bare value can't be known without its id
+                    let message = format!("`{}` has several constructors and can only be deserialized as boxed",
+                        item_name);
+
+                    quote! {
+                        Err(_serde_mtproto::ErrorKind::DeCustom(#message.to_owned()).into())
+                    }
+                },
+            };
+
+            (
+                quote! {
+                    match <u32 as _serde_mtproto::MtProtoDeserialize>::deserialize_bare(__de)? {
+                        #arms
+                    }
+                },
+                deserialize_bare_body,
+            )
+        },
+
+        syn::Data::Union(ref data) => {
+            return Err(syn::Error::new_spanned(data.union_token,
+                "#[derive(MtProtoDeserialize)] is not supported for unions"));
+        },
+    };
+
+    Ok(quote! {
+        #[allow(non_upper_case_globals)]
+        const #dummy_const: () = {
+            extern crate serde_mtproto as _serde_mtproto;
+
+            impl #item_impl_generics _serde_mtproto::MtProtoDeserialize for #item_name #item_ty_generics
+                #item_where_clause
+            {
+                fn deserialize_boxed<__R: ::std::io::Read>(__de: &mut _serde_mtproto::Deserializer<__R>)
+                    -> _serde_mtproto::Result<Self>
+                {
+                    #deserialize_boxed_body
+                }
+
+                #[allow(unused_variables)]
+                fn deserialize_bare<__R: ::std::io::Read>(__de: &mut _serde_mtproto::Deserializer<__R>)
+                    -> _serde_mtproto::Result<Self>
+                {
+                    #deserialize_bare_body
+                }
+            }
+        };
+    })
+}
+
+/// Expression which constructs a struct or an enum variant at `path`, deserializing its fields
+/// in declaration order.
+fn deserialize_fields(path: &TokenStream, fields: &syn::Fields) -> syn::Result<TokenStream> {
+    let mut values = Vec::new();
+
+    for field in fields.iter() {
+        values.push(match field_encoding(field)? {
+            FieldEncoding::Boxed => quote! { _serde_mtproto::MtProtoDeserialize::deserialize_boxed(__de)? },
+            FieldEncoding::Bare => quote! { _serde_mtproto::MtProtoDeserialize::deserialize_bare(__de)? },
+            FieldEncoding::Serde => quote! { _serde_mtproto::native::deserialize_with_serde(__de)? },
+        });
+    }
+
+    Ok(match *fields {
+        syn::Fields::Named(ref fields) => {
+            let names = fields.named.iter().map(|f| &f.ident);
+            quote! { #path { #(#names: #values),* } }
+        },
+        syn::Fields::Unnamed(_) => quote! { #path(#(#values),*) },
+        syn::Fields::Unit => quote! { #path },
+    })
+}
@@ -1,104 +1,154 @@
-use quote;
+use proc_macro2::{Span, TokenStream};
 use syn;
 
+use {EnumVariants, attr_meta_items, enum_variants, get_id_from_attrs, other_variant_constructor, other_variant_pattern};
 
-pub fn impl_mt_proto_identifiable(ast: &syn::DeriveInput) -> quote::Tokens {
+
+pub fn impl_mt_proto_identifiable(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
     let (item_impl_generics, item_ty_generics, item_where_clause) = ast.generics.split_for_impl();
 
     let item_name = &ast.ident;
-    let dummy_const = syn::Ident::new(format!("_IMPL_MT_PROTO_IDENTIFIABLE_FOR_{}", item_name));
-    let all_type_ids_const = syn::Ident::new(format!("_ALL_TYPE_IDS_OF_{}", item_name));
-
-    let all_type_ids_value = match ast.body {
-        syn::Body::Struct(_) => {
-            let id = get_id_from_attrs(&ast.attrs);
-
-            quote! {
-                &[#id]
-            }
-        }
-
-        syn::Body::Enum(ref variants) => {
-            let ids = variants.iter()
-                .map(|v| get_id_from_attrs(&v.attrs))
-                .collect::<Vec<_>>();
+    let dummy_const = syn::Ident::new(&format!("_IMPL_MT_PROTO_IDENTIFIABLE_FOR_{}", item_name), Span::call_site());
 
-            quote! {
-                &[#(#ids),*]
-            }
-        }
+    let EnumVariants { identified: identified_variants, other: other_variant } = match ast.data {
+        syn::Data::Struct(_) => EnumVariants::default(),
+        syn::Data::Enum(ref data) => enum_variants(data)?,
+        syn::Data::Union(ref data) => {
+            return Err(syn::Error::new_spanned(data.union_token,
+                "#[derive(MtProtoIdentifiable)] is not supported for unions"));
+        },
     };
 
-    let type_id_body = match ast.body {
-        syn::Body::Struct(_) => {
-            let id = get_id_from_attrs(&ast.attrs);
+    let (all_type_ids_value, variants_by_id_value, type_id_body, enum_variant_id_body) = match ast.data {
+        syn::Data::Enum(ref data) => {
+            let ids = identified_variants.iter().map(|&(_, id)| id);
 
-            quote! {
-                #id
-            }
-        }
-
-        syn::Body::Enum(ref variants) => {
-            let mut variants_quoted = quote::Tokens::new();
-
-            for variant in variants {
+            let mut type_id_arms = TokenStream::new();
+            for &(variant, id) in &identified_variants {
                 let variant_name = &variant.ident;
-                let id = get_id_from_attrs(&variant.attrs);
 
-                variants_quoted.append(quote! {
+                type_id_arms.extend(quote! {
                     #item_name::#variant_name { .. } => #id,
                 });
             }
 
-            quote! {
-                match *self {
-                    #variants_quoted
-                }
-            }
-        }
-    };
+            if let Some(variant) = other_variant {
+                let pattern = other_variant_pattern(item_name, variant, quote! { id }, quote! { _ })?;
 
-    let enum_variant_id_body = match ast.body {
-        syn::Body::Struct(_) => {
-            quote! {
-                None
+                type_id_arms.extend(quote! {
+                    #pattern => id,
+                });
             }
-        }
-
-        syn::Body::Enum(ref variants) => {
-            let mut variants_quoted = quote::Tokens::new();
 
-            for variant in variants {
+            let mut enum_variant_id_arms = TokenStream::new();
+            for variant in &data.variants {
                 let variant_name = &variant.ident;
 
-                variants_quoted.append(quote! {
+                enum_variant_id_arms.extend(quote! {
                     #item_name::#variant_name { .. } => stringify!(#variant_name),
                 });
             }
 
+            // Sorted by id for binary search in `variant_for_id()`
+            let mut variants_by_id: Vec<_> = identified_variants.iter()
+                .enumerate()
+                .map(|(index, &(variant, id))| (id, index, &variant.ident))
+                .collect();
+            variants_by_id.sort_by_key(|&(id, _, _)| id);
+
+            let variants_by_id = variants_by_id.into_iter().map(|(id, index, variant_name)| quote! {
+                (#id, _serde_mtproto::TypeIdVariant { index: #index, name: Some(stringify!(#variant_name)) })
+            });
+
+            (
+                quote! {
+                    &[#(#ids),*]
+                },
+                quote! {
+                    &[#(#variants_by_id),*]
+                },
+                quote! {
+                    match *self {
+                        #type_id_arms
+                    }
+                },
+                quote! {
+                    let variant_id = match *self {
+                        #enum_variant_id_arms
+                    };
+
+                    Some(variant_id)
+                },
+            )
+        },
+
+        _ => {
+            let (id, _) = get_id_from_attrs(&ast.attrs, item_name)?;
+
+            (
+                quote! {
+                    &[#id]
+                },
+                quote! {
+                    &[(#id, _serde_mtproto::TypeIdVariant { index: 0, name: None })]
+                },
+                quote! {
+                    #id
+                },
+                quote! {
+                    None
+                },
+            )
+        },
+    };
+
+    let unknown_methods = match other_variant {
+        Some(variant) => {
+            let variant_name = &variant.ident;
+            let pattern = other_variant_pattern(item_name, variant, quote! { _ }, quote! { ref raw })?;
+            let constructor = other_variant_constructor(item_name, variant)?;
+
             quote! {
-                let variant_id = match *self {
-                    #variants_quoted
-                };
+                fn unknown_variant_id() -> Option<&'static str> {
+                    Some(stringify!(#variant_name))
+                }
+
+                fn from_unknown(id: u32, raw: Vec<u8>) -> Option<Self> {
+                    Some(#constructor)
+                }
 
-                Some(variant_id)
+                #[allow(unreachable_patterns)]
+                fn unknown_raw(&self) -> Option<&[u8]> {
+                    match *self {
+                        #pattern => Some(&raw[..]),
+                        _ => None,
+                    }
+                }
             }
-        }
+        },
+        None => TokenStream::new(),
     };
 
-    // Use rvalue static promotion syntax after bumping minimum supported Rust version to 1.21
-    quote! {
+    let generators = impl_generators(ast, &identified_variants)?;
+
+    Ok(quote! {
         #[allow(non_upper_case_globals)]
         const #dummy_const: () = {
             extern crate serde_mtproto as _serde_mtproto;
 
-            const #all_type_ids_const: &'static [u32] = #all_type_ids_value;
-
             impl #item_impl_generics _serde_mtproto::Identifiable for #item_name #item_ty_generics
                 #item_where_clause
             {
                 fn all_type_ids() -> &'static [u32] {
-                    #all_type_ids_const
+                    #all_type_ids_value
+                }
+
+                fn variant_for_id(type_id: u32) -> Option<_serde_mtproto::TypeIdVariant> {
+                    let variants_by_id: &'static [(u32, _serde_mtproto::TypeIdVariant)] = #variants_by_id_value;
+
+                    variants_by_id.binary_search_by_key(&type_id, |&(id, _)| id)
+                        .ok()
+                        .map(|i| variants_by_id[i].1)
                 }
 
                 fn type_id(&self) -> u32 {
@@ -108,32 +158,192 @@ pub fn impl_mt_proto_identifiable(ast: &syn::DeriveInput) -> quote::Tokens {
                 fn enum_variant_id(&self) -> Option<&'static str> {
                     #enum_variant_id_body
                 }
+
+                #unknown_methods
             }
+
+            #generators
         };
-    }
+    })
 }
 
-fn get_id_from_attrs(attrs: &[syn::Attribute]) -> u32 {
-    for attr in attrs {
-        match attr.value {
-            syn::MetaItem::NameValue(ref name, ref value) => {
-                if name.as_ref() == "id" {
-                    if let syn::Lit::Str(ref value, syn::StrStyle::Cooked) = *value {
-                        // Found an identifier
-                        let value = u32::from_str_radix(&value[2..], 16).unwrap();
+/// Generate `arbitrary` and/or `proptest` generators requested by
+/// `#[mtproto_identifiable(arbitrary, proptest)]`.
+///
+/// Every generated value is built from one of the variants (or the struct itself), so its type id
+/// is always one of the valid ids. The `#[mtproto(other)]` variant is never generated.
+fn impl_generators(ast: &syn::DeriveInput,
+                   identified_variants: &[(&syn::Variant, u32)])
+                  -> syn::Result<TokenStream> {
+    let mut generators = TokenStream::new();
+    let (arbitrary, proptest) = get_generators_from_attrs(&ast.attrs)?;
 
-                        return value;
-                    }
+    if !arbitrary && !proptest {
+        return Ok(generators);
+    }
+
+    if !ast.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&ast.generics,
+            "#[mtproto_identifiable(arbitrary)] and #[mtproto_identifiable(proptest)] \
+             are not supported for generic types"));
+    }
+
+    let item_name = &ast.ident;
+
+    let constructors = match ast.data {
+        syn::Data::Struct(ref data) => vec![(quote! { #item_name }, &data.fields)],
+        _ => {
+            identified_variants.iter()
+                .map(|&(v, _)| {
+                    let variant_name = &v.ident;
+                    (quote! { #item_name::#variant_name }, &v.fields)
+                })
+                .collect()
+        },
+    };
+
+    if constructors.is_empty() {
+        return Err(syn::Error::new_spanned(item_name,
+            "#[mtproto_identifiable(arbitrary)] and #[mtproto_identifiable(proptest)] \
+             need at least one enum variant with an id"));
+    }
+
+    if arbitrary {
+        let max_index = constructors.len() as u32 - 1;
+        let mut arms = TokenStream::new();
+
+        for (i, &(ref path, fields)) in constructors.iter().enumerate() {
+            let index = i as u32;
+            let value = construct(path, fields, |_| quote! {
+                _arbitrary::Arbitrary::arbitrary(u)?
+            });
+
+            arms.extend(quote! {
+                #index => #value,
+            });
+        }
+
+        generators.extend(quote! {
+            extern crate arbitrary as _arbitrary;
+
+            impl<'arbitrary> _arbitrary::Arbitrary<'arbitrary> for #item_name {
+                fn arbitrary(u: &mut _arbitrary::Unstructured<'arbitrary>)
+                    -> _arbitrary::Result<Self>
+                {
+                    let index: u32 = u.int_in_range(0..=#max_index)?;
+                    let value = match index {
+                        #arms
+                        _ => unreachable!(),
+                    };
+
+                    Ok(value)
                 }
             }
+        });
+    }
 
-            _ => {
-                // Do nothing
+    if proptest {
+        let mut strategies = TokenStream::new();
+
+        for &(ref path, fields) in &constructors {
+            // Nest fields in pairs like `(x0, (x1, ()))` to not depend on
+            // the maximum arity of tuples supported by `proptest`.
+            let mut types = quote! { () };
+            let mut pattern = quote! { () };
+            for (i, field) in fields.iter().enumerate().rev() {
+                let ty = &field.ty;
+                let binding = syn::Ident::new(&format!("x{}", i), Span::call_site());
+                types = quote! { (#ty, #types) };
+                pattern = quote! { (#binding, #pattern) };
             }
+
+            let mut index = 0;
+            let value = construct(path, fields, |_| {
+                let binding = syn::Ident::new(&format!("x{}", index), Span::call_site());
+                index += 1;
+                quote! { #binding }
+            });
+
+            strategies.extend(quote! {
+                _proptest::strategy::Strategy::boxed(_proptest::strategy::Strategy::prop_map(
+                    _proptest::arbitrary::any::<#types>(),
+                    |#pattern| #value,
+                )),
+            });
+        }
+
+        generators.extend(quote! {
+            extern crate proptest as _proptest;
+
+            impl _proptest::arbitrary::Arbitrary for #item_name {
+                type Parameters = ();
+                type Strategy = _proptest::strategy::BoxedStrategy<Self>;
+
+                fn arbitrary_with(_args: ()) -> Self::Strategy {
+                    let strategies: Vec<_proptest::strategy::BoxedStrategy<Self>> = vec![
+                        #strategies
+                    ];
+
+                    _proptest::strategy::Strategy::boxed(_proptest::strategy::Union::new(strategies))
+                }
+            }
+        });
+    }
+
+    Ok(generators)
+}
+
+fn construct<F>(path: &TokenStream, fields: &syn::Fields, mut field_value: F) -> TokenStream
+    where F: FnMut(&syn::Field) -> TokenStream
+{
+    match *fields {
+        syn::Fields::Named(ref fields) => {
+            let mut fields_quoted = TokenStream::new();
+
+            for field in &fields.named {
+                let field_name = &field.ident;
+                let value = field_value(field);
+
+                fields_quoted.extend(quote! {
+                    #field_name: #value,
+                });
+            }
+
+            quote! {
+                #path { #fields_quoted }
+            }
+        },
+
+        syn::Fields::Unnamed(ref fields) => {
+            let values = fields.unnamed.iter().map(field_value).collect::<Vec<_>>();
+
+            quote! {
+                #path(#(#values),*)
+            }
+        },
+
+        syn::Fields::Unit => {
+            quote! {
+                #path
+            }
+        },
+    }
+}
+
+fn get_generators_from_attrs(attrs: &[syn::Attribute]) -> syn::Result<(bool, bool)> {
+    let mut arbitrary = false;
+    let mut proptest = false;
+
+    for meta_item in attr_meta_items(attrs, "mtproto_identifiable")? {
+        match meta_item {
+            syn::Meta::Path(ref path) if path.is_ident("arbitrary") => arbitrary = true,
+            syn::Meta::Path(ref path) if path.is_ident("proptest") => proptest = true,
+            _ => {
+                return Err(syn::Error::new_spanned(meta_item,
+                    "#[mtproto_identifiable(...)] only supports `arbitrary` and `proptest` options"));
+            },
         }
     }
 
-    panic!("#[derive(MtProtoIdentifiable)] requires an #[id = \"0x...\"] attribute:\n    \
-            - on top of struct for structs;\n    \
-            - or on top of each enum variant for enums");
+    Ok((arbitrary, proptest))
 }
@@ -0,0 +1,142 @@
+use proc_macro2::{Span, TokenStream};
+use syn;
+
+use {EnumVariants, FieldEncoding, enum_variants, field_bindings, field_encoding, fields_pattern,
+     get_id_from_attrs, other_variant_pattern, with_bound};
+
+
+pub fn impl_mt_proto_serialize(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
+    let generics = with_bound(&ast.generics, parse_quote!(_serde_mtproto::MtProtoSerialize));
+    let (item_impl_generics, item_ty_generics, item_where_clause) = generics.split_for_impl();
+
+    let item_name = &ast.ident;
+    let dummy_const = syn::Ident::new(&format!("_IMPL_MT_PROTO_SERIALIZE_FOR_{}", item_name), Span::call_site());
+
+    let (serialize_boxed_body, serialize_bare_body) = match ast.data {
+        syn::Data::Struct(ref data) => {
+            let (id, _) = get_id_from_attrs(&ast.attrs, item_name)?;
+            let bindings = field_bindings(&data.fields);
+            let pattern = fields_pattern(&quote! { #item_name }, &data.fields, &bindings);
+            let fields_quoted = serialize_fields(&data.fields, &bindings)?;
+
+            (
+                quote! {
+                    _serde_mtproto::MtProtoSerialize::serialize_bare(&#id, __ser)?;
+                    _serde_mtproto::MtProtoSerialize::serialize_bare(self, __ser)
+                },
+                quote! {
+                    let #pattern = *self;
+                    #fields_quoted
+                    Ok(())
+                },
+            )
+        },
+
+        syn::Data::Enum(ref data) => {
+            let EnumVariants { identified, other } = enum_variants(data)?;
+            let mut boxed_arms = TokenStream::new();
+            let mut bare_arms = TokenStream::new();
+
+            for &(variant, id) in &identified {
+                let variant_name = &variant.ident;
+                let bindings = field_bindings(&variant.fields);
+                let pattern = fields_pattern(&quote! { #item_name::#variant_name }, &variant.fields, &bindings);
+                let fields_quoted = serialize_fields(&variant.fields, &bindings)?;
+
+                boxed_arms.extend(quote! {
+                    #pattern => {
+                        _serde_mtproto::MtProtoSerialize::serialize_bare(&#id, __ser)?;
+                        #fields_quoted
+                        Ok(())
+                    },
+                });
+
+                bare_arms.extend(quote! {
+                    #pattern => {
+                        #fields_quoted
+                        Ok(())
+                    },
+                });
+            }
+
+            if let Some(variant) = other {
+                // Raw bytes are serialized as is, without length
+                let pattern = other_variant_pattern(item_name, variant, quote! { ref __id }, quote! { ref __raw })?;
+
+                boxed_arms.extend(quote! {
+                    #pattern => {
+                        _serde_mtproto::MtProtoSerialize::serialize_bare(__id, __ser)?;
+                        __ser.write_raw_bytes(&__raw[..])
+                    },
+                });
+
+                bare_arms.extend(quote! {
+                    #pattern => __ser.write_raw_bytes(&__raw[..]),
+                });
+            }
+
+            (
+                quote! {
+                    match *self {
+                        #boxed_arms
+                    }
+                },
+                quote! {
+                    match *self {
+                        #bare_arms
+                    }
+                },
+            )
+        },
+
+        syn::Data::Union(ref data) => {
+            return Err(syn::Error::new_spanned(data.union_token,
+                "#[derive(MtProtoSerialize)] is not supported for unions"));
+        },
+    };
+
+    Ok(quote! {
+        #[allow(non_upper_case_globals)]
+        const #dummy_const: () = {
+            extern crate serde_mtproto as _serde_mtproto;
+
+            impl #item_impl_generics _serde_mtproto::MtProtoSerialize for #item_name #item_ty_generics
+                #item_where_clause
+            {
+                fn serialize_boxed<__W: ::std::io::Write>(&self, __ser: &mut _serde_mtproto::Serializer<__W>)
+                    -> _serde_mtproto::Result<()>
+                {
+                    #serialize_boxed_body
+                }
+
+                #[allow(unused_variables)]
+                fn serialize_bare<__W: ::std::io::Write>(&self, __ser: &mut _serde_mtproto::Serializer<__W>)
+                    -> _serde_mtproto::Result<()>
+                {
+                    #serialize_bare_body
+                }
+            }
+        };
+    })
+}
+
+/// Statements which serialize fields bound to `bindings` one after another.
+fn serialize_fields(fields: &syn::Fields, bindings: &[syn::Ident]) -> syn::Result<TokenStream> {
+    let mut fields_quoted = TokenStream::new();
+
+    for (field, binding) in fields.iter().zip(bindings) {
+        fields_quoted.extend(match field_encoding(field)? {
+            FieldEncoding::Boxed => quote! {
+                _serde_mtproto::MtProtoSerialize::serialize_boxed(#binding, __ser)?;
+            },
+            FieldEncoding::Bare => quote! {
+                _serde_mtproto::MtProtoSerialize::serialize_bare(#binding, __ser)?;
+            },
+            FieldEncoding::Serde => quote! {
+                _serde_mtproto::native::serialize_with_serde(#binding, __ser)?;
+            },
+        });
+    }
+
+    Ok(fields_quoted)
+}
@@ -1,49 +1,42 @@
-use quote;
+use proc_macro2::{Span, TokenStream};
 use syn;
 
+use {attr_meta_items, is_other_variant, other_variant_pattern};
 
-pub fn impl_mt_proto_sized(ast: &mut syn::DeriveInput) -> quote::Tokens {
+
+pub fn impl_mt_proto_sized(ast: &mut syn::DeriveInput) -> syn::Result<TokenStream> {
     add_mt_proto_sized_trait_bound_if_missing(ast);
     let (item_impl_generics, item_ty_generics, item_where_clause) = ast.generics.split_for_impl();
 
     let item_name = &ast.ident;
-    let dummy_const = syn::Ident::new(format!("_IMPL_MT_PROTO_SIZED_FOR_{}", item_name));
+    let dummy_const = syn::Ident::new(&format!("_IMPL_MT_PROTO_SIZED_FOR_{}", item_name), Span::call_site());
 
-    let size_hint_body = match ast.body {
-        syn::Body::Struct(ref data) => {
+    let size_hint_body = match ast.data {
+        syn::Data::Struct(ref data) => {
             let mut fields_quoted = quote! { 0 };
 
-            match *data {
-                syn::VariantData::Struct(ref fields) => {
-                    for field in fields {
-                        if is_skippable_field(field) {
-                            continue;
-                        }
-
+            match data.fields {
+                syn::Fields::Named(ref fields) => {
+                    for field in &fields.named {
                         let field_name = &field.ident;
 
-                        fields_quoted.append(quote! {
-                            + _serde_mtproto::MtProtoSized::size_hint(&self.#field_name)?
-                        });
+                        if let Some(size) = field_size(field)?.size_expr(quote! { &self.#field_name }) {
+                            fields_quoted.extend(quote! { + #size });
+                        }
                     }
                 }
 
-                syn::VariantData::Tuple(ref fields) => {
-                    for (i, field) in fields.iter().enumerate() {
-                        if is_skippable_field(field) {
-                            continue;
-                        }
-
-                        // Integers are rendered with type suffixes. We don't want this.
-                        let i = quote::Ident::new(i.to_string());
+                syn::Fields::Unnamed(ref fields) => {
+                    for (i, field) in fields.unnamed.iter().enumerate() {
+                        let i = syn::Index::from(i);
 
-                        fields_quoted.append(quote! {
-                            + _serde_mtproto::MtProtoSized::size_hint(&self.#i)?
-                        });
+                        if let Some(size) = field_size(field)?.size_expr(quote! { &self.#i }) {
+                            fields_quoted.extend(quote! { + #size });
+                        }
                     }
                 }
 
-                syn::VariantData::Unit => {}
+                syn::Fields::Unit => {}
             }
 
             quote! {
@@ -51,53 +44,57 @@ pub fn impl_mt_proto_sized(ast: &mut syn::DeriveInput) -> quote::Tokens {
             }
         }
 
-        syn::Body::Enum(ref variants) => {
-            let mut variants_quoted = quote::Tokens::new();
+        syn::Data::Enum(ref data) => {
+            let mut variants_quoted = TokenStream::new();
+
+            for variant in &data.variants {
+                if is_other_variant(&variant.attrs)? {
+                    // Raw bytes are serialized as is, without length
+                    let pattern = other_variant_pattern(item_name, variant, quote! { _ }, quote! { ref raw })?;
+
+                    variants_quoted.extend(quote! {
+                        #pattern => Ok(raw.len()),
+                    });
+
+                    continue;
+                }
 
-            for variant in variants {
                 let variant_name = &variant.ident;
+                check_variant_attrs(variant)?;
 
                 let pattern_match_quoted;
                 let mut fields_quoted = quote! { 0 };
 
-                match variant.data {
-                    syn::VariantData::Struct(ref fields) => {
+                match variant.fields {
+                    syn::Fields::Named(ref fields) => {
                         let mut pattern_matches = Vec::new();
 
-                        for field in fields {
-                            if is_skippable_field(field) {
-                                continue;
-                            }
-
+                        for field in &fields.named {
                             let field_name = &field.ident;
 
-                            pattern_matches.push(quote! { ref #field_name });
-
-                            fields_quoted.append(quote! {
-                                + _serde_mtproto::MtProtoSized::size_hint(#field_name)?
-                            });
+                            if let Some(size) = field_size(field)?.size_expr(quote! { #field_name }) {
+                                pattern_matches.push(quote! { ref #field_name });
+                                fields_quoted.extend(quote! { + #size });
+                            }
                         }
 
                         pattern_match_quoted = quote! {
-                            { #(#pattern_matches),* }
+                            { #(#pattern_matches,)* .. }
                         }
                     }
 
-                    syn::VariantData::Tuple(ref fields) => {
+                    syn::Fields::Unnamed(ref fields) => {
                         let mut pattern_matches = Vec::new();
 
-                        for (i, field) in fields.iter().enumerate() {
-                            if is_skippable_field(field) {
-                                continue;
-                            }
-
-                            let field_name = syn::Ident::new(format!("__field_{}", i));
+                        for (i, field) in fields.unnamed.iter().enumerate() {
+                            let field_name = syn::Ident::new(&format!("__field_{}", i), Span::call_site());
 
-                            pattern_matches.push(quote! { ref #field_name });
-
-                            fields_quoted.append(quote! {
-                                + _serde_mtproto::MtProtoSized::size_hint(#field_name)?
-                            });
+                            if let Some(size) = field_size(field)?.size_expr(quote! { #field_name }) {
+                                pattern_matches.push(quote! { ref #field_name });
+                                fields_quoted.extend(quote! { + #size });
+                            } else {
+                                pattern_matches.push(quote! { _ });
+                            }
                         }
 
                         pattern_match_quoted = quote! {
@@ -105,12 +102,12 @@ pub fn impl_mt_proto_sized(ast: &mut syn::DeriveInput) -> quote::Tokens {
                         }
                     }
 
-                    syn::VariantData::Unit => {
+                    syn::Fields::Unit => {
                         pattern_match_quoted = quote! {};
                     }
                 }
 
-                variants_quoted.append(quote! {
+                variants_quoted.extend(quote! {
                     #item_name::#variant_name #pattern_match_quoted => {
                         Ok(#fields_quoted)
                     },
@@ -123,9 +120,14 @@ pub fn impl_mt_proto_sized(ast: &mut syn::DeriveInput) -> quote::Tokens {
                 }
             }
         }
+
+        syn::Data::Union(ref data) => {
+            return Err(syn::Error::new_spanned(data.union_token,
+                "#[derive(MtProtoSized)] is not supported for unions"));
+        }
     };
 
-    quote! {
+    Ok(quote! {
         #[allow(non_upper_case_globals)]
         const #dummy_const: () = {
             extern crate serde_mtproto as _serde_mtproto;
@@ -138,22 +140,25 @@ pub fn impl_mt_proto_sized(ast: &mut syn::DeriveInput) -> quote::Tokens {
                 }
             }
         };
-    }
+    })
 }
 
 fn add_mt_proto_sized_trait_bound_if_missing(ast: &mut syn::DeriveInput) {
-    'ty_param: for ty_param in &mut ast.generics.ty_params {
+    'ty_param: for ty_param in ast.generics.type_params_mut() {
         for bound in &ty_param.bounds {
             match *bound {
-                syn::TyParamBound::Trait(ref poly_trait_ref, syn::TraitBoundModifier::None) => {
-                    let path = &poly_trait_ref.trait_ref;
-                    if path.global {
+                syn::TypeParamBound::Trait(syn::TraitBound {
+                    modifier: syn::TraitBoundModifier::None,
+                    ref path,
+                    ..
+                }) => {
+                    if path.leading_colon.is_some() {
                         continue;
                     }
 
                     let trait_ref_segments = path.segments
                         .iter()
-                        .map(|s| s.ident.as_ref());
+                        .map(|s| s.ident.to_string());
                     let mt_proto_sized_segments = vec!["_serde_mtproto", "MtProtoSized"].into_iter();
 
                     if trait_ref_segments.eq(mt_proto_sized_segments) {
@@ -164,39 +169,152 @@ fn add_mt_proto_sized_trait_bound_if_missing(ast: &mut syn::DeriveInput) {
             }
         }
 
-        ty_param.bounds.push(syn::TyParamBound::Trait(
-            syn::PolyTraitRef {
-                bound_lifetimes: vec![],
-                trait_ref: syn::Path {
-                    global: false,
-                    segments: vec!["_serde_mtproto".into(), "MtProtoSized".into()],
-                }
+        ty_param.bounds.push(parse_quote!(_serde_mtproto::MtProtoSized));
+    }
+}
+
+/// How a field contributes to the size of the value it belongs to.
+struct FieldSize {
+    kind: FieldSizeKind,
+    /// `#[serde(skip_serializing_if = "...")]` predicate.
+    skip_if: Option<syn::Path>,
+}
+
+enum FieldSizeKind {
+    /// The field is never serialized.
+    Skip,
+    /// `MtProtoSized` implementation of the field type.
+    Default,
+    /// `size_hint` function of a module which also provides `serialize`.
+    With(syn::Path),
+    /// A function which computes the size.
+    SizeWith(syn::Path),
+}
+
+impl FieldSize {
+    /// Expression which computes the size of the field referenced by `field_ref`, if it is
+    /// serialized at all.
+    fn size_expr(&self, field_ref: TokenStream) -> Option<TokenStream> {
+        let size = match self.kind {
+            FieldSizeKind::Skip => return None,
+            FieldSizeKind::Default => quote! { _serde_mtproto::MtProtoSized::size_hint(#field_ref)? },
+            FieldSizeKind::With(ref module) => quote! { #module::size_hint(#field_ref)? },
+            FieldSizeKind::SizeWith(ref function) => quote! { #function(#field_ref)? },
+        };
+
+        Some(match self.skip_if {
+            Some(ref predicate) => quote! { (if #predicate(#field_ref) { 0 } else { #size }) },
+            None => size,
+        })
+    }
+}
+
+/// Find out how to size a field judging by its `#[mtproto_sized(...)]` and `#[serde(...)]`
+/// attributes.
+///
+/// `#[mtproto_sized(...)]` options take precedence over the ones inferred from Serde attributes.
+fn field_size(field: &syn::Field) -> syn::Result<FieldSize> {
+    let mut skip = false;
+    let mut with = None;
+    let mut size_with = None;
+
+    let mut serde_skip = false;
+    let mut serde_with = None;
+    let mut skip_if = None;
+
+    for meta_item in attr_meta_items(&field.attrs, "mtproto_sized")? {
+        match meta_item {
+            syn::Meta::Path(ref path) if path.is_ident("skip") => skip = true,
+            syn::Meta::NameValue(ref name_value) if name_value.path.is_ident("with") => {
+                with = Some(parse_path_lit("mtproto_sized", "with", &name_value.lit)?);
+            },
+            syn::Meta::NameValue(ref name_value) if name_value.path.is_ident("size_with") => {
+                size_with = Some(parse_path_lit("mtproto_sized", "size_with", &name_value.lit)?);
+            },
+            _ => {
+                return Err(syn::Error::new_spanned(meta_item,
+                    "#[mtproto_sized(...)] only supports `skip`, `with` and `size_with` options"));
+            },
+        }
+    }
+
+    for meta_item in attr_meta_items(&field.attrs, "serde")? {
+        match meta_item {
+            syn::Meta::Path(ref path) if path.is_ident("skip") || path.is_ident("skip_serializing") => {
+                serde_skip = true;
+            },
+            syn::Meta::Path(ref path) if path.is_ident("flatten") => {
+                return Err(syn::Error::new_spanned(path, format!(
+                    "field {} with #[serde(flatten)] cannot be sized since it is serialized as a map",
+                    field_display_name(field))));
+            },
+            syn::Meta::NameValue(ref name_value)
+                if name_value.path.is_ident("with") || name_value.path.is_ident("serialize_with") =>
+            {
+                serde_with = Some(name_value.clone());
+            },
+            syn::Meta::NameValue(ref name_value) if name_value.path.is_ident("skip_serializing_if") => {
+                skip_if = Some(parse_path_lit("serde", "skip_serializing_if", &name_value.lit)?);
             },
-            syn::TraitBoundModifier::None,
-        ));
+            _ => (),
+        }
     }
+
+    let kind = if skip || serde_skip {
+        FieldSizeKind::Skip
+    } else if let Some(size_with) = size_with {
+        FieldSizeKind::SizeWith(size_with)
+    } else if let Some(with) = with {
+        FieldSizeKind::With(with)
+    } else if let Some(serde_with) = serde_with {
+        let name = serde_with.path.get_ident().map(ToString::to_string).unwrap_or_default();
+
+        return Err(syn::Error::new_spanned(serde_with, format!(
+            "field {} with #[serde({} = \"...\")] cannot be sized by its type, \
+             use #[mtproto_sized(size_with = \"...\")] or #[mtproto_sized(with = \"...\")] \
+             to compute its size",
+            field_display_name(field), name)));
+    } else {
+        FieldSizeKind::Default
+    };
+
+    Ok(FieldSize {
+        kind: kind,
+        skip_if: skip_if,
+    })
 }
 
-fn is_skippable_field(field: &syn::Field) -> bool {
-    for attr in &field.attrs {
-        if let syn::Attribute {
-            style: syn::AttrStyle::Outer,
-            value: syn::MetaItem::List(ref namespace_ident, ref nested_meta_items),
-            is_sugared_doc: false,
-        } = *attr {
-            if namespace_ident.as_ref() == "mtproto_sized" {
-                for nested_mi in nested_meta_items {
-                    if let syn::NestedMetaItem::MetaItem(ref meta_item) = *nested_mi {
-                        if let syn::MetaItem::Word(ref flag_ident) = *meta_item {
-                            if flag_ident == "skip" {
-                                return true;
-                            }
-                        }
-                    }
+/// Reject variant attributes which change serialization of the whole variant since the derive
+/// can't size it then.
+fn check_variant_attrs(variant: &syn::Variant) -> syn::Result<()> {
+    for meta_item in attr_meta_items(&variant.attrs, "serde")? {
+        if let syn::Meta::NameValue(ref name_value) = meta_item {
+            for &name in &["with", "serialize_with"] {
+                if name_value.path.is_ident(name) {
+                    return Err(syn::Error::new_spanned(name_value, format!(
+                        "variant `{}` with #[serde({} = \"...\")] cannot be sized",
+                        variant.ident, name)));
                 }
             }
         }
     }
 
-    false
+    Ok(())
+}
+
+fn parse_path_lit(namespace: &str, name: &str, value: &syn::Lit) -> syn::Result<syn::Path> {
+    if let syn::Lit::Str(ref value) = *value {
+        if let Ok(path) = value.parse() {
+            return Ok(path);
+        }
+    }
+
+    Err(syn::Error::new_spanned(value, format!("#[{}({} = \"...\")] must be a string with a path", namespace, name)))
+}
+
+fn field_display_name(field: &syn::Field) -> String {
+    match field.ident {
+        Some(ref ident) => format!("`{}`", ident),
+        None => "of a tuple".to_owned(),
+    }
 }
@@ -0,0 +1,261 @@
+use proc_macro2::{Span, TokenStream};
+use syn;
+
+use {EnumVariants, FieldEncoding, attr_meta_items, enum_variants, field_encoding, get_id_from_attrs, with_bound};
+
+
+pub fn impl_tl_describe(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
+    let generics = with_bound(&ast.generics, parse_quote!(_serde_mtproto::TlDescribe));
+    let (item_impl_generics, item_ty_generics, item_where_clause) = generics.split_for_impl();
+
+    let item_name = &ast.ident;
+    let dummy_const = syn::Ident::new(&format!("_IMPL_TL_DESCRIBE_FOR_{}", item_name), Span::call_site());
+
+    let item_attrs = ItemAttrs::from_attrs(&ast.attrs)?;
+    let type_name = item_attrs.type_name.unwrap_or_else(|| item_name.to_string());
+    let type_quoted = quote! { _serde_mtproto::schema::TlType::Boxed(#type_name) };
+
+    let constructors = match ast.data {
+        syn::Data::Struct(ref data) => {
+            let (id, _) = get_id_from_attrs(&ast.attrs, item_name)?;
+            let name = item_attrs.name.unwrap_or_else(|| lower_camel_case(&item_name.to_string()));
+
+            let result = match (item_attrs.is_function, item_attrs.result) {
+                (true, Some(result)) => quote! { <#result as _serde_mtproto::TlDescribe>::tl_type() },
+                (true, None) => {
+                    return Err(syn::Error::new_spanned(item_name,
+                        "#[tl(function)] requires #[tl(result = \"...\")] with the type the function returns"));
+                },
+                (false, Some(result)) => {
+                    return Err(syn::Error::new_spanned(result,
+                        "#[tl(result = \"...\")] can only be used together with #[tl(function)]"));
+                },
+                (false, None) => type_quoted.clone(),
+            };
+
+            vec![describe_constructor(&name, id, &data.fields, &result, item_attrs.is_function)?]
+        },
+
+        syn::Data::Enum(ref data) => {
+            if item_attrs.is_function || item_attrs.result.is_some() {
+                return Err(syn::Error::new_spanned(item_name, "only structs can be described as TL functions"));
+            }
+
+            let EnumVariants { identified, .. } = enum_variants(data)?;
+            let mut constructors = Vec::new();
+
+            for &(variant, id) in &identified {
+                let name = match tl_name_from_attrs(&variant.attrs)? {
+                    Some(name) => name,
+                    None => format!("{}{}", lower_camel_case(&item_name.to_string()), variant.ident),
+                };
+
+                constructors.push(describe_constructor(&name, id, &variant.fields, &type_quoted, false)?);
+            }
+
+            constructors
+        },
+
+        syn::Data::Union(ref data) => {
+            return Err(syn::Error::new_spanned(data.union_token,
+                "#[derive(TlDescribe)] is not supported for unions"));
+        },
+    };
+
+    Ok(quote! {
+        #[allow(non_upper_case_globals)]
+        const #dummy_const: () = {
+            extern crate serde_mtproto as _serde_mtproto;
+
+            impl #item_impl_generics _serde_mtproto::TlDescribe for #item_name #item_ty_generics
+                #item_where_clause
+            {
+                fn tl_type() -> _serde_mtproto::schema::TlType {
+                    #type_quoted
+                }
+
+                fn tl_constructors() -> Vec<_serde_mtproto::schema::TlConstructor> {
+                    vec![#(#constructors),*]
+                }
+            }
+        };
+    })
+}
+
+/// Options of `#[tl(...)]` attributes of a struct or an enum.
+#[derive(Default)]
+struct ItemAttrs {
+    name: Option<String>,
+    type_name: Option<String>,
+    is_function: bool,
+    result: Option<syn::Type>,
+}
+
+impl ItemAttrs {
+    fn from_attrs(attrs: &[syn::Attribute]) -> syn::Result<ItemAttrs> {
+        let mut item_attrs = ItemAttrs::default();
+
+        for meta_item in attr_meta_items(attrs, "tl")? {
+            match meta_item {
+                syn::Meta::Path(ref path) if path.is_ident("function") => item_attrs.is_function = true,
+                syn::Meta::NameValue(ref name_value) if name_value.path.is_ident("name") => {
+                    item_attrs.name = Some(parse_str_lit("name", &name_value.lit)?.value());
+                },
+                syn::Meta::NameValue(ref name_value) if name_value.path.is_ident("type_name") => {
+                    item_attrs.type_name = Some(parse_str_lit("type_name", &name_value.lit)?.value());
+                },
+                syn::Meta::NameValue(ref name_value) if name_value.path.is_ident("result") => {
+                    let result = parse_str_lit("result", &name_value.lit)?.parse().map_err(|_| {
+                        syn::Error::new_spanned(&name_value.lit, "#[tl(result = \"...\")] must be a string with a type")
+                    })?;
+
+                    item_attrs.result = Some(result);
+                },
+                _ => {
+                    return Err(syn::Error::new_spanned(meta_item,
+                        "#[tl(...)] only supports `name`, `type_name`, `function` and `result` options here"));
+                },
+            }
+        }
+
+        Ok(item_attrs)
+    }
+}
+
+/// Constructor name from `#[tl(name = "...")]` of an enum variant.
+fn tl_name_from_attrs(attrs: &[syn::Attribute]) -> syn::Result<Option<String>> {
+    let mut name = None;
+
+    for meta_item in attr_meta_items(attrs, "tl")? {
+        match meta_item {
+            syn::Meta::NameValue(ref name_value) if name_value.path.is_ident("name") => {
+                name = Some(parse_str_lit("name", &name_value.lit)?.value());
+            },
+            _ => {
+                return Err(syn::Error::new_spanned(meta_item,
+                    "#[tl(...)] only supports `name` option on enum variants"));
+            },
+        }
+    }
+
+    Ok(name)
+}
+
+/// Expression which constructs a `TlConstructor`.
+fn describe_constructor(name: &str,
+                        id: u32,
+                        fields: &syn::Fields,
+                        result: &TokenStream,
+                        is_function: bool)
+                       -> syn::Result<TokenStream> {
+    let mut fields_quoted = Vec::new();
+
+    for (i, field) in fields.iter().enumerate() {
+        let field_name = match field.ident {
+            Some(ref ident) => ident.to_string().trim_start_matches("r#").to_owned(),
+            None => format!("field{}", i),
+        };
+        let field_type = describe_field_type(field)?;
+
+        fields_quoted.push(quote! {
+            _serde_mtproto::schema::TlField {
+                name: #field_name,
+                ty: #field_type,
+            }
+        });
+    }
+
+    Ok(quote! {
+        _serde_mtproto::schema::TlConstructor {
+            name: #name,
+            id: #id,
+            fields: vec![#(#fields_quoted),*],
+            result: #result,
+            is_function: #is_function,
+        }
+    })
+}
+
+/// Expression which evaluates to the `TlType` of a field.
+fn describe_field_type(field: &syn::Field) -> syn::Result<TokenStream> {
+    let mut is_flags = false;
+    let mut flag = None;
+
+    for meta_item in attr_meta_items(&field.attrs, "tl")? {
+        match meta_item {
+            syn::Meta::Path(ref path) if path.is_ident("flags") => is_flags = true,
+            syn::Meta::NameValue(ref name_value) if name_value.path.is_ident("flag") => {
+                flag = Some(parse_flag(&name_value.lit)?);
+            },
+            _ => {
+                return Err(syn::Error::new_spanned(meta_item,
+                    "#[tl(...)] only supports `flags` and `flag` options on fields"));
+            },
+        }
+    }
+
+    if is_flags {
+        return Ok(quote! { _serde_mtproto::schema::TlType::Flags });
+    }
+
+    let ty = &field.ty;
+    let is_bool = match *ty {
+        syn::Type::Path(ref type_path) => type_path.qself.is_none() && type_path.path.is_ident("bool"),
+        _ => false,
+    };
+
+    let mut field_type = match flag {
+        // A flag which is present only in the field of flags
+        Some(_) if is_bool => quote! { _serde_mtproto::schema::TlType::Builtin("true") },
+        _ => quote! { <#ty as _serde_mtproto::TlDescribe>::tl_type() },
+    };
+
+    if let FieldEncoding::Bare = field_encoding(field)? {
+        field_type = quote! { #field_type.bare() };
+    }
+
+    if let Some((flags, bit)) = flag {
+        field_type = quote! {
+            _serde_mtproto::schema::TlType::Conditional {
+                flags: #flags,
+                bit: #bit,
+                ty: ::std::boxed::Box::new(#field_type),
+            }
+        };
+    }
+
+    Ok(field_type)
+}
+
+/// Parse `"flags.N"` into the name of the field of flags and the number of the bit.
+fn parse_flag(lit: &syn::Lit) -> syn::Result<(String, u32)> {
+    let value = parse_str_lit("flag", lit)?.value();
+    let mut parts = value.splitn(2, '.');
+
+    if let (Some(flags), Some(bit)) = (parts.next(), parts.next()) {
+        if let Ok(bit) = bit.parse::<u32>() {
+            if !flags.is_empty() && bit < 32 {
+                return Ok((flags.to_owned(), bit));
+            }
+        }
+    }
+
+    Err(syn::Error::new_spanned(lit, "#[tl(flag = \"...\")] must look like \"flags.0\" with a bit number below 32"))
+}
+
+fn parse_str_lit<'a>(name: &str, lit: &'a syn::Lit) -> syn::Result<&'a syn::LitStr> {
+    match *lit {
+        syn::Lit::Str(ref value) => Ok(value),
+        _ => Err(syn::Error::new_spanned(lit, format!("#[tl({} = \"...\")] must be a string", name))),
+    }
+}
+
+/// Turn `MessageMedia` into `messageMedia`.
+fn lower_camel_case(name: &str) -> String {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
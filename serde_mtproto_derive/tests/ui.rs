@@ -0,0 +1,8 @@
+extern crate trybuild;
+
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,12 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoIdentifiable)]
+enum Foo {
+    #[id = "0xdeadbeef"]
+    Bar,
+    #[id = 0xdeadbeef]
+    Baz,
+}
+
+fn main() {}
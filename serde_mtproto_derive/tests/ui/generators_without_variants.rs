@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoIdentifiable)]
+#[mtproto_identifiable(arbitrary)]
+enum Foo {
+    #[mtproto(other)]
+    Unknown(u32, Vec<u8>),
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoIdentifiable)]
+#[id = "0xdeadbeeg"]
+struct Foo;
+
+fn main() {}
@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoIdentifiable)]
+enum Foo {
+    #[id = 0xdeadbeef]
+    Bar,
+    Baz,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoIdentifiable)]
+#[id = "deadbeef"]
+struct Foo;
+
+fn main() {}
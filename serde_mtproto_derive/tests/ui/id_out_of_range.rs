@@ -0,0 +1,8 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoIdentifiable)]
+#[id = 0x1_0000_0000]
+struct Foo;
+
+fn main() {}
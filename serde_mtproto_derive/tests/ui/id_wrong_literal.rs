@@ -0,0 +1,8 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoIdentifiable)]
+#[id = 1.5]
+struct Foo;
+
+fn main() {}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoIdentifiable)]
+enum Foo {
+    #[id = 0xdeadbeef]
+    Bar,
+    #[mtproto(other)]
+    Unknown(u32, Vec<u8>),
+    #[mtproto(other)]
+    AlsoUnknown(u32, Vec<u8>),
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoSerialize)]
+#[id = 0xdeadbeef]
+struct Foo {
+    #[mtproto(bare)]
+    #[mtproto(serde)]
+    bar: Vec<u32>,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoDeserialize)]
+#[id = 0xdeadbeef]
+struct Foo {
+    #[mtproto(boxed)]
+    bar: Vec<u32>,
+}
+
+fn main() {}
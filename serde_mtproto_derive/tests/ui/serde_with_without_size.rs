@@ -0,0 +1,21 @@
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+mod as_u8 {
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*value as u8)
+    }
+}
+
+#[derive(Serialize, MtProtoSized)]
+struct Foo {
+    #[serde(with = "as_u8")]
+    bar: u32,
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+use serde::Serializer;
+
+fn serialize_as_u8<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u8(*value as u8)
+}
+
+#[derive(Serialize, MtProtoSized)]
+struct Foo {
+    #[serde(serialize_with = "serialize_as_u8")]
+    bar: u32,
+}
+
+fn main() {}
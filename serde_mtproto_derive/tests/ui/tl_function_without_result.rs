@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(TlDescribe)]
+#[id = 0xdeadbeef]
+#[tl(function)]
+struct GetFoo {
+    id: u32,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(TlDescribe)]
+#[id = 0xdeadbeef]
+struct Foo {
+    #[tl(flags)]
+    flags: u32,
+    #[tl(flag = "flags.32")]
+    bar: bool,
+}
+
+fn main() {}
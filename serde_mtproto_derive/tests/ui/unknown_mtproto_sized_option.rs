@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+#[derive(MtProtoSized)]
+struct Foo {
+    #[mtproto_sized(ignore)]
+    bar: u32,
+}
+
+fn main() {}
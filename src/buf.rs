@@ -0,0 +1,173 @@
+//! Integration with the `bytes` crate.
+//!
+//! This module is available with the `bytes` feature enabled. It allows to serialize directly into
+//! a `BufMut` and deserialize from a `Buf` without going through intermediate buffers. Byte
+//! sequences read by `Deserializer::read_shared_bytes`, e.g. `SharedBytes` deserialized with
+//! `MtProtoDeserialize`, from `Bytes` or `BytesMut` share memory with the input instead of being
+//! copied.
+
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+
+use bytes::{Buf, BufMut, Bytes};
+use serde::de::{Deserialize, DeserializeOwned, Deserializer as SerdeDeserializer, Error as DeError, Visitor};
+use serde::ser::{Serialize, Serializer as SerdeSerializer};
+
+use de::Deserializer;
+use error;
+use native::{MtProtoDeserialize, MtProtoSerialize};
+use ser::Serializer;
+use sized::{MtProtoSized, size_hint_from_byte_seq_len};
+
+
+/// A byte sequence backed by `Bytes`.
+///
+/// It is [de]serialized exactly like `ByteBuf`. When deserialized with `MtProtoDeserialize` from a
+/// `Deserializer::from_buf`, the contents are taken with `Buf::copy_to_bytes` which doesn't copy
+/// anything for `Bytes` and `BytesMut` inputs, but returns a reference-counted slice of the input.
+/// Serde has no way to pass `Bytes` to a `Deserialize` implementation, so with `Deserialize` (and
+/// therefore `from_buf`) the contents are copied once.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SharedBytes {
+    inner: Bytes,
+}
+
+impl SharedBytes {
+    /// Wrap `Bytes`.
+    pub fn new(inner: Bytes) -> SharedBytes {
+        SharedBytes {
+            inner: inner,
+        }
+    }
+
+    /// Return an immutable reference to the underlying `Bytes`.
+    pub fn inner(&self) -> &Bytes {
+        &self.inner
+    }
+
+    /// Unwrap the underlying `Bytes`.
+    pub fn into_inner(self) -> Bytes {
+        self.inner
+    }
+}
+
+impl From<Bytes> for SharedBytes {
+    fn from(inner: Bytes) -> SharedBytes {
+        SharedBytes::new(inner)
+    }
+}
+
+impl Deref for SharedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.inner
+    }
+}
+
+impl Serialize for SharedBytes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: SerdeSerializer
+    {
+        serializer.serialize_bytes(&self.inner)
+    }
+}
+
+impl<'de> Deserialize<'de> for SharedBytes {
+    fn deserialize<D>(deserializer: D) -> Result<SharedBytes, D::Error>
+        where D: SerdeDeserializer<'de>
+    {
+        struct SharedBytesVisitor;
+
+        impl<'de> Visitor<'de> for SharedBytesVisitor {
+            type Value = SharedBytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a byte sequence")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<SharedBytes, E>
+                where E: DeError
+            {
+                Ok(SharedBytes::new(Bytes::copy_from_slice(v)))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<SharedBytes, E>
+                where E: DeError
+            {
+                Ok(SharedBytes::new(Bytes::from(v)))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(SharedBytesVisitor)
+    }
+}
+
+impl MtProtoSerialize for SharedBytes {
+    fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        Serialize::serialize(self, ser)
+    }
+}
+
+impl MtProtoDeserialize for SharedBytes {
+    fn deserialize_bare<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<SharedBytes> {
+        de.read_shared_bytes().map(SharedBytes::new)
+    }
+}
+
+impl MtProtoSized for SharedBytes {
+    fn size_hint(&self) -> error::Result<usize> {
+        size_hint_from_byte_seq_len(self.inner.len())
+    }
+}
+
+
+/// Serialize the given data structure as binary MTProto into the buffer.
+pub fn to_buf_mut<B, T>(buf: &mut B, value: &T) -> error::Result<()>
+    where B: BufMut,
+          T: ?Sized + Serialize,
+{
+    let mut ser = Serializer::new(BufMut::writer(buf));
+    value.serialize(&mut ser)?;
+
+    Ok(())
+}
+
+/// Deserialize an instance of type `T` from a buffer of binary MTProto.
+///
+/// The buffer is advanced past the deserialized value, pass it as `&mut buf` to use the
+/// remaining data afterwards.
+pub fn from_buf<B, T>(buf: B, enum_variant_id: Option<&'static str>) -> error::Result<T>
+    where B: Buf,
+          T: DeserializeOwned,
+{
+    let mut de = Deserializer::from_buf(buf, enum_variant_id);
+    let value: T = Deserialize::deserialize(&mut de)?;
+
+    Ok(value)
+}
+
+impl<B: Buf> Deserializer<::bytes::buf::Reader<B>> {
+    /// Create a MTProto deserializer from a `Buf` and enum variant hint.
+    ///
+    /// Byte sequences read by `read_shared_bytes` share memory with the buffer if it is `Bytes`
+    /// or `BytesMut`.
+    pub fn from_buf(buf: B, enum_variant_id: Option<&'static str>) -> Deserializer<::bytes::buf::Reader<B>> {
+        Deserializer::new(buf.reader(), enum_variant_id).share_bytes_with(share_bytes::<B>)
+    }
+}
+
+fn share_bytes<B: Buf>(reader: &mut ::bytes::buf::Reader<B>, len: usize) -> io::Result<Bytes> {
+    let buf = reader.get_mut();
+
+    if buf.remaining() < len {
+        // Consume the rest to report the correct offset
+        let remaining = buf.remaining();
+        buf.advance(remaining);
+
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+    }
+
+    Ok(buf.copy_to_bytes(len))
+}
@@ -0,0 +1,230 @@
+//! Encoding policies for `Serializer` and `Deserializer`.
+//!
+//! MTProto leaves several details of mapping Rust values to its binary representation open.
+//! `Config` decides how they are handled. Its defaults match the behavior of plain `to_*` and
+//! `from_*` functions.
+
+use std::io;
+
+use serde::de::{Deserialize, DeserializeOwned};
+use serde::ser::Serialize;
+
+use de::Deserializer;
+use error;
+use ser::Serializer;
+
+
+/// How `f64` values are narrowed when deserialized into `f32`.
+///
+/// MTProto has only one floating-point type of double precision, so `f32` values are always
+/// widened to `f64` when serialized, which is lossless.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FloatNarrowing {
+    /// Fail with `ErrorKind::FloatCast` if the value is out of `f32` range, round it otherwise.
+    Checked,
+    /// Fail with `ErrorKind::FloatCast` unless the value is exactly representable as `f32`.
+    Strict,
+    /// Round the value to the nearest `f32`, out of range values become infinite.
+    Lenient,
+}
+
+impl Default for FloatNarrowing {
+    fn default() -> FloatNarrowing {
+        FloatNarrowing::Checked
+    }
+}
+
+/// How `char` values are represented, since MTProto has no dedicated type for them.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CharEncoding {
+    /// Fail with `ErrorKind::UnsupportedSerdeType`.
+    Unsupported,
+    /// A 4-byte integer holding the Unicode scalar value.
+    Int,
+    /// A string containing exactly one character encoded as UTF-8.
+    Utf8,
+}
+
+impl Default for CharEncoding {
+    fn default() -> CharEncoding {
+        CharEncoding::Unsupported
+    }
+}
+
+/// A builder of encoding policies for `Serializer` and `Deserializer`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate serde_mtproto;
+/// use std::collections::HashMap;
+///
+/// use serde_mtproto::config::{CharEncoding, Config};
+///
+/// # fn run() -> serde_mtproto::Result<()> {
+/// let config = Config::new()
+///     .sort_maps(true)
+///     .char_encoding(CharEncoding::Utf8)
+///     .reject_trailing_bytes(true);
+///
+/// let mut map = HashMap::new();
+/// map.insert('b', 2u32);
+/// map.insert('a', 1u32);
+///
+/// let bytes = config.to_bytes(&map)?;
+/// // Always the same bytes regardless of the iteration order of the map
+/// assert_eq!(bytes, [2, 0, 0, 0, 1, 97, 0, 0, 1, 0, 0, 0, 1, 98, 0, 0, 2, 0, 0, 0]);
+///
+/// let decoded: HashMap<char, u32> = config.from_bytes(&bytes, None)?;
+/// assert_eq!(decoded, map);
+///
+/// assert!(config.from_bytes::<HashMap<char, u32>>(&[0, 0, 0, 0, 1], None).is_err());
+/// #     Ok(())
+/// # }
+/// # fn main() { run().unwrap(); }
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Config {
+    pub(crate) float_narrowing: FloatNarrowing,
+    pub(crate) reject_trailing_bytes: bool,
+    pub(crate) verify_padding: bool,
+    pub(crate) sort_maps: bool,
+    pub(crate) char_encoding: CharEncoding,
+    pub(crate) lossy_strings: bool,
+}
+
+impl Config {
+    /// Create a configuration with default policies.
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Set how `f64` values are narrowed when deserialized into `f32`.
+    ///
+    /// Defaults to `FloatNarrowing::Checked`.
+    pub fn float_narrowing(mut self, float_narrowing: FloatNarrowing) -> Config {
+        self.float_narrowing = float_narrowing;
+        self
+    }
+
+    /// Fail with `ErrorKind::TrailingBytes` if `from_bytes` or `from_reader` leave any input
+    /// after the deserialized value.
+    ///
+    /// Disabled by default.
+    pub fn reject_trailing_bytes(mut self, reject: bool) -> Config {
+        self.reject_trailing_bytes = reject;
+        self
+    }
+
+    /// Fail with `ErrorKind::NonZeroPadding` if padding bytes which follow strings and byte
+    /// sequences are not zeros.
+    ///
+    /// Disabled by default.
+    pub fn verify_padding(mut self, verify: bool) -> Config {
+        self.verify_padding = verify;
+        self
+    }
+
+    /// Write map entries ordered by the serialized representation of their keys instead of the
+    /// iteration order, so that equal maps (like `HashMap`s) always produce the same bytes.
+    ///
+    /// Entries are buffered in memory until the whole map is serialized. Disabled by default.
+    pub fn sort_maps(mut self, sort: bool) -> Config {
+        self.sort_maps = sort;
+        self
+    }
+
+    /// Set how `char` values are represented.
+    ///
+    /// Defaults to `CharEncoding::Unsupported`.
+    pub fn char_encoding(mut self, char_encoding: CharEncoding) -> Config {
+        self.char_encoding = char_encoding;
+        self
+    }
+
+    /// Replace invalid UTF-8 sequences in deserialized strings with `U+FFFD` instead of failing
+    /// with `ErrorKind::InvalidUtf8`.
+    ///
+    /// Use `TlString` to keep the original bytes instead. Disabled by default.
+    pub fn lossy_strings(mut self, lossy: bool) -> Config {
+        self.lossy_strings = lossy;
+        self
+    }
+
+    /// Serialize the given data structure as a byte vector of binary MTProto.
+    pub fn to_bytes<T>(&self, value: &T) -> error::Result<Vec<u8>>
+        where T: ?Sized + Serialize
+    {
+        let mut ser = Serializer::with_config(Vec::new(), *self);
+        value.serialize(&mut ser)?;
+
+        Ok(ser.into_writer())
+    }
+
+    /// Serialize the given data structure as binary MTProto into the IO stream.
+    pub fn to_writer<W, T>(&self, writer: W, value: &T) -> error::Result<()>
+        where W: io::Write,
+              T: ?Sized + Serialize,
+    {
+        let mut ser = Serializer::with_config(writer, *self);
+        value.serialize(&mut ser)
+    }
+
+    /// Serialize the given data structure prepended with its serialized size as a byte vector of
+    /// binary MTProto.
+    ///
+    /// See `ser::to_bytes_with_size` for details.
+    pub fn to_bytes_with_size<T>(&self, value: &T) -> error::Result<Vec<u8>>
+        where T: ?Sized + Serialize
+    {
+        let mut ser = Serializer::with_config(io::Cursor::new(Vec::new()), *self);
+        ser.serialize_with_size(value)?;
+
+        Ok(ser.into_writer().into_inner())
+    }
+
+    /// Serialize the given data structure prepended with its serialized size as binary MTProto
+    /// into the seekable IO stream.
+    ///
+    /// See `ser::to_writer_with_size` for details.
+    pub fn to_writer_with_size<W, T>(&self, writer: W, value: &T) -> error::Result<()>
+        where W: io::Write + io::Seek,
+              T: ?Sized + Serialize,
+    {
+        let mut ser = Serializer::with_config(writer, *self);
+        ser.serialize_with_size(value)
+    }
+
+    /// Deserialize an instance of type `T` from bytes of binary MTProto.
+    pub fn from_bytes<'a, T>(&self, bytes: &'a [u8], enum_variant_id: Option<&'static str>) -> error::Result<T>
+        where T: Deserialize<'a>
+    {
+        let mut de = Deserializer::with_config(bytes, enum_variant_id, *self);
+        let value: T = Deserialize::deserialize(&mut de)?;
+        self.check_end(&mut de)?;
+
+        Ok(value)
+    }
+
+    /// Deserialize an instance of type `T` from an IO stream of binary MTProto.
+    ///
+    /// If trailing bytes are rejected, the stream is expected to end right after the value.
+    pub fn from_reader<R, T>(&self, reader: R, enum_variant_id: Option<&'static str>) -> error::Result<T>
+        where R: io::Read,
+              T: DeserializeOwned,
+    {
+        let mut de = Deserializer::with_config(reader, enum_variant_id, *self);
+        let value: T = Deserialize::deserialize(&mut de)?;
+        self.check_end(&mut de)?;
+
+        Ok(value)
+    }
+
+    fn check_end<R: io::Read>(&self, de: &mut Deserializer<R>) -> error::Result<()> {
+        if self.reject_trailing_bytes {
+            de.end()?;
+        }
+
+        Ok(())
+    }
+}
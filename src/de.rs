@@ -1,34 +1,226 @@
 //! Deserialize MTProto binary representation to a Rust data structure.
 
-use std::io;
-
-use byteorder::{ReadBytesExt, LittleEndian};
+use std::borrow::Cow;
+use std::cmp;
+use std::io::{self, Read};
+use std::marker::PhantomData;
+use std::mem;
+use std::str;
+
+use byteorder::{ByteOrder, ReadBytesExt, LittleEndian};
 use serde::de::{self, Deserialize, DeserializeOwned, DeserializeSeed, Visitor};
 
-use error::{self, DeErrorKind, DeSerdeType};
-use identifiable::{BOOL_FALSE_ID, BOOL_TRUE_ID};
-use utils::{safe_float_cast, safe_int_cast};
+use config::{CharEncoding, Config, FloatNarrowing};
+use error::{self, ErrorKind, SerdeType};
+use identifiable::{BOOL_FALSE_ID, BOOL_TRUE_ID, Identifiable};
+use utils::{DECLARED_SIZE_NEWTYPE_NAME, PACKED_INTS_NEWTYPE_NAME, PACKED_NEWTYPE_NAME, RAW_NEWTYPE_NAME,
+            REMAINING_BYTES_NEWTYPE_NAME, UNKNOWN_VALUE_NEWTYPE_NAME, safe_int_cast};
+use wrappers::Boxed;
+
+
+/// The maximum number of bytes of packed integers allocated before they are actually read.
+const MAX_PREALLOCATED_PACKED_BYTES: u64 = 64 * 1024;
+
+/// Packed integers up to this size in bytes are read into a buffer on the stack.
+const PACKED_BYTES_STACK_BUFFER_SIZE: usize = 64;
+
+
+/// An `io::Read` wrapper which keeps track of the number of bytes read so far and can refuse to
+/// read past a certain position (like `io::Take` does, but without taking ownership).
+///
+/// It can also capture the bytes being read to reproduce the exact serialized span of a value
+/// and peek at the following bytes without consuming them.
+#[derive(Debug)]
+struct LimitedReader<R: io::Read> {
+    inner: R,
+    position: u64,
+    limit: Option<u64>,
+    /// How many bytes past the limit a read attempted to get, if any.
+    limit_overrun: Option<u64>,
+    captured: Option<Vec<u8>>,
+    /// Bytes read from `inner` by `peek` which are returned first by subsequent reads.
+    peeked: Vec<u8>,
+}
+
+impl<R: io::Read> LimitedReader<R> {
+    fn new(inner: R) -> LimitedReader<R> {
+        LimitedReader {
+            inner: inner,
+            position: 0,
+            limit: None,
+            limit_overrun: None,
+            captured: None,
+            peeked: Vec::new(),
+        }
+    }
+
+    /// Fail if the following `len` bytes extend past the limit, remembering by how much.
+    fn check_limit(&mut self, len: u64) -> io::Result<()> {
+        if let Some(limit) = self.limit {
+            let end = self.position + len;
+
+            if end > limit {
+                self.limit_overrun = Some(end - limit);
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "cannot read past the size limit"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the next `len` bytes without consuming them.
+    fn peek(&mut self, len: usize) -> io::Result<&[u8]> {
+        self.check_limit(len as u64)?;
+
+        while self.peeked.len() < len {
+            let start = self.peeked.len();
+            self.peeked.resize(len, 0);
+
+            match self.inner.read(&mut self.peeked[start..]) {
+                Ok(0) => {
+                    self.peeked.truncate(start);
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+                },
+                Ok(read_len) => self.peeked.truncate(start + read_len),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => self.peeked.truncate(start),
+                Err(e) => {
+                    self.peeked.truncate(start);
+                    return Err(e);
+                },
+            }
+        }
+
+        Ok(&self.peeked[..len])
+    }
+}
+
+impl<R: io::Read> io::Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max_len = match self.limit {
+            Some(limit) => {
+                let remaining = limit - self.position;
+                if remaining < buf.len() as u64 {
+                    // The read is cut short, so it was requested to end past the limit
+                    self.limit_overrun = Some(buf.len() as u64 - remaining);
+
+                    // Cannot truncate since `remaining` is less than `buf.len()`
+                    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+                    let remaining_usize = remaining as usize;
+                    remaining_usize
+                } else {
+                    buf.len()
+                }
+            },
+            None => buf.len(),
+        };
+
+        let read_len = if self.peeked.is_empty() {
+            self.inner.read(&mut buf[..max_len])?
+        } else {
+            let read_len = cmp::min(max_len, self.peeked.len());
+            buf[..read_len].copy_from_slice(&self.peeked[..read_len]);
+            self.peeked.drain(..read_len);
+            read_len
+        };
+        self.position += read_len as u64;
+
+        if let Some(ref mut captured) = self.captured {
+            captured.extend_from_slice(&buf[..read_len]);
+        }
+
+        Ok(read_len)
+    }
+}
 
 
 /// A structure that deserializes  MTProto binary representation into Rust values.
 #[derive(Debug)]
 pub struct Deserializer<R: io::Read> {
-    reader: R,
+    reader: LimitedReader<R>,
     enum_variant_id: Option<&'static str>,
+    config: Config,
+    pending_size: Option<u32>,
+    skip_trailing_bytes: bool,
+    scratch: Vec<u8>,
+    required_position: u64,
+    read_to_end: bool,
+    #[cfg(feature = "bytes")]
+    share_bytes: Option<ShareBytesFn<R>>,
 }
 
+/// Take a byte sequence of the given length from a reader without copying it.
+#[cfg(feature = "bytes")]
+pub(crate) type ShareBytesFn<R> = fn(&mut R, usize) -> io::Result<::bytes::Bytes>;
+
 impl<R: io::Read> Deserializer<R> {
     /// Create a MTProto deserializer from an `io::Read` and enum variant hint.
     pub fn new(reader: R, enum_variant_id: Option<&'static str>) -> Deserializer<R> {
+        Deserializer::with_config(reader, enum_variant_id, Config::default())
+    }
+
+    /// Create a MTProto deserializer from an `io::Read` and enum variant hint which follows the
+    /// given encoding policies.
+    ///
+    /// Note that trailing bytes can only be rejected by `Config::from_bytes`,
+    /// `Config::from_reader` or by calling `end` explicitly.
+    pub fn with_config(reader: R, enum_variant_id: Option<&'static str>, config: Config) -> Deserializer<R> {
         Deserializer {
-            reader: reader,
+            reader: LimitedReader::new(reader),
             enum_variant_id: enum_variant_id,
+            config: config,
+            pending_size: None,
+            skip_trailing_bytes: false,
+            scratch: Vec::new(),
+            required_position: 0,
+            read_to_end: false,
+            #[cfg(feature = "bytes")]
+            share_bytes: None,
         }
     }
 
+    /// Make `read_shared_bytes` take byte sequences from the reader using `share`.
+    #[cfg(feature = "bytes")]
+    pub(crate) fn share_bytes_with(mut self, share: ShareBytesFn<R>) -> Deserializer<R> {
+        self.share_bytes = Some(share);
+        self
+    }
+
+    /// Allow values wrapped in `WithSize` or `BoxedWithSize` to not consume all bytes of their
+    /// declared size.
+    ///
+    /// The unconsumed bytes are then skipped instead of failing with
+    /// `ErrorKind::UnconsumedBytes`. This is useful for forward compatibility with newer
+    /// versions of a type which have additional trailing fields.
+    pub fn skip_trailing_bytes(mut self, skip: bool) -> Deserializer<R> {
+        self.skip_trailing_bytes = skip;
+        self
+    }
+
     /// Unwraps the `Deserializer` and returns the underlying `io::Read`.
+    ///
+    /// Note that bytes which were peeked at, but not consumed afterwards are lost.
     pub fn into_reader(self) -> R {
-        self.reader
+        self.reader.inner
+    }
+
+    /// Number of bytes processed by this `Deserializer` so far.
+    pub fn position(&self) -> u64 {
+        self.reader.position
+    }
+
+    /// Check that the input ends here, failing with `ErrorKind::TrailingBytes` otherwise.
+    pub fn end(&mut self) -> error::Result<()> {
+        let limit_overrun = self.reader.limit_overrun;
+
+        match self.reader.peek(1) {
+            Ok(_) => bail!(ErrorKind::TrailingBytes { offset: self.reader.position }),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                // Reaching the limit is expected here
+                self.reader.limit_overrun = limit_overrun;
+                Ok(())
+            },
+            Err(e) => Err(e.into()),
+        }
     }
 
     /// Consumes the `Deserializer` and returns remaining unprocessed bytes.
@@ -39,8 +231,139 @@ impl<R: io::Read> Deserializer<R> {
         Ok(buf)
     }
 
+    /// Read a byte sequence and copy it into `writer` without holding the whole byte sequence in
+    /// memory.
+    ///
+    /// This is the streaming counterpart of deserializing a `ByteBuf` which is useful for large
+    /// payloads like file parts. Returns the length of the byte sequence.
+    pub fn stream_bytes_to<W>(&mut self, mut writer: W) -> error::Result<u64>
+        where W: io::Write
+    {
+        let (len, padding) = self.get_str_info()?;
+        let len = safe_int_cast::<usize, u64>(len)?;
+        self.read_with(|r| r.check_limit(len))?;
+
+        let copied = io::copy(&mut (&mut self.reader).take(len), &mut writer)?;
+        if copied < len {
+            bail!(ErrorKind::UnexpectedEof { offset: self.reader.position });
+        }
+
+        self.read_padding(padding)?;
+
+        debug!("Streamed {} bytes", len);
+        Ok(len)
+    }
+
+    /// Return the type id of the following boxed value without consuming it.
+    pub fn peek_type_id(&mut self) -> error::Result<u32> {
+        self.peek_u32()
+    }
+
+    /// Return the following `u32` value without consuming it.
+    ///
+    /// This is useful to look at the length of a vector before deserializing it.
+    pub fn peek_u32(&mut self) -> error::Result<u32> {
+        self.read_with(|r| r.peek(4).map(LittleEndian::read_u32))
+    }
+
+    /// Return the length of the following byte sequence or string without consuming it.
+    pub fn peek_bytes_len(&mut self) -> error::Result<usize> {
+        let first_byte = self.read_with(|r| r.peek(1).map(|bytes| bytes[0]))?;
+
+        match first_byte {
+            0..=253 => Ok(usize::from(first_byte)),
+            254 => {
+                let len = self.read_with(|r| r.peek(4).map(|bytes| LittleEndian::read_u24(&bytes[1..])))?;
+                safe_int_cast(len)
+            },
+            _ => Err(de::Error::invalid_value(
+                de::Unexpected::Unsigned(u64::from(first_byte)),
+                &"a byte in [0..254] range")),
+        }
+    }
+
+    /// Skip the following boxed value if its type id belongs to `T`.
+    ///
+    /// The value is deserialized as `Boxed<T>` and discarded. If the type id doesn't belong to `T`
+    /// (or to its catch-all variant inside a declared size), `ErrorKind::InvalidTypeId` is returned
+    /// and nothing is consumed, so that another type can be tried. Returns the type id of the
+    /// skipped value.
+    pub fn skip_value_by_type_id<T>(&mut self) -> error::Result<u32>
+        where T: DeserializeOwned + Identifiable
+    {
+        let type_id = self.peek_type_id()?;
+        let expected = T::all_type_ids();
+
+        let is_unknown_allowed = T::unknown_variant_id().is_some() && self.reader.limit.is_some();
+
+        if !expected.contains(&type_id) && !is_unknown_allowed {
+            bail!(ErrorKind::InvalidTypeId { found: type_id, expected: expected });
+        }
+
+        let start = self.reader.position;
+        let _: Boxed<T> = Deserialize::deserialize(&mut *self)?;
+        debug!("Skipped {} bytes of a value with type id {:#x}", self.reader.position - start, type_id);
+
+        Ok(type_id)
+    }
+
+    /// Start deserializing a bare vector of `T` values element by element.
+    ///
+    /// The count of elements is read immediately, each element is read only when the returned
+    /// iterator is advanced. This way huge vectors can be processed without collecting them
+    /// into a `Vec<T>` first. The iterator stops after the first error.
+    pub fn vector_iter<'a, T>(&'a mut self) -> error::Result<VectorIter<'a, R, T>>
+        where T: DeserializeOwned
+    {
+        let len = self.read_with(|r| r.read_u32::<LittleEndian>())?;
+        debug!("Deserializing vector of len {} lazily", len);
+
+        Ok(VectorIter {
+            de: self,
+            len: len,
+            next_index: 0,
+            failed: false,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Same as `vector_iter`, but for a boxed vector which is prefixed with the vector type id.
+    pub fn boxed_vector_iter<'a, T>(&'a mut self) -> error::Result<VectorIter<'a, R, T>>
+        where T: DeserializeOwned
+    {
+        let type_id = self.read_with(|r| r.read_u32::<LittleEndian>())?;
+        let expected = <Vec<T> as Identifiable>::all_type_ids();
+
+        if !expected.contains(&type_id) {
+            bail!(ErrorKind::InvalidTypeId { found: type_id, expected: expected });
+        }
+
+        self.vector_iter()
+    }
+
+    /// Position up to which the input is known to extend, judging by length prefixes and
+    /// declared sizes read so far.
+    pub(crate) fn required_position(&self) -> u64 {
+        self.required_position
+    }
+
+    /// Whether a value without a declared size was read up to the end of input, so that its
+    /// extent depends on where the input ends.
+    pub(crate) fn has_read_to_end(&self) -> bool {
+        self.read_to_end
+    }
+
+    /// Remember that the input must contain at least `len` more bytes after the current position.
+    fn require(&mut self, len: u64) {
+        let end = self.reader.position + len;
+
+        if end > self.required_position {
+            self.required_position = end;
+        }
+    }
+
     fn get_str_info(&mut self) -> error::Result<(usize, usize)> {
-        let first_byte = self.reader.read_u8()?;
+        let first_byte = self.read_with(|r| r.read_u8())?;
         let len;
         let rem;
 
@@ -48,7 +371,7 @@ impl<R: io::Read> Deserializer<R> {
             len = usize::from(first_byte);
             rem = (len + 1) % 4;
         } else if first_byte == 254 {
-            let uncasted = self.reader.read_u24::<LittleEndian>()?;
+            let uncasted = self.read_with(|r| r.read_u24::<LittleEndian>())?;
             len = safe_int_cast::<u32, usize>(uncasted)?;
             rem = len % 4;
         } else { // must be 255
@@ -59,34 +382,250 @@ impl<R: io::Read> Deserializer<R> {
         }
 
         let padding = (4 - rem) % 4;
+        self.require(safe_int_cast(len + padding)?);
 
         Ok((len, padding))
     }
 
-    fn read_string(&mut self) -> error::Result<String> {
-        let s_bytes = self.read_byte_buf()?;
-        let s = String::from_utf8(s_bytes)?;
+    fn read_byte_buf(&mut self) -> error::Result<Vec<u8>> {
+        let mut b = Vec::new();
+        self.read_byte_buf_into(&mut b)?;
 
-        Ok(s)
+        Ok(b)
     }
 
-    fn read_byte_buf(&mut self) -> error::Result<Vec<u8>> {
+    /// Read a byte sequence into `buf` replacing its contents, but keeping its capacity.
+    fn read_byte_buf_into(&mut self, buf: &mut Vec<u8>) -> error::Result<()> {
         let (len, padding) = self.get_str_info()?;
 
-        let mut b = vec![0; len];
-        self.reader.read_exact(&mut b)?;
+        buf.clear();
+        buf.resize(len, 0);
+        self.read_with(|r| r.read_exact(buf))?;
 
-        let mut p = vec![0; padding];
-        self.reader.read_exact(&mut p)?;
+        self.read_padding(padding)?;
 
-        Ok(b)
+        Ok(())
+    }
+
+    /// Read padding which follows a byte sequence, checking that it's zeros if configured to.
+    fn read_padding(&mut self, padding: usize) -> error::Result<()> {
+        let mut p = [0; 3];
+        self.read_with(|r| r.read_exact(&mut p[..padding]))?;
+
+        if self.config.verify_padding && p[..padding].iter().any(|&b| b != 0) {
+            bail!(ErrorKind::NonZeroPadding { offset: self.reader.position - padding as u64 });
+        }
+
+        Ok(())
+    }
+
+    /// Read a byte sequence as `Bytes`.
+    ///
+    /// If the deserializer was created with `Deserializer::from_buf` from `Bytes` or `BytesMut`,
+    /// the returned `Bytes` share memory with the input. Otherwise the byte sequence is copied.
+    #[cfg(feature = "bytes")]
+    pub fn read_shared_bytes(&mut self) -> error::Result<::bytes::Bytes> {
+        let share = match self.share_bytes {
+            Some(share) => share,
+            None => return self.read_byte_buf().map(::bytes::Bytes::from),
+        };
+
+        let (len, padding) = self.get_str_info()?;
+        let len_u64 = safe_int_cast::<usize, u64>(len)?;
+
+        self.read_with(|r| r.check_limit(len_u64))?;
+
+        let bytes = if self.reader.peeked.is_empty() {
+            // `share` bypasses `LimitedReader`, so do its bookkeeping here
+            let bytes = self.read_with(|r| share(&mut r.inner, len))?;
+            self.reader.position += len_u64;
+            if let Some(ref mut captured) = self.reader.captured {
+                captured.extend_from_slice(&bytes);
+            }
+
+            bytes
+        } else {
+            // Some of the bytes were already read from the input by `peek`
+            let mut buf = vec![0; len];
+            self.read_with(|r| r.read_exact(&mut buf))?;
+
+            ::bytes::Bytes::from(buf)
+        };
+
+        self.read_padding(padding)?;
+
+        Ok(bytes)
+    }
+
+    /// Read a byte sequence into the scratch buffer which is reused between reads and pass it
+    /// to `f` as a borrowed slice.
+    ///
+    /// Visiting borrowed data lets `deserialize_in_place` implementations (like the one for
+    /// `String`) reuse their existing allocations.
+    fn with_scratch<T, F>(&mut self, f: F) -> error::Result<T>
+        where F: FnOnce(&[u8]) -> error::Result<T>
+    {
+        let mut scratch = mem::replace(&mut self.scratch, Vec::new());
+        self.read_byte_buf_into(&mut scratch)?;
+
+        let result = f(&scratch);
+        self.scratch = scratch;
+
+        result
+    }
+
+    /// Perform a read operation, reporting the offset where the input ended if it did.
+    fn read_with<T, F>(&mut self, f: F) -> error::Result<T>
+        where F: FnOnce(&mut LimitedReader<R>) -> io::Result<T>
+    {
+        f(&mut self.reader).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                ErrorKind::UnexpectedEof { offset: self.reader.position }.into()
+            } else {
+                e.into()
+            }
+        })
+    }
+
+    /// Read `len` bytes of packed integers at once.
+    ///
+    /// The buffer grows as the bytes arrive, so a forged vector length can't make it allocate
+    /// more than `MAX_PREALLOCATED_PACKED_BYTES` up front.
+    fn read_packed_bytes(&mut self, len: u64) -> error::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(safe_int_cast(cmp::min(len, MAX_PREALLOCATED_PACKED_BYTES))?);
+        self.require(len);
+        self.read_with(|r| r.check_limit(len))?;
+
+        let read_len = self.read_with(|r| r.take(len).read_to_end(&mut buf))?;
+        if (read_len as u64) < len {
+            bail!(ErrorKind::UnexpectedEof { offset: self.reader.position });
+        }
+
+        Ok(buf)
+    }
+
+    /// Read all bytes up to the declared size of the value being deserialized or, if there is
+    /// none, to the end of input.
+    pub fn read_remaining_bytes(&mut self) -> error::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+
+        match self.reader.limit {
+            Some(limit) => {
+                let remaining = limit - self.reader.position;
+                let read_len = (&mut self.reader).take(remaining).read_to_end(&mut buf)?;
+                if (read_len as u64) < remaining {
+                    bail!(ErrorKind::UnexpectedEof { offset: self.reader.position });
+                }
+            },
+            None => {
+                self.reader.read_to_end(&mut buf)?;
+                self.read_to_end = true;
+            },
+        }
+
+        Ok(buf)
+    }
+
+    /// Read all bytes up to the declared size of the value being deserialized.
+    ///
+    /// Returns `None` and consumes nothing if there is no declared size, because then the extent
+    /// of the value can't be determined.
+    pub fn read_declared_remaining_bytes(&mut self) -> error::Result<Option<Vec<u8>>> {
+        if self.reader.limit.is_none() {
+            return Ok(None);
+        }
+
+        self.read_remaining_bytes().map(Some)
+    }
+
+    /// Deserialize a value which must consume exactly `size` bytes.
+    fn deserialize_sized<'de, T>(&mut self, size: u32, seed: T) -> error::Result<T::Value>
+        where T: DeserializeSeed<'de>
+    {
+        self.with_declared_size(size, |de| seed.deserialize(de))
+    }
+
+    /// Run `f` with the input limited to the following `size` bytes, which must be consumed
+    /// exactly unless trailing bytes are skipped.
+    ///
+    /// This is how the values of `WithSize` and `BoxedWithSize` are deserialized. Within the
+    /// limit, `read_declared_remaining_bytes` can take the rest of the sized value, e.g. for
+    /// `MtProtoDeserialize` implementations with an `#[mtproto(other)]` variant.
+    pub fn with_declared_size<T, F>(&mut self, size: u32, f: F) -> error::Result<T>
+        where F: FnOnce(&mut Deserializer<R>) -> error::Result<T>
+    {
+        let start = self.reader.position;
+        let end = start + u64::from(size);
+        self.require(u64::from(size));
+        let outer_limit = self.reader.limit;
+
+        if let Some(outer_limit) = outer_limit {
+            if end > outer_limit {
+                bail!(ErrorKind::SizeLimitExceeded {
+                    declared: size,
+                    offset: start,
+                    exceeded_by: end - outer_limit,
+                });
+            }
+        }
+
+        debug!("Limiting deserialization to {} bytes starting from position {}", size, start);
+        self.reader.limit = Some(end);
+        let result = f(self);
+        let limit_overrun = self.reader.limit_overrun.take();
+        self.reader.limit = outer_limit;
+
+        let value = match (result, limit_overrun) {
+            (Ok(value), _) => value,
+            (Err(_), Some(exceeded_by)) => bail!(ErrorKind::SizeLimitExceeded {
+                declared: size,
+                offset: start,
+                exceeded_by: exceeded_by,
+            }),
+            (Err(e), None) => return Err(e),
+        };
+
+        // Cannot truncate since the limit above guarantees `self.reader.position <= end`
+        #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+        let unconsumed = (end - self.reader.position) as u32;
+
+        if unconsumed > 0 {
+            if !self.skip_trailing_bytes {
+                bail!(ErrorKind::UnconsumedBytes { unconsumed: unconsumed, declared: size });
+            }
+
+            debug!("Skipping {} trailing bytes", unconsumed);
+            let skipped = io::copy(&mut (&mut self.reader).take(u64::from(unconsumed)), &mut io::sink())?;
+            if skipped < u64::from(unconsumed) {
+                bail!(ErrorKind::UnexpectedEof { offset: self.reader.position });
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Deserialize a value and return it along with the bytes it was deserialized from.
+    fn deserialize_captured<'de, T>(&mut self, seed: T) -> error::Result<(T::Value, Vec<u8>)>
+        where T: DeserializeSeed<'de>
+    {
+        let outer_captured = mem::replace(&mut self.reader.captured, Some(Vec::new()));
+        let result = seed.deserialize(&mut *self);
+        let captured = mem::replace(&mut self.reader.captured, outer_captured).unwrap_or_default();
+
+        // Nested captures must be visible to the enclosing one too
+        if let Some(ref mut outer_captured) = self.reader.captured {
+            outer_captured.extend_from_slice(&captured);
+        }
+
+        debug!("Captured {} bytes", captured.len());
+        result.map(|value| (value, captured))
     }
 }
 
 impl<'a> Deserializer<&'a [u8]> {
     /// Length of unprocessed data in the byte buffer.
     pub fn remaining_length(&self) -> usize {
-        self.reader.len()
+        self.reader.peeked.len() + self.reader.inner.len()
     }
 }
 
@@ -98,7 +637,7 @@ macro_rules! impl_deserialize_small_int {
         fn $small_deserialize<V>(self, visitor: V) -> error::Result<V::Value>
             where V: Visitor<'de>
         {
-            let value = self.reader.$big_read::<$big_endianness>()?;
+            let value = self.read_with(|r| r.$big_read::<$big_endianness>())?;
             debug!("Deserialized big int: {:#x}", value);
             let casted = safe_int_cast(value)?;
             debug!("Casted to {}: {:#x}", stringify!($small_type), casted);
@@ -113,7 +652,7 @@ macro_rules! impl_deserialize_big_int {
         fn $deserialize<V>(self, visitor: V) -> error::Result<V::Value>
             where V: Visitor<'de>
         {
-            let value = self.reader.$read::<$endianness>()?;
+            let value = self.read_with(|r| r.$read::<$endianness>())?;
             debug!("Deserialized {}: {:#x}", stringify!($type), value);
 
             visitor.$visit(value)
@@ -129,13 +668,13 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     fn deserialize_any<V>(self, _visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        bail!(DeErrorKind::UnsupportedSerdeType(DeSerdeType::Any));
+        bail!(ErrorKind::UnsupportedSerdeType { serde_type: SerdeType::Any });
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        let id_value = self.reader.read_u32::<LittleEndian>()?;
+        let id_value = self.read_with(|r| r.read_u32::<LittleEndian>())?;
 
         let value = match id_value {
             BOOL_FALSE_ID => false,
@@ -162,13 +701,28 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     impl_deserialize_big_int!(u32, deserialize_u32, read_u32::<LittleEndian>, visit_u32);
     impl_deserialize_big_int!(u64, deserialize_u64, read_u64::<LittleEndian>, visit_u64);
 
+    impl_deserialize_big_int!(i128, deserialize_i128, read_i128::<LittleEndian>, visit_i128);
+    impl_deserialize_big_int!(u128, deserialize_u128, read_u128::<LittleEndian>, visit_u128);
+
     fn deserialize_f32<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        let value = self.reader.read_f64::<LittleEndian>()?;
+        let value = self.read_with(|r| r.read_f64::<LittleEndian>())?;
         debug!("Deserialized big float: {}", value);
 
-        let casted = safe_float_cast(value)?;
+        #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+        let casted = value as f32;
+
+        let fits = match self.config.float_narrowing {
+            FloatNarrowing::Checked => casted.is_finite() || !value.is_finite(),
+            // NaN can't be compared, but it is representable as `f32` anyway
+            FloatNarrowing::Strict => f64::from(casted) == value || value.is_nan(),
+            FloatNarrowing::Lenient => true,
+        };
+
+        if !fits {
+            bail!(ErrorKind::FloatCast { value: value });
+        }
         debug!("Casted to f32: {}", casted);
 
         visitor.visit_f32(casted)
@@ -177,40 +731,73 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     fn deserialize_f64<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        let value = self.reader.read_f64::<LittleEndian>()?;
+        let value = self.read_with(|r| r.read_f64::<LittleEndian>())?;
         debug!("Deserialized f64: {}", value);
 
         visitor.visit_f64(value)
     }
 
-    fn deserialize_char<V>(self, _visitor: V) -> error::Result<V::Value>
+    fn deserialize_char<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        bail!(DeErrorKind::UnsupportedSerdeType(DeSerdeType::Char));
+        let value = match self.config.char_encoding {
+            CharEncoding::Unsupported => {
+                bail!(ErrorKind::UnsupportedSerdeType { serde_type: SerdeType::Char });
+            },
+            CharEncoding::Int => {
+                let code = self.read_with(|r| r.read_u32::<LittleEndian>())?;
+                ::std::char::from_u32(code).ok_or_else(|| -> error::Error {
+                    de::Error::invalid_value(de::Unexpected::Unsigned(u64::from(code)), &"a Unicode scalar value")
+                })?
+            },
+            CharEncoding::Utf8 => {
+                self.with_scratch(|b| {
+                    let s = str::from_utf8(b).map_err(|_| invalid_utf8(b))?;
+                    let mut chars = s.chars();
+
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => Ok(c),
+                        _ => Err(de::Error::invalid_value(de::Unexpected::Str(s), &"a single character")),
+                    }
+                })?
+            },
+        };
+
+        debug!("Deserialized char: {:?}", value);
+        visitor.visit_char(value)
     }
 
     fn deserialize_str<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        let s = self.read_string()?;
-        debug!("Deserialized str: {:?}", s);
-        visitor.visit_str(&s)
+        let lossy = self.config.lossy_strings;
+        self.with_scratch(|b| {
+            let s = decode_utf8(b, lossy)?;
+            debug!("Deserialized str: {:?}", s);
+            visitor.visit_str(&s)
+        })
     }
 
     fn deserialize_string<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        let s = self.read_string()?;
-        debug!("Deserialized string: {:?}", s);
-        visitor.visit_string(s)
+        // Visit a borrowed string so that `String::deserialize_in_place` can reuse the capacity
+        // of the existing value
+        let lossy = self.config.lossy_strings;
+        self.with_scratch(|b| {
+            let s = decode_utf8(b, lossy)?;
+            debug!("Deserialized string: {:?}", s);
+            visitor.visit_str(&s)
+        })
     }
 
     fn deserialize_bytes<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        let b = self.read_byte_buf()?;
-        debug!("Deserialized bytes: {:?}", b);
-        visitor.visit_bytes(&b)
+        self.with_scratch(|b| {
+            debug!("Deserialized bytes: {:?}", b);
+            visitor.visit_bytes(b)
+        })
     }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> error::Result<V::Value>
@@ -224,13 +811,13 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     fn deserialize_option<V>(self, _visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        bail!(DeErrorKind::UnsupportedSerdeType(DeSerdeType::Option));
+        bail!(ErrorKind::UnsupportedSerdeType { serde_type: SerdeType::Option });
     }
 
     fn deserialize_unit<V>(self, _visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        bail!(DeErrorKind::UnsupportedSerdeType(DeSerdeType::Unit));
+        bail!(ErrorKind::UnsupportedSerdeType { serde_type: SerdeType::Unit });
     }
 
     fn deserialize_unit_struct<V>(self, name: &'static str, visitor: V) -> error::Result<V::Value>
@@ -243,6 +830,52 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
+        if name == DECLARED_SIZE_NEWTYPE_NAME {
+            let size = self.read_with(|r| r.read_u32::<LittleEndian>())?;
+            debug!("Deserialized declared size {}", size);
+            // The next value will be limited to this size
+            self.pending_size = Some(size);
+
+            return visitor.visit_u32(size);
+        }
+
+        if name == RAW_NEWTYPE_NAME {
+            debug!("Deserializing value with captured bytes");
+            return visitor.visit_seq(CaptureAccess::new(self));
+        }
+
+        if name == PACKED_NEWTYPE_NAME {
+            let (len, padding) = self.get_str_info()?;
+            debug!("Deserializing a packed value from {} bytes", len);
+
+            // The length of the byte sequence is the declared size of the packed value, so that
+            // it is read with the enum variant hint and settings of this deserializer
+            let value = self.with_declared_size(safe_int_cast(len)?, |de| visitor.visit_newtype_struct(de))?;
+            self.read_padding(padding)?;
+
+            return Ok(value);
+        }
+
+        if name == REMAINING_BYTES_NEWTYPE_NAME {
+            let bytes = self.read_remaining_bytes()?;
+            debug!("Deserialized {} remaining bytes", bytes.len());
+
+            return visitor.visit_byte_buf(bytes);
+        }
+
+        if name == UNKNOWN_VALUE_NEWTYPE_NAME {
+            return match self.read_declared_remaining_bytes()? {
+                Some(bytes) => {
+                    debug!("Deserialized {} bytes of a value with unknown type id", bytes.len());
+                    visitor.visit_byte_buf(bytes)
+                },
+                None => {
+                    debug!("Cannot determine the extent of a value with unknown type id");
+                    visitor.visit_none()
+                },
+            };
+        }
+
         debug!("Deserializing newtype struct {}", name);
         visitor.visit_newtype_struct(self)
     }
@@ -250,7 +883,7 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     fn deserialize_seq<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        let len = self.reader.read_u32::<LittleEndian>()?;
+        let len = self.read_with(|r| r.read_u32::<LittleEndian>())?;
         debug!("Deserializing seq of len {}", len);
 
         visitor.visit_seq(SeqAccess::new(self, len))
@@ -266,6 +899,25 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     fn deserialize_tuple_struct<V>(self, name: &'static str, len: usize, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
+        if name == PACKED_INTS_NEWTYPE_NAME {
+            // All 32-bit words of packed integers are read at once
+            let byte_len = len.saturating_mul(4);
+
+            if byte_len <= PACKED_BYTES_STACK_BUFFER_SIZE {
+                let mut buf = [0; PACKED_BYTES_STACK_BUFFER_SIZE];
+                self.require(safe_int_cast(byte_len)?);
+                self.read_with(|r| r.read_exact(&mut buf[..byte_len]))?;
+                debug!("Deserialized {} bytes of packed integers", byte_len);
+
+                return visitor.visit_bytes(&buf[..byte_len]);
+            }
+
+            let bytes = self.read_packed_bytes(safe_int_cast(byte_len)?)?;
+            debug!("Deserialized {} bytes of packed integers", bytes.len());
+
+            return visitor.visit_byte_buf(bytes);
+        }
+
         debug!("Deserializing tuple struct {} of len {}", name, len);
         visitor.visit_seq(SeqAccess::new(self, safe_int_cast(len)?))
     }
@@ -273,7 +925,7 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     fn deserialize_map<V>(self, visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        let len = self.reader.read_u32::<LittleEndian>()?;
+        let len = self.read_with(|r| r.read_u32::<LittleEndian>())?;
         debug!("Deserializing map of len {}", len);
 
         visitor.visit_map(MapAccess::new(self, len))
@@ -306,7 +958,7 @@ impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
     fn deserialize_ignored_any<V>(self, _visitor: V) -> error::Result<V::Value>
         where V: Visitor<'de>
     {
-        bail!(DeErrorKind::UnsupportedSerdeType(DeSerdeType::IgnoredAny));
+        bail!(ErrorKind::UnsupportedSerdeType { serde_type: SerdeType::IgnoredAny });
     }
 }
 
@@ -344,7 +996,10 @@ impl<'de, 'a, R> de::SeqAccess<'de> for SeqAccess<'a, R>
         }
 
         debug!("Deserializing sequence element");
-        seed.deserialize(&mut *self.de).map(Some)
+        match self.de.pending_size.take() {
+            Some(size) => self.de.deserialize_sized(size, seed).map(Some),
+            None => seed.deserialize(&mut *self.de).map(Some),
+        }
     }
 
     fn size_hint(&self) -> Option<usize> {
@@ -402,6 +1057,78 @@ impl<'de, 'a, R> de::MapAccess<'de> for MapAccess<'a, R>
 }
 
 
+/// Yields a deserialized value and then the bytes it was deserialized from.
+#[derive(Debug)]
+struct CaptureAccess<'a, R: 'a + io::Read> {
+    de: &'a mut Deserializer<R>,
+    captured: Option<Vec<u8>>,
+    next_index: u32,
+}
+
+impl<'a, R: io::Read> CaptureAccess<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> CaptureAccess<'a, R> {
+        CaptureAccess {
+            de: de,
+            captured: None,
+            next_index: 0,
+        }
+    }
+}
+
+impl<'de, 'a, R> de::SeqAccess<'de> for CaptureAccess<'a, R>
+    where R: 'a + io::Read
+{
+    type Error = error::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> error::Result<Option<T::Value>>
+        where T: DeserializeSeed<'de>
+    {
+        let value = match self.next_index {
+            0 => {
+                let (value, captured) = self.de.deserialize_captured(seed)?;
+                self.captured = Some(captured);
+                value
+            },
+            1 => {
+                let captured = self.captured.take().unwrap_or_default();
+                seed.deserialize(CapturedBytesDeserializer(captured))?
+            },
+            _ => {
+                debug!("CaptureAccess::next_element_seed() is called when no elements is left to deserialize");
+                return Ok(None);
+            },
+        };
+
+        self.next_index += 1;
+
+        Ok(Some(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        safe_int_cast(2 - self.next_index).ok()
+    }
+}
+
+
+/// Hands captured bytes over to a visitor.
+struct CapturedBytesDeserializer(Vec<u8>);
+
+impl<'de> de::Deserializer<'de> for CapturedBytesDeserializer {
+    type Error = error::Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> error::Result<V::Value>
+        where V: Visitor<'de>
+    {
+        visitor.visit_byte_buf(self.0)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+
 #[derive(Debug)]
 struct EnumVariantAccess<'a, R: 'a + io::Read> {
     de: &'a mut Deserializer<R>,
@@ -462,6 +1189,86 @@ impl<'de, 'a, R> de::VariantAccess<'de> for EnumVariantAccess<'a, R>
 }
 
 
+/// An iterator which lazily deserializes elements of a vector.
+///
+/// Created by `Deserializer::vector_iter` and `Deserializer::boxed_vector_iter`.
+#[derive(Debug)]
+pub struct VectorIter<'a, R: 'a + io::Read, T> {
+    de: &'a mut Deserializer<R>,
+    len: u32,
+    next_index: u32,
+    failed: bool,
+    phantom: PhantomData<T>,
+}
+
+impl<'a, R, T> Iterator for VectorIter<'a, R, T>
+    where R: 'a + io::Read,
+          T: DeserializeOwned,
+{
+    type Item = error::Result<T>;
+
+    fn next(&mut self) -> Option<error::Result<T>> {
+        if self.failed || self.next_index >= self.len {
+            return None;
+        }
+
+        self.next_index += 1;
+        debug!("Deserializing vector element {} of {}", self.next_index, self.len);
+
+        let result = T::deserialize(&mut *self.de);
+        if result.is_err() {
+            self.failed = true;
+        }
+
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.failed {
+            0
+        } else {
+            // `u32` always fits into `usize` on supported platforms
+            safe_int_cast(self.len - self.next_index).unwrap_or(usize::max_value())
+        };
+
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a, R, T> ExactSizeIterator for VectorIter<'a, R, T>
+    where R: 'a + io::Read,
+          T: DeserializeOwned,
+{}
+
+
+/// Decode a string, replacing invalid UTF-8 sequences if `lossy` is set or failing otherwise.
+fn decode_utf8<'a>(bytes: &'a [u8], lossy: bool) -> error::Result<Cow<'a, str>> {
+    if lossy {
+        Ok(String::from_utf8_lossy(bytes))
+    } else {
+        str::from_utf8(bytes).map(Cow::Borrowed).map_err(|_| invalid_utf8(bytes))
+    }
+}
+
+/// Report invalid UTF-8 in a byte sequence which was expected to be a string.
+fn invalid_utf8(bytes: &[u8]) -> error::Error {
+    match String::from_utf8(bytes.to_vec()) {
+        Err(e) => e.into(),
+        Ok(_) => unreachable!("bytes are known to be invalid UTF-8"),
+    }
+}
+
+
+/// Return the type id of a boxed value serialized in the byte buffer.
+pub fn peek_type_id(bytes: &[u8]) -> error::Result<u32> {
+    Deserializer::new(bytes, None).peek_type_id()
+}
+
+/// Return the length of a byte sequence or string serialized in the byte buffer.
+pub fn peek_bytes_len(bytes: &[u8]) -> error::Result<usize> {
+    Deserializer::new(bytes, None).peek_bytes_len()
+}
+
 /// Deserialize an instance of type `T` from bytes of binary MTProto.
 pub fn from_bytes<'a, T>(bytes: &'a [u8], enum_variant_id: Option<&'static str>) -> error::Result<T>
     where T: Deserialize<'a>
@@ -481,7 +1288,25 @@ pub fn from_bytes_reuse<'a, T>(bytes: &'a [u8],
     let mut de = Deserializer::new(bytes, enum_variant_id);
     let value: T = Deserialize::deserialize(&mut de)?;
 
-    Ok((value, de.reader))
+    Ok((value, de.reader.inner))
+}
+
+/// Deserialize bytes of binary MTProto into an existing instance of type `T`, overwriting it.
+///
+/// Unlike `from_bytes`, this reuses allocations of `place` where possible, e.g. capacities of
+/// `Vec`s and `String`s it contains. Types generated by `#[derive(Deserialize)]` support this
+/// only if `serde_derive`'s `deserialize_in_place` feature is enabled, otherwise they are simply
+/// replaced.
+///
+/// If deserialization fails, `place` is left in an unspecified, but valid state.
+pub fn from_bytes_into<'a, T>(place: &mut T,
+                              bytes: &'a [u8],
+                              enum_variant_id: Option<&'static str>)
+                             -> error::Result<()>
+    where T: Deserialize<'a>
+{
+    let mut de = Deserializer::new(bytes, enum_variant_id);
+    T::deserialize_in_place(&mut de, place)
 }
 
 /// Deserialize an instance of type `T` from an IO stream of binary MTProto.
@@ -506,5 +1331,5 @@ pub fn from_reader_reuse<R, T>(reader: R,
     let mut de = Deserializer::new(reader, enum_variant_id);
     let value: T = Deserialize::deserialize(&mut de)?;
 
-    Ok((value, de.reader))
+    Ok((value, de.reader.inner))
 }
@@ -0,0 +1,220 @@
+//! Incremental decoding of MTProto values from chunks of input.
+//!
+//! `Decoder` is meant for event loops over non-blocking sockets where input arrives piece by
+//! piece and a value can't be deserialized until all of its bytes are available.
+
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+
+use config::Config;
+use de::Deserializer;
+use error;
+
+
+/// The result of feeding input to a `Decoder`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DecodeStatus<T> {
+    /// More input is needed to decode a value.
+    Incomplete {
+        /// The minimum number of additional bytes needed, if it is known from length prefixes
+        /// or declared sizes read so far.
+        needed: Option<usize>,
+    },
+    /// A value is decoded along with the number of bytes consumed from the last fed chunk, or
+    /// from the buffered input for `Decoder::finish`.
+    ///
+    /// The rest of the chunk is not consumed and should be fed again to decode the next value.
+    Complete(T, usize),
+}
+
+/// A push-based decoder which accumulates chunks of input until a value of type `T` can be
+/// deserialized.
+///
+/// Input is buffered only while a value is incomplete. When the number of missing bytes is
+/// known, the following chunks are only buffered until there are enough of them, so that a
+/// large value arriving in many small chunks isn't re-parsed on each one.
+///
+/// Values which read up to the end of input, like `UnsizedByteBufSeed::to_end` payloads outside
+/// of a declared size, can't be told apart from their truncated prefixes. Once such a value is
+/// decoded, `feed` only buffers the following chunks and returns `DecodeStatus::Incomplete`
+/// until `finish` is called at the end of the stream.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate serde_mtproto;
+/// use serde_mtproto::decoder::{DecodeStatus, Decoder};
+///
+/// # fn run() -> serde_mtproto::Result<()> {
+/// let bytes = serde_mtproto::to_bytes(&"a string which arrives in pieces")?;
+/// let mut decoder = Decoder::<String>::new(None);
+///
+/// assert_eq!(decoder.feed(&bytes[..10])?, DecodeStatus::Incomplete { needed: Some(26) });
+/// assert_eq!(decoder.feed(&bytes[10..])?,
+///            DecodeStatus::Complete("a string which arrives in pieces".to_owned(), 26));
+/// #     Ok(())
+/// # }
+/// # fn main() { run().unwrap(); }
+/// ```
+#[derive(Debug)]
+pub struct Decoder<T> {
+    buf: Vec<u8>,
+    required_len: Option<usize>,
+    reads_to_end: bool,
+    enum_variant_id: Option<&'static str>,
+    config: Config,
+    phantom: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Decoder<T> {
+    /// Create a decoder with an enum variant hint.
+    pub fn new(enum_variant_id: Option<&'static str>) -> Decoder<T> {
+        Decoder::with_config(enum_variant_id, Config::default())
+    }
+
+    /// Create a decoder with an enum variant hint which follows the given encoding policies.
+    ///
+    /// Trailing bytes are never rejected since they belong to the following values.
+    pub fn with_config(enum_variant_id: Option<&'static str>, config: Config) -> Decoder<T> {
+        Decoder {
+            buf: Vec::new(),
+            required_len: None,
+            reads_to_end: false,
+            enum_variant_id: enum_variant_id,
+            config: config,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Feed a chunk of input and try to decode a value.
+    ///
+    /// If decoding fails for reasons other than lack of input, the buffered input is discarded
+    /// and the error is returned.
+    pub fn feed(&mut self, bytes: &[u8]) -> error::Result<DecodeStatus<T>> {
+        let buffered_len = self.buf.len();
+
+        if buffered_len == 0 {
+            // Nothing is buffered, so try to avoid copying the input
+            let status = self.decode(bytes, false)?;
+            if let DecodeStatus::Incomplete { .. } = status {
+                self.buf.extend_from_slice(bytes);
+            }
+
+            return Ok(status);
+        }
+
+        self.buf.extend_from_slice(bytes);
+
+        if self.reads_to_end {
+            return Ok(DecodeStatus::Incomplete { needed: None });
+        }
+
+        if let Some(required_len) = self.required_len {
+            if self.buf.len() < required_len {
+                return Ok(DecodeStatus::Incomplete { needed: Some(required_len - self.buf.len()) });
+            }
+        }
+
+        let buf = ::std::mem::replace(&mut self.buf, Vec::new());
+
+        match self.decode(&buf, false)? {
+            DecodeStatus::Complete(value, end) if end < buffered_len => {
+                // The value ends within the bytes left buffered by `finish`, so none of the chunk
+                // is consumed and the rest of the buffered bytes precede the next value
+                self.buf = buf[end..buffered_len].to_vec();
+                Ok(DecodeStatus::Complete(value, 0))
+            },
+            DecodeStatus::Complete(value, end) => Ok(DecodeStatus::Complete(value, end - buffered_len)),
+            status => {
+                self.buf = buf;
+                Ok(status)
+            },
+        }
+    }
+
+    /// Decode a value from the buffered input at the end of the stream.
+    ///
+    /// This completes values which read up to the end of input. Returns
+    /// `DecodeStatus::Incomplete` if the buffered input is still not enough to decode a value.
+    ///
+    /// The number of consumed bytes of a decoded value counts from the start of the buffered
+    /// input. The bytes after the value stay buffered, so that the following values are decoded
+    /// by calling `finish` again.
+    pub fn finish(&mut self) -> error::Result<DecodeStatus<T>> {
+        let buf = ::std::mem::replace(&mut self.buf, Vec::new());
+        self.reads_to_end = false;
+
+        match self.decode(&buf, true) {
+            Ok(DecodeStatus::Complete(value, end)) => {
+                self.buf = buf[end..].to_vec();
+                Ok(DecodeStatus::Complete(value, end))
+            },
+            Ok(status) => {
+                self.buf = buf;
+                Ok(status)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Number of buffered bytes of an incomplete value or left by `finish`.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Discard the buffered input.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.required_len = None;
+        self.reads_to_end = false;
+    }
+
+    /// Decode a value from `input`, returning the position where it ends in `input` if it is
+    /// complete.
+    ///
+    /// Unless `input` is the end of the stream, values which read up to the end of `input` are
+    /// incomplete.
+    fn decode(&mut self, input: &[u8], is_end: bool) -> error::Result<DecodeStatus<T>> {
+        let mut de = Deserializer::with_config(input, self.enum_variant_id, self.config);
+
+        match T::deserialize(&mut de) {
+            Ok(_) if de.has_read_to_end() && !is_end => {
+                debug!("Value reads up to the end of input, waiting for the end of the stream");
+                self.required_len = None;
+                self.reads_to_end = true;
+
+                Ok(DecodeStatus::Incomplete { needed: None })
+            },
+            Ok(value) => {
+                self.required_len = None;
+                // Cannot truncate since the position is within `input`
+                #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+                let end = de.position() as usize;
+
+                Ok(DecodeStatus::Complete(value, end))
+            },
+            Err(ref e) if e.is_eof() => {
+                let required_position = de.required_position();
+                let needed = if required_position > input.len() as u64 {
+                    // Cannot truncate since the required position is a sum of input lengths
+                    // and a declared `u32` size
+                    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+                    let needed = (required_position - input.len() as u64) as usize;
+                    self.required_len = Some(input.len() + needed);
+                    Some(needed)
+                } else {
+                    self.required_len = None;
+                    None
+                };
+
+                debug!("Incomplete value after {} bytes, needed: {:?}", input.len(), needed);
+                Ok(DecodeStatus::Incomplete { needed: needed })
+            },
+            Err(e) => {
+                self.required_len = None;
+                Err(e)
+            },
+        }
+    }
+}
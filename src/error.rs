@@ -1,195 +1,369 @@
 //! When serializing or deserializing MTProto goes wrong.
+//!
+//! All errors are represented by a single `Error` type which is `Send + Sync + 'static`, so it
+//! can freely cross thread and async task boundaries and be wrapped by other error handling
+//! libraries. The cause of an error is described by a flat `ErrorKind` enum with structured
+//! fields.
+//!
+//! Errors can be roughly classified using `Error::is_io`, `Error::is_eof` and `Error::is_data`.
+//! Errors outside of these categories are caused by values and types that can't be represented
+//! in MTProto at all (e.g. unsupported Serde data types).
 
+use std::cell::{Cell, RefCell};
+use std::error;
 use std::fmt;
+use std::io;
+use std::result;
+use std::string::FromUtf8Error;
 
+#[cfg(feature = "backtrace")]
+use backtrace::Backtrace;
 use serde::{ser, de};
 
 
-error_chain! {
-    foreign_links {
-        Io(::std::io::Error) #[doc = "Wraps an `io::Error`"];
-        FromUtf8(::std::string::FromUtf8Error) #[doc = "Wraps a `FromUtf8Error`"];
-    }
+/// A specialized `Result` type for `serde_mtproto` operations.
+pub type Result<T> = result::Result<T, Error>;
 
-    errors {
-        /// An error during serialization.
-        Ser(kind: SerErrorKind) {
-            description("serialization error in serde_mtproto")
-            display("serialization error in serde_mtproto: {}", kind)
-        }
 
-        /// An error during deserialization.
-        De(kind: DeErrorKind) {
-            description("deserialization error in serde_mtproto")
-            display("deserialization error in serde_mtproto: {}", kind)
-        }
+/// An error which can occur while serializing or deserializing MTProto.
+pub struct Error {
+    kind: ErrorKind,
+    #[cfg(feature = "backtrace")]
+    backtrace: Backtrace,
+}
 
-        /// Error while casting an integer.
-        IntegerCast(num: u64) {
-            description("error while casting an integer")
-            display("error while casting an integer: {}", num)
-        }
+impl Error {
+    /// Return the kind of this error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 
-        /// Error while casting a floating-point number.
-        FloatCast(num: f64) {
-            description("error while casting a floating-point number")
-            display("error while casting a floating-point number: {}", num)
-        }
+    /// Consume the error and return its kind.
+    pub fn into_kind(self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Return the backtrace captured when this error was created.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
 
-        /// A string that cannot be serialized because it exceeds a certain length limit.
-        StringTooLong(len: usize) {
-            description("string is too long to serialize")
-            display("string of length {} is too long to serialize", len)
+    /// Whether this error was caused by an I/O failure other than reaching the end of input.
+    pub fn is_io(&self) -> bool {
+        match self.kind {
+            ErrorKind::Io(ref e) => e.kind() != io::ErrorKind::UnexpectedEof,
+            _ => false,
         }
+    }
 
-        /// A byte sequence that cannot be serialized because it exceeds a certain length limit.
-        ByteSeqTooLong(len: usize) {
-            description("byte sequence is too long to serialize")
-            display("byte sequence of length {} is too long to serialize", len)
+    /// Whether this error was caused by the input ending prematurely.
+    ///
+    /// This usually means that more data may fix the error.
+    pub fn is_eof(&self) -> bool {
+        match self.kind {
+            ErrorKind::UnexpectedEof { .. } => true,
+            ErrorKind::Io(ref e) => e.kind() == io::ErrorKind::UnexpectedEof,
+            _ => false,
         }
+    }
 
-        /// A sequence that cannot be serialized because it exceeds a certain length limit.
-        SeqTooLong(len: usize) {
-            description("sequence is too long to serialize")
-            display("sequence of length {} is too long to serialize", len)
+    /// Whether this error was caused by data that is not a valid MTProto representation of the
+    /// requested type or by a value that doesn't fit into the MTProto representation.
+    pub fn is_data(&self) -> bool {
+        match self.kind {
+            ErrorKind::InvalidUtf8(_) |
+            ErrorKind::IntegerCast { .. } |
+            ErrorKind::FloatCast { .. } |
+            ErrorKind::StringTooLong { .. } |
+            ErrorKind::ByteSeqTooLong { .. } |
+            ErrorKind::SeqTooLong { .. } |
+            ErrorKind::NotEnoughElements { .. } |
+            ErrorKind::InvalidMapKey { .. } |
+            ErrorKind::InvalidTypeId { .. } |
+            ErrorKind::TypeIdMismatch { .. } |
+            ErrorKind::SizeMismatch { .. } |
+            ErrorKind::SizeLimitExceeded { .. } |
+            ErrorKind::UnconsumedBytes { .. } |
+            ErrorKind::TrailingBytes { .. } |
+            ErrorKind::NonZeroPadding { .. } |
+            ErrorKind::DeCustom(_) => true,
+            _ => false,
         }
     }
 }
 
-
-/// Serialization error kinds.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub enum SerErrorKind {
-    /// A convenient variant for String.
-    Msg(String),
-    /// Excess elements found, stores the needed count.
-    ExcessElements(u32),
-    /// Cannot serialize maps with unknown length.
-    MapsWithUnknownLengthUnsupported,
-    /// Not enough elements, stores the actual and needed count.
-    NotEnoughElements(u32, u32),
-    /// Cannot serialize sequences with unknown length.
-    SeqsWithUnknownLengthUnsupported,
-    /// A string that cannot be serialized because it exceeds a certain length limit.
-    StringTooLong(usize),
-    /// This `serde` data format doesn't support several types in the Serde data model.
-    UnsupportedSerdeType(SerSerdeType),
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error {
+            kind: kind,
+            #[cfg(feature = "backtrace")]
+            backtrace: Backtrace::new(),
+        }
+    }
 }
 
-impl fmt::Display for SerErrorKind {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            SerErrorKind::Msg(ref string) => {
-                write!(f, "custom string: {}", string)
-            },
-            SerErrorKind::ExcessElements(len) => {
-                write!(f, "excess elements, need no more than {}", len)
-            },
-            SerErrorKind::MapsWithUnknownLengthUnsupported => {
-                write!(f, "maps with ahead-of-time unknown length are not supported")
-            },
-            SerErrorKind::NotEnoughElements(unexpected_len, expected_len) => {
-                write!(f, "not enough elements: have {}, need {}", unexpected_len, expected_len)
-            },
-            SerErrorKind::SeqsWithUnknownLengthUnsupported => {
-                write!(f, "seqs with ahead-of-time unknown length are not supported")
-            },
-            SerErrorKind::StringTooLong(len) => {
-                write!(f, "string of length {} is too long to serialize", len)
-            },
-            SerErrorKind::UnsupportedSerdeType(ref type_) => {
-                write!(f, "{} type is not supported for serialization", type_)
-            },
-        }
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        ErrorKind::Io(err).into()
     }
 }
 
-/// Serde serialization data types that are not supported by `serde_mtproto`.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub enum SerSerdeType {
-    /// Single character type.
-    Char,
-    /// None value of Option type.
-    None,
-    /// Some value of Option type.
-    Some,
-    /// Unit `()` type.
-    Unit,
+impl From<FromUtf8Error> for Error {
+    fn from(err: FromUtf8Error) -> Error {
+        ErrorKind::InvalidUtf8(err).into()
+    }
 }
 
-impl fmt::Display for SerSerdeType {
+impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let repr = match *self {
-            SerSerdeType::Char => "char",
-            SerSerdeType::None => "none",
-            SerSerdeType::Some => "some",
-            SerSerdeType::Unit => "unit",
-        };
+        let mut debug_struct = f.debug_struct("Error");
+        debug_struct.field("kind", &self.kind);
+        #[cfg(feature = "backtrace")]
+        debug_struct.field("backtrace", &self.backtrace);
+        debug_struct.finish()
+    }
+}
 
-        f.write_str(repr)
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.kind, f)
     }
 }
 
-impl From<SerErrorKind> for Error {
-    fn from(kind: SerErrorKind) -> Error {
-        ErrorKind::Ser(kind).into()
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "serde_mtproto error"
+    }
+
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self.kind {
+            ErrorKind::Io(ref e) => Some(e),
+            ErrorKind::InvalidUtf8(ref e) => Some(e),
+            _ => None,
+        }
     }
 }
 
 
-/// Deserialization error kinds.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub enum DeErrorKind {
-    /// A convenient variant for String.
-    Msg(String),
-    /// This `serde` data format doesn't support several types in the Serde data model.
-    UnsupportedSerdeType(DeSerdeType),
-    /// Not enough elements, stores the already deserialized and expected count.
-    NotEnoughElements(u32, u32),
+/// The cause of an `Error`.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// An I/O error.
+    Io(io::Error),
+    /// The input ended prematurely, stores the number of bytes read before that.
+    UnexpectedEof {
+        /// The number of bytes successfully read.
+        offset: u64,
+    },
+    /// A deserialized string is not valid UTF-8.
+    InvalidUtf8(FromUtf8Error),
+    /// Error while casting an integer.
+    IntegerCast {
+        /// The value (upcasted to `u64`) which failed to cast.
+        value: u64,
+    },
+    /// Error while casting a floating-point number.
+    FloatCast {
+        /// The value (upcasted to `f64`) which failed to cast.
+        value: f64,
+    },
+    /// A string that cannot be serialized because it exceeds a certain length limit.
+    StringTooLong {
+        /// The length of the string.
+        len: usize,
+    },
+    /// A byte sequence that cannot be serialized because it exceeds a certain length limit.
+    ByteSeqTooLong {
+        /// The length of the byte sequence.
+        len: usize,
+    },
+    /// A sequence that cannot be serialized because it exceeds a certain length limit.
+    SeqTooLong {
+        /// The length of the sequence.
+        len: usize,
+    },
+    /// This Serde data format doesn't support several types in the Serde data model.
+    UnsupportedSerdeType {
+        /// The unsupported type.
+        serde_type: SerdeType,
+    },
+    /// Cannot serialize sequences with unknown length.
+    SeqsWithUnknownLengthUnsupported,
+    /// Cannot serialize maps with unknown length.
+    MapsWithUnknownLengthUnsupported,
+    /// More elements are serialized than declared.
+    ExcessElements {
+        /// The declared count of elements.
+        expected: u32,
+    },
+    /// Fewer elements are serialized or deserialized than needed.
+    NotEnoughElements {
+        /// The count of already processed elements.
+        found: u32,
+        /// The needed count of elements.
+        expected: u32,
+    },
     /// A wrong map key found while deserializing.
-    InvalidMapKey(String, &'static str),
+    InvalidMapKey {
+        /// The deserialized key.
+        found: String,
+        /// The expected key.
+        expected: &'static str,
+    },
     /// A wrong type id found while deserializing.
-    InvalidTypeId(u32, &'static [u32]),
+    InvalidTypeId {
+        /// The deserialized type id.
+        found: u32,
+        /// All type ids valid for the type being deserialized.
+        expected: &'static [u32],
+    },
     /// The deserialized type id and the one known from value aren't the same.
-    TypeIdMismatch(u32, u32),
+    TypeIdMismatch {
+        /// The deserialized type id.
+        found: u32,
+        /// The type id of the deserialized value.
+        expected: u32,
+        /// The enum variant of the deserialized type id, if known.
+        found_variant: Option<&'static str>,
+        /// The enum variant of the deserialized value, if known.
+        expected_variant: Option<&'static str>,
+    },
     /// The deserialized size and the predicted one aren't the same.
-    SizeMismatch(u32, u32),
+    SizeMismatch {
+        /// The deserialized size.
+        found: u32,
+        /// The size hint of the deserialized value.
+        expected: u32,
+    },
+    /// A value attempted to read past its declared size.
+    SizeLimitExceeded {
+        /// The declared size.
+        declared: u32,
+        /// The offset at which the value with the declared size starts.
+        offset: u64,
+        /// How many bytes past the declared size the value attempted to read.
+        exceeded_by: u64,
+    },
+    /// A value didn't consume all bytes of its declared size.
+    UnconsumedBytes {
+        /// The count of unconsumed bytes.
+        unconsumed: u32,
+        /// The declared size.
+        declared: u32,
+    },
+    /// The input didn't end after the deserialized value.
+    TrailingBytes {
+        /// The number of bytes read before the first trailing byte.
+        offset: u64,
+    },
+    /// A padding byte following a string or a byte sequence is not zero.
+    NonZeroPadding {
+        /// The number of bytes read before the padding.
+        offset: u64,
+    },
+    /// A custom error message produced by a `Serialize` implementation.
+    SerCustom(String),
+    /// A custom error message produced by a `Deserialize` implementation.
+    DeCustom(String),
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
-impl fmt::Display for DeErrorKind {
+impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            DeErrorKind::Msg(ref string) => {
-                write!(f, "custom string: {}", string)
+            ErrorKind::Io(ref e) => {
+                write!(f, "I/O error: {}", e)
+            },
+            ErrorKind::UnexpectedEof { offset } => {
+                write!(f, "unexpected end of input after {} bytes", offset)
+            },
+            ErrorKind::InvalidUtf8(ref e) => {
+                write!(f, "invalid UTF-8 string: {}", e)
+            },
+            ErrorKind::IntegerCast { value } => {
+                write!(f, "error while casting an integer: {}", value)
+            },
+            ErrorKind::FloatCast { value } => {
+                write!(f, "error while casting a floating-point number: {}", value)
+            },
+            ErrorKind::StringTooLong { len } => {
+                write!(f, "string of length {} is too long to serialize", len)
+            },
+            ErrorKind::ByteSeqTooLong { len } => {
+                write!(f, "byte sequence of length {} is too long to serialize", len)
             },
-            DeErrorKind::UnsupportedSerdeType(ref type_) => {
-                write!(f, "{} type is not supported for deserialization", type_)
+            ErrorKind::SeqTooLong { len } => {
+                write!(f, "sequence of length {} is too long to serialize", len)
+            },
+            ErrorKind::UnsupportedSerdeType { ref serde_type } => {
+                write!(f, "{} type is not supported", serde_type)
+            },
+            ErrorKind::SeqsWithUnknownLengthUnsupported => {
+                write!(f, "seqs with ahead-of-time unknown length are not supported")
+            },
+            ErrorKind::MapsWithUnknownLengthUnsupported => {
+                write!(f, "maps with ahead-of-time unknown length are not supported")
+            },
+            ErrorKind::ExcessElements { expected } => {
+                write!(f, "excess elements, need no more than {}", expected)
+            },
+            ErrorKind::NotEnoughElements { found, expected } => {
+                write!(f, "not enough elements: have {}, need {}", found, expected)
+            },
+            ErrorKind::InvalidMapKey { ref found, expected } => {
+                write!(f, "invalid map key {:?}, expected {:?}", found, expected)
+            },
+            ErrorKind::InvalidTypeId { found, expected } => {
+                write!(f, "invalid type id {:#x}, expected one of {:#x?}", found, expected)
+            },
+            ErrorKind::TypeIdMismatch { found, expected, found_variant, expected_variant } => {
+                write!(f, "type id mismatch: deserialized {:#x}", found)?;
+                if let Some(variant) = found_variant {
+                    write!(f, " (`{}`)", variant)?;
+                }
+
+                write!(f, ", but {:#x}", expected)?;
+                if let Some(variant) = expected_variant {
+                    write!(f, " (`{}`)", variant)?;
+                }
+
+                write!(f, " found from value")
             },
-            DeErrorKind::NotEnoughElements(deserialized_count, expected_count) => {
-                write!(f, "not enough elements: have {}, need {}", deserialized_count, expected_count)
+            ErrorKind::SizeMismatch { found, expected } => {
+                write!(f, "size mismatch: deserialized {}, predicted {}", found, expected)
             },
-            DeErrorKind::InvalidMapKey(ref found_key, expected_key) => {
-                write!(f, "invalid map key {:?}, expected {:?}", found_key, expected_key)
+            ErrorKind::SizeLimitExceeded { declared, offset, exceeded_by } => {
+                write!(f, "attempted to read {} bytes past the declared size of {} bytes of the value at offset {}",
+                    exceeded_by, declared, offset)
             },
-            DeErrorKind::InvalidTypeId(found_type_id, valid_type_ids) => {
-                write!(f, "invalid type id {}, expected {:?}", found_type_id, valid_type_ids)
+            ErrorKind::UnconsumedBytes { unconsumed, declared } => {
+                write!(f, "{} bytes left unconsumed out of the declared size of {} bytes",
+                    unconsumed, declared)
             },
-            DeErrorKind::TypeIdMismatch(deserialized_type_id, static_type_id) => {
-                write!(f, "type id mismatch: deserialized {}, but {} found from value",
-                    deserialized_type_id, static_type_id)
+            ErrorKind::TrailingBytes { offset } => {
+                write!(f, "trailing bytes after the deserialized value at offset {}", offset)
             },
-            DeErrorKind::SizeMismatch(deserialized_size, static_size_hint) => {
-                write!(f, "size mismatch: deserialized {}, predicted {}",
-                    deserialized_size, static_size_hint)
+            ErrorKind::NonZeroPadding { offset } => {
+                write!(f, "non-zero padding bytes at offset {}", offset)
             },
+            ErrorKind::SerCustom(ref msg) => {
+                write!(f, "serialization error: {}", msg)
+            },
+            ErrorKind::DeCustom(ref msg) => {
+                write!(f, "deserialization error: {}", msg)
+            },
+            ErrorKind::__Nonexhaustive => unreachable!(),
         }
     }
 }
 
-/// Serde deserialization data types that are not supported by `serde_mtproto`.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub enum DeSerdeType {
-    /// `serde_mtproto` doesn't support `*_any` hint.
+
+/// Serde data types that are not supported by `serde_mtproto`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum SerdeType {
+    /// `serde_mtproto` doesn't support `deserialize_any` hint.
     Any,
     /// Single character type.
     Char,
@@ -197,39 +371,88 @@ pub enum DeSerdeType {
     Option,
     /// Unit `()` type.
     Unit,
-    /// `serde_mtproto` doesn't support `*_ignored_any` hint.
+    /// `serde_mtproto` doesn't support `deserialize_ignored_any` hint.
     IgnoredAny,
 }
 
-impl fmt::Display for DeSerdeType {
+impl fmt::Display for SerdeType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let repr = match *self {
-            DeSerdeType::Any => "any",
-            DeSerdeType::Char => "char",
-            DeSerdeType::Option => "option",
-            DeSerdeType::Unit => "unit",
-            DeSerdeType::IgnoredAny => "ignored_any",
+            SerdeType::Any => "any",
+            SerdeType::Char => "char",
+            SerdeType::Option => "option",
+            SerdeType::Unit => "unit",
+            SerdeType::IgnoredAny => "ignored_any",
         };
 
         f.write_str(repr)
     }
 }
 
-impl From<DeErrorKind> for Error {
-    fn from(kind: DeErrorKind) -> Error {
-        ErrorKind::De(kind).into()
-    }
-}
-
 
 impl ser::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Error {
-        SerErrorKind::Msg(msg.to_string()).into()
+        ErrorKind::SerCustom(msg.to_string()).into()
     }
 }
 
 impl de::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Error {
-        DeErrorKind::Msg(msg.to_string()).into()
+        // Only a `PassedThrough` formatted right here hands its error over, see `pass_through`
+        let outer = RECEIVER.with(|receiver| receiver.replace(Some(None)));
+        let msg = msg.to_string();
+        let received = RECEIVER.with(|receiver| receiver.replace(outer));
+
+        match received {
+            Some(Some(err)) => err,
+            _ => ErrorKind::DeCustom(msg).into(),
+        }
     }
 }
+
+
+thread_local! {
+    // `Some` only while `de::Error::custom` of `Error` formats its message, so that nested calls
+    // and other error types never see an error handed over to an enclosing call
+    static RECEIVER: RefCell<Option<Option<Error>>> = RefCell::new(None);
+}
+
+/// The message of an error converted by `pass_through` which carries the error itself.
+struct PassedThrough {
+    err: Cell<Option<Error>>,
+    msg: String,
+}
+
+impl fmt::Display for PassedThrough {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(err) = self.err.take() {
+            let rejected = RECEIVER.with(|receiver| match *receiver.borrow_mut() {
+                Some(ref mut slot @ None) => {
+                    *slot = Some(err);
+                    None
+                },
+                _ => Some(err),
+            });
+
+            self.err.set(rejected);
+        }
+
+        f.write_str(&self.msg)
+    }
+}
+
+/// Convert an `Error` into a generic deserializer error.
+///
+/// If the generic error turns out to be our `Error` (which is the case when deserializing with
+/// `serde_mtproto`), the original structured error is recovered instead of being flattened into
+/// a string. Generic code can't tell the error types apart on stable Rust, so the error travels
+/// inside of the message passed to `de::Error::custom`: formatting it from `de::Error::custom`
+/// of `Error` hands the error over, while other error types just get the message.
+pub(crate) fn pass_through<E: de::Error>(err: Error) -> E {
+    let msg = err.to_string();
+
+    E::custom(PassedThrough {
+        err: Cell::new(Some(err)),
+        msg: msg,
+    })
+}
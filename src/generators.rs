@@ -0,0 +1,54 @@
+//! Generators for property-based testing of MTProto types which are defined in other crates and
+//! thus can't implement `arbitrary` and `proptest` traits themselves.
+//!
+//! Types defined in this crate (wrappers and helpers, including `IntVector` and `PackedInts` for
+//! vectors and `int128`/`int256` values) implement these traits directly when the corresponding
+//! feature is enabled, while `Vec<T>` and the built-in `i128` and `u128` are covered by
+//! the upstream implementations.
+
+#[cfg(feature = "proptest")]
+use proptest::arbitrary::{any, StrategyFor};
+#[cfg(feature = "proptest")]
+use proptest::strategy::{Map, Strategy};
+
+use extprim::i128::i128;
+use extprim::u128::u128;
+
+
+/// Generate an arbitrary 128-bit signed integer.
+#[cfg(feature = "arbitrary")]
+pub fn arbitrary_i128(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<i128> {
+    let hi = ::arbitrary::Arbitrary::arbitrary(u)?;
+    let lo = ::arbitrary::Arbitrary::arbitrary(u)?;
+
+    Ok(i128::from_parts(hi, lo))
+}
+
+/// Generate an arbitrary 128-bit unsigned integer.
+#[cfg(feature = "arbitrary")]
+pub fn arbitrary_u128(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<u128> {
+    let hi = ::arbitrary::Arbitrary::arbitrary(u)?;
+    let lo = ::arbitrary::Arbitrary::arbitrary(u)?;
+
+    Ok(u128::from_parts(hi, lo))
+}
+
+/// A strategy producing 128-bit signed integers.
+#[cfg(feature = "proptest")]
+pub fn i128_strategy() -> Map<(StrategyFor<i64>, StrategyFor<u64>), fn((i64, u64)) -> i128> {
+    fn from_parts((hi, lo): (i64, u64)) -> i128 {
+        i128::from_parts(hi, lo)
+    }
+
+    (any::<i64>(), any::<u64>()).prop_map(from_parts as fn((i64, u64)) -> i128)
+}
+
+/// A strategy producing 128-bit unsigned integers.
+#[cfg(feature = "proptest")]
+pub fn u128_strategy() -> Map<(StrategyFor<u64>, StrategyFor<u64>), fn((u64, u64)) -> u128> {
+    fn from_parts((hi, lo): (u64, u64)) -> u128 {
+        u128::from_parts(hi, lo)
+    }
+
+    (any::<u64>(), any::<u64>()).prop_map(from_parts as fn((u64, u64)) -> u128)
+}
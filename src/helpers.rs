@@ -1,30 +1,92 @@
 //! Helper types for assisting in some [de]serialization scenarios.
 
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::str;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use serde::de::{self, Deserializer, DeserializeSeed, Error as DeError, Visitor};
-use serde::ser::{Serialize, Serializer, SerializeTuple};
+use byteorder::{ByteOrder, LittleEndian};
+use serde::de::{self, Deserialize, Deserializer, DeserializeSeed, Visitor};
+use serde::ser::{Error as SerError, Serialize, Serializer, SerializeSeq, SerializeTuple};
+use serde_bytes::Bytes;
 
-use error;
-use sized::MtProtoSized;
-use utils::safe_int_cast;
+use error::{self, ErrorKind, pass_through};
+use identifiable::{Identifiable, STRING_ID, TypeIdVariant, VECTOR_ID};
+use sized::{INT_SIZE, LONG_SIZE, MtProtoSized, size_hint_from_byte_seq_len};
+use utils::{PACKED_INTS_NEWTYPE_NAME, REMAINING_BYTES_NEWTYPE_NAME, STREAMED_BYTES_NEWTYPE_NAME, check_seq_len,
+            safe_int_cast};
+
+
+/// Alignment of an unsized byte sequence.
+///
+/// The serialized byte sequence is padded with zeros so that its length is divisible by the
+/// alignment.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Alignment {
+    /// Pad to 4 bytes, the alignment of all MTProto values.
+    Four,
+    /// Pad to 16 bytes, the alignment of encrypted MTProto messages.
+    Sixteen,
+}
+
+impl Alignment {
+    /// Alignment in bytes.
+    pub fn bytes(self) -> usize {
+        match self {
+            Alignment::Four => 4,
+            Alignment::Sixteen => 16,
+        }
+    }
+
+    /// Length of a byte sequence of length `len` after padding.
+    pub fn padded_len(self, len: usize) -> usize {
+        let alignment = self.bytes();
+
+        len + (alignment - len % alignment) % alignment
+    }
+}
+
+impl Default for Alignment {
+    fn default() -> Alignment {
+        Alignment::Sixteen
+    }
+}
 
 
 /// A byte buffer which doesn't write its length when serialized.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct UnsizedByteBuf {
     inner: Vec<u8>,
+    alignment: Alignment,
 }
 
 impl UnsizedByteBuf {
-    /// Wrap a byte buffer.
+    /// Wrap a byte buffer which is padded to 16 bytes when serialized.
     pub fn new(inner: Vec<u8>) -> UnsizedByteBuf {
+        UnsizedByteBuf::with_alignment(inner, Alignment::default())
+    }
+
+    /// Wrap a byte buffer which is padded to `alignment` when serialized.
+    pub fn with_alignment(inner: Vec<u8>, alignment: Alignment) -> UnsizedByteBuf {
         UnsizedByteBuf {
             inner: inner,
+            alignment: alignment,
         }
     }
 
+    /// Alignment of the serialized byte buffer.
+    pub fn alignment(&self) -> Alignment {
+        self.alignment
+    }
+
+    /// View the `UnsizedByteBuf` as the underlying byte buffer.
+    pub fn as_inner(&self) -> &[u8] {
+        &self.inner
+    }
+
     /// Consume the `UnsizedByteBuf` and return the underlying byte buffer.
     pub fn into_inner(self) -> Vec<u8> {
         self.inner
@@ -35,51 +97,45 @@ impl Serialize for UnsizedByteBuf {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: Serializer
     {
-        let inner_len = self.inner.len();
-        let padded_len = inner_len + (16 - inner_len % 16) % 16;
-        let u64_padded_len = padded_len / 8;
-
-        let mut inner_ref = self.inner.as_slice();
-        let mut serialize_tuple = serializer.serialize_tuple(u64_padded_len)?;
-
-        let mut serialized_len = 0;
-        while serialized_len < padded_len {
-            let u64_value = if !inner_ref.is_empty() {
-                // Prefer using `WriteBytesExt::write_u64` over `ByteOrder::write_u64` in case
-                // if something goes really wrong
-                inner_ref.read_u64::<LittleEndian>().unwrap_or_else(|_| {
-                    // TODO: Revise this part for guarantees of len % 4 == 0 or the like
-                    // Must be the last element out there, let's read the truncated value and use
-                    // it as any u64 element
-                    if let Ok(value) = inner_ref.read_uint::<LittleEndian>(inner_ref.len()) {
-                        value
-                    } else {
-                        // Prefer using a detailed error message in case if something goes really wrong
-                        unreachable!("Should be able to read remaining bytes without errors from {:?}", inner_ref)
-                    }
-                })
-            } else {
-                0
-            };
-
-            serialize_tuple.serialize_element(&u64_value)?;
-            serialized_len += 8;
-        }
-
-        serialize_tuple.end()
+        serialize_unsized_bytes(&self.inner, self.alignment, serializer)
     }
 }
 
 impl MtProtoSized for UnsizedByteBuf {
     fn size_hint(&self) -> error::Result<usize> {
-        size_hint_from_unsized_byte_seq_len(self.inner.len())
+        size_hint_from_unsized_byte_seq_len_with_alignment(self.inner.len(), self.alignment)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> ::arbitrary::Arbitrary<'a> for UnsizedByteBuf {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<UnsizedByteBuf> {
+        ::arbitrary::Arbitrary::arbitrary(u).map(UnsizedByteBuf::new)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl ::proptest::arbitrary::Arbitrary for UnsizedByteBuf {
+    type Parameters = <Vec<u8> as ::proptest::arbitrary::Arbitrary>::Parameters;
+    type Strategy = ::proptest::strategy::Map<
+        <Vec<u8> as ::proptest::arbitrary::Arbitrary>::Strategy,
+        fn(Vec<u8>) -> UnsizedByteBuf,
+    >;
+
+    fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(
+            <Vec<u8> as ::proptest::arbitrary::Arbitrary>::arbitrary_with(args),
+            UnsizedByteBuf::new as fn(Vec<u8>) -> UnsizedByteBuf)
     }
 }
 
 /// An unsized byte buffer seed with the length of the byte sequence to be deserialized.
+///
+/// The deserialized buffer keeps the padding bytes.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct UnsizedByteBufSeed {
-    inner_len: u32,
+    inner_len: Option<u32>,
+    alignment: Alignment,
 }
 
 impl UnsizedByteBufSeed {
@@ -87,9 +143,29 @@ impl UnsizedByteBufSeed {
     /// deserialized.
     pub fn new(inner_len: u32) -> UnsizedByteBufSeed {
         UnsizedByteBufSeed {
-            inner_len: inner_len,
+            inner_len: Some(inner_len),
+            alignment: Alignment::default(),
         }
     }
+
+    /// Construct a new unsized byte buffer seed which reads everything up to the end of input.
+    ///
+    /// With `serde_mtproto` deserializer this means up to the declared size of the enclosing
+    /// `WithSize`/`BoxedWithSize` value if there is one or, otherwise, all remaining input (see
+    /// `Deserializer::remaining_length`). The number of read bytes must be divisible by the
+    /// alignment.
+    pub fn to_end() -> UnsizedByteBufSeed {
+        UnsizedByteBufSeed {
+            inner_len: None,
+            alignment: Alignment::default(),
+        }
+    }
+
+    /// Set the alignment of the byte sequence to be deserialized.
+    pub fn with_alignment(mut self, alignment: Alignment) -> UnsizedByteBufSeed {
+        self.alignment = alignment;
+        self
+    }
 }
 
 impl<'de> DeserializeSeed<'de> for UnsizedByteBufSeed {
@@ -98,75 +174,844 @@ impl<'de> DeserializeSeed<'de> for UnsizedByteBufSeed {
     fn deserialize<D>(self, deserializer: D) -> Result<UnsizedByteBuf, D::Error>
         where D: Deserializer<'de>
     {
-        struct UnsizedByteBufVisitor;
+        struct UnsizedByteBufVisitor {
+            alignment: Alignment,
+        }
 
         impl<'de> Visitor<'de> for UnsizedByteBufVisitor {
             type Value = UnsizedByteBuf;
 
             fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                f.write_str("a stream of bytes without prepended length and with a EOF")
+                write!(f, "a stream of bytes without prepended length padded to {} bytes",
+                    self.alignment.bytes())
             }
 
             fn visit_seq<A>(self, mut seq: A) -> Result<UnsizedByteBuf, A::Error>
                 where A: de::SeqAccess<'de>
             {
-                let mut inner = Vec::with_capacity(seq.size_hint().unwrap_or(0));
-
-                while let Some(value) = seq.next_element()? {
-                    // Prefer using `WriteBytesExt::write_u64` over `ByteOrder::write_u64` in case
-                    // if something goes really wrong
-                    if inner.write_u64::<LittleEndian>(value).is_err() {
-                        unreachable!("Should be able to write without errors into the in-memory buffer {:?}", inner)
-                    }
+                let mut inner = Vec::with_capacity(seq.size_hint().unwrap_or(0) * 4);
+
+                while let Some(word) = seq.next_element()? {
+                    let mut buf = [0; 4];
+                    LittleEndian::write_u32(&mut buf, word);
+                    inner.extend_from_slice(&buf);
                 }
 
-                Ok(UnsizedByteBuf {
-                    inner: inner,
-                })
+                self.visit_byte_buf(inner)
+            }
+
+            fn visit_byte_buf<E>(self, inner: Vec<u8>) -> Result<UnsizedByteBuf, E>
+                where E: de::Error
+            {
+                if inner.len() % self.alignment.bytes() != 0 {
+                    return Err(E::invalid_length(inner.len(), &self));
+                }
+
+                Ok(UnsizedByteBuf::with_alignment(inner, self.alignment))
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<UnsizedByteBuf, D::Error>
+                where D: Deserializer<'de>
+            {
+                deserializer.deserialize_seq(self)
             }
         }
 
-        let padded_bytes_len = self.inner_len + (16 - self.inner_len % 16) % 16;
-        let padded_len = safe_int_cast::<u32, usize>(padded_bytes_len / 8).map_err(D::Error::custom)?;
+        let visitor = UnsizedByteBufVisitor { alignment: self.alignment };
 
-        deserializer.deserialize_tuple(padded_len, UnsizedByteBufVisitor)
+        match self.inner_len {
+            Some(inner_len) => {
+                let padded_len = self.alignment.padded_len(safe_int_cast(inner_len).map_err(pass_through)?);
+                deserializer.deserialize_tuple(padded_len / 4, visitor)
+            },
+            None => deserializer.deserialize_newtype_struct(REMAINING_BYTES_NEWTYPE_NAME, visitor),
+        }
     }
 }
 
 
 /// A bytes slice which doesn't write its length when serialized.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct UnsizedBytes<'a> {
     inner: &'a [u8],
+    alignment: Alignment,
 }
 
 impl<'a> UnsizedBytes<'a> {
-    /// Wrap a bytes slice.
+    /// Wrap a bytes slice which is padded to 16 bytes when serialized.
     pub fn new(inner: &'a [u8]) -> UnsizedBytes<'a> {
+        UnsizedBytes::with_alignment(inner, Alignment::default())
+    }
+
+    /// Wrap a bytes slice which is padded to `alignment` when serialized.
+    pub fn with_alignment(inner: &'a [u8], alignment: Alignment) -> UnsizedBytes<'a> {
         UnsizedBytes {
             inner: inner,
+            alignment: alignment,
         }
     }
 
+    /// Alignment of the serialized bytes slice.
+    pub fn alignment(&self) -> Alignment {
+        self.alignment
+    }
+
     /// View the `UnsizedBytes` as the underlying bytes slice.
     pub fn as_inner(&'a self) -> &'a [u8] {
         self.inner
     }
 }
 
+impl<'a> Serialize for UnsizedBytes<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serialize_unsized_bytes(self.inner, self.alignment, serializer)
+    }
+}
+
 impl<'a> MtProtoSized for UnsizedBytes<'a> {
     fn size_hint(&self) -> error::Result<usize> {
-        size_hint_from_unsized_byte_seq_len(self.inner.len())
+        size_hint_from_unsized_byte_seq_len_with_alignment(self.inner.len(), self.alignment)
+    }
+}
+
+
+/// A TL string which keeps its contents as raw bytes.
+///
+/// TL strings are byte sequences which aren't guaranteed to be valid UTF-8, so deserializing them
+/// into `String` fails on invalid input. `TlString` is [de]serialized exactly like `String`, but
+/// accepts any bytes and leaves it up to the user how to interpret them.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TlString {
+    inner: Vec<u8>,
+}
+
+impl TlString {
+    /// Wrap raw bytes of a string.
+    pub fn new(inner: Vec<u8>) -> TlString {
+        TlString {
+            inner: inner,
+        }
+    }
+
+    /// View the raw bytes of the string.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner
+    }
+
+    /// Consume the `TlString` and return its raw bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.inner
+    }
+
+    /// View the string as `str` if it is valid UTF-8.
+    pub fn to_str(&self) -> Result<&str, str::Utf8Error> {
+        str::from_utf8(&self.inner)
+    }
+
+    /// Convert the string to `str`, replacing invalid UTF-8 sequences with `U+FFFD`.
+    pub fn to_string_lossy<'a>(&'a self) -> Cow<'a, str> {
+        String::from_utf8_lossy(&self.inner)
+    }
+}
+
+impl From<Vec<u8>> for TlString {
+    fn from(inner: Vec<u8>) -> TlString {
+        TlString::new(inner)
+    }
+}
+
+impl From<String> for TlString {
+    fn from(s: String) -> TlString {
+        TlString::new(s.into_bytes())
+    }
+}
+
+impl<'a> From<&'a str> for TlString {
+    fn from(s: &'a str) -> TlString {
+        TlString::new(s.as_bytes().to_vec())
+    }
+}
+
+impl AsRef<[u8]> for TlString {
+    fn as_ref(&self) -> &[u8] {
+        &self.inner
+    }
+}
+
+/// Displays the string lossily, see `TlString::to_string_lossy`.
+impl fmt::Display for TlString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+impl Serialize for TlString {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_bytes(&self.inner)
+    }
+}
+
+impl<'de> Deserialize<'de> for TlString {
+    fn deserialize<D>(deserializer: D) -> Result<TlString, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct TlStringVisitor;
+
+        impl<'de> Visitor<'de> for TlStringVisitor {
+            type Value = TlString;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or a byte sequence")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<TlString, E>
+                where E: de::Error
+            {
+                Ok(TlString::from(v))
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<TlString, E>
+                where E: de::Error
+            {
+                Ok(TlString::from(v))
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<TlString, E>
+                where E: de::Error
+            {
+                Ok(TlString::new(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<TlString, E>
+                where E: de::Error
+            {
+                Ok(TlString::new(v))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(TlStringVisitor)
+    }
+}
+
+impl MtProtoSized for TlString {
+    fn size_hint(&self) -> error::Result<usize> {
+        size_hint_from_byte_seq_len(self.inner.len())
+    }
+}
+
+impl Identifiable for TlString {
+    fn all_type_ids() -> &'static [u32] {
+        <String as Identifiable>::all_type_ids()
+    }
+
+    fn variant_for_id(type_id: u32) -> Option<TypeIdVariant> {
+        <String as Identifiable>::variant_for_id(type_id)
+    }
+
+    fn type_id(&self) -> u32 {
+        STRING_ID
+    }
+
+    fn enum_variant_id(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> ::arbitrary::Arbitrary<'a> for TlString {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<TlString> {
+        ::arbitrary::Arbitrary::arbitrary(u).map(TlString::new)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl ::proptest::arbitrary::Arbitrary for TlString {
+    type Parameters = <Vec<u8> as ::proptest::arbitrary::Arbitrary>::Parameters;
+    type Strategy = ::proptest::strategy::Map<
+        <Vec<u8> as ::proptest::arbitrary::Arbitrary>::Strategy,
+        fn(Vec<u8>) -> TlString,
+    >;
+
+    fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(
+            <Vec<u8> as ::proptest::arbitrary::Arbitrary>::arbitrary_with(args),
+            TlString::new as fn(Vec<u8>) -> TlString)
+    }
+}
+
+
+/// The maximum length of a chunk of `StreamedBytes` held in memory at once.
+const STREAMED_BYTES_CHUNK_LEN: usize = 64 * 1024;
+
+/// A byte sequence of known length which is copied from an `io::Read` when serialized instead of
+/// being held in memory as a whole.
+///
+/// It is serialized exactly like a `ByteBuf` of the same contents. This is useful for large
+/// payloads like file parts.
+///
+/// Serializing a `StreamedBytes` drains the reader, so it can be serialized only once: any later
+/// serialization fails, and so does `MtProtoSized::size_hint`, instead of reading whatever the
+/// reader has left. Mind that `serialized_size` and `Serializer::serialize_with_size` serialize
+/// the value too, use `MtProtoSized` before serializing to compute the size instead.
+/// Serialization fails if the reader ends before `len` bytes are read.
+#[derive(Debug)]
+pub struct StreamedBytes<R: io::Read> {
+    reader: RefCell<R>,
+    len: u32,
+    drained: Cell<bool>,
+}
+
+impl<R: io::Read> StreamedBytes<R> {
+    /// Wrap a reader which provides at least `len` bytes.
+    pub fn new(reader: R, len: u32) -> StreamedBytes<R> {
+        StreamedBytes {
+            reader: RefCell::new(reader),
+            len: len,
+            drained: Cell::new(false),
+        }
+    }
+
+    /// Length of the byte sequence.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Return `true` if the byte sequence is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Consume the `StreamedBytes` and return the underlying reader.
+    pub fn into_reader(self) -> R {
+        self.reader.into_inner()
+    }
+
+    fn check_not_drained(&self) -> error::Result<()> {
+        if self.drained.get() {
+            bail!(ErrorKind::SerCustom("the reader of `StreamedBytes` is already drained".to_owned()));
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: io::Read> Serialize for StreamedBytes<R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        self.check_not_drained().map_err(S::Error::custom)?;
+        self.drained.set(true);
+
+        serializer.serialize_newtype_struct(STREAMED_BYTES_NEWTYPE_NAME, &StreamedChunks(self))
+    }
+}
+
+impl<R: io::Read> MtProtoSized for StreamedBytes<R> {
+    fn size_hint(&self) -> error::Result<usize> {
+        self.check_not_drained()?;
+        size_hint_from_byte_seq_len(safe_int_cast(self.len)?)
+    }
+}
+
+/// The length of `StreamedBytes` followed by its contents split into chunks.
+struct StreamedChunks<'a, R: io::Read + 'a>(&'a StreamedBytes<R>);
+
+impl<'a, R: io::Read> Serialize for StreamedChunks<'a, R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let len = safe_int_cast::<u32, usize>(self.0.len).map_err(S::Error::custom)?;
+        let chunks_count = (len + STREAMED_BYTES_CHUNK_LEN - 1) / STREAMED_BYTES_CHUNK_LEN;
+
+        let mut serialize_tuple = serializer.serialize_tuple(1 + chunks_count)?;
+        serialize_tuple.serialize_element(&self.0.len)?;
+
+        let mut reader = self.0.reader.try_borrow_mut().map_err(S::Error::custom)?;
+        let mut buf = vec![0; cmp::min(len, STREAMED_BYTES_CHUNK_LEN)];
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let chunk_len = cmp::min(remaining, STREAMED_BYTES_CHUNK_LEN);
+            let chunk = &mut buf[..chunk_len];
+
+            reader.read_exact(chunk).map_err(S::Error::custom)?;
+            serialize_tuple.serialize_element(&Bytes::new(chunk))?;
+            remaining -= chunk_len;
+        }
+
+        serialize_tuple.end()
+    }
+}
+
+
+/// A wrapper which serializes elements of an iterator as a vector without collecting them.
+///
+/// The length of the vector is known up front thanks to `ExactSizeIterator`, unlike with
+/// `Serializer::collect_seq` which fails with `ErrorKind::SeqsWithUnknownLengthUnsupported` for
+/// most iterators.
+///
+/// Serializing a `SerializeIter` consumes the iterator, so it can be serialized only once: any
+/// later serialization fails. Mind that `serialized_size` and `Serializer::serialize_with_size`
+/// serialize the value too.
+pub struct SerializeIter<I> {
+    iter: Cell<Option<I>>,
+}
+
+impl<I> SerializeIter<I>
+    where I: ExactSizeIterator,
+          I::Item: Serialize,
+{
+    /// Wrap an iterator.
+    pub fn new(iter: I) -> SerializeIter<I> {
+        SerializeIter {
+            iter: Cell::new(Some(iter)),
+        }
+    }
+}
+
+impl<I> Serialize for SerializeIter<I>
+    where I: ExactSizeIterator,
+          I::Item: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let iter = self.iter.take()
+            .ok_or_else(|| S::Error::custom("the iterator of `SerializeIter` is already consumed"))?;
+        let mut serialize_seq = serializer.serialize_seq(Some(iter.len()))?;
+
+        for item in iter {
+            serialize_seq.serialize_element(&item)?;
+        }
+
+        serialize_seq.end()
+    }
+}
+
+
+/// An integer type which `IntVector` and `PackedInts` copy as whole little-endian slices.
+///
+/// Implemented for `i32` and `u32` (MTProto `int`) and for `i64` and `u64` (MTProto `long`).
+pub trait PackedInt: Copy + Default {
+    /// Size of a serialized value in bytes.
+    const SIZE: usize;
+
+    /// Write `src` to `dst` in little-endian byte order, `dst` must be `src.len() * SIZE` long.
+    fn write_le_slice(src: &[Self], dst: &mut [u8]);
+
+    /// Read `dst` from `src` in little-endian byte order, `src` must be `dst.len() * SIZE` long.
+    fn read_le_slice(src: &[u8], dst: &mut [Self]);
+}
+
+macro_rules! impl_packed_int {
+    ($($type:ty => ($size:expr, $write_into:ident, $read_into:ident),)*) => {
+        $(
+            impl PackedInt for $type {
+                const SIZE: usize = $size;
+
+                fn write_le_slice(src: &[$type], dst: &mut [u8]) {
+                    LittleEndian::$write_into(src, dst);
+                }
+
+                fn read_le_slice(src: &[u8], dst: &mut [$type]) {
+                    LittleEndian::$read_into(src, dst);
+                }
+            }
+        )*
+    };
+}
+
+impl_packed_int! {
+    i32 => (INT_SIZE, write_i32_into, read_i32_into),
+    u32 => (INT_SIZE, write_u32_into, read_u32_into),
+    i64 => (LONG_SIZE, write_i64_into, read_i64_into),
+    u64 => (LONG_SIZE, write_u64_into, read_u64_into),
+}
+
+/// Packed integers up to this size in bytes are serialized from a buffer on the stack.
+const PACKED_INTS_STACK_BUFFER_SIZE: usize = 64;
+
+/// Serialize packed integers, optionally preceded by their count, with a single write.
+fn serialize_packed_ints<T, S>(ints: &[T], with_len: bool, serializer: S) -> Result<S::Ok, S::Error>
+    where T: PackedInt,
+          S: Serializer,
+{
+    let len_size = if with_len { 4 } else { 0 };
+    let total_size = len_size + ints.len() * T::SIZE;
+
+    // Short values like `int128` and `int256` don't need an allocation
+    let mut stack_buf = [0; PACKED_INTS_STACK_BUFFER_SIZE];
+    let mut heap_buf = Vec::new();
+    let bytes = if total_size <= PACKED_INTS_STACK_BUFFER_SIZE {
+        &mut stack_buf[..total_size]
+    } else {
+        heap_buf.resize(total_size, 0);
+        &mut heap_buf[..]
+    };
+
+    if with_len {
+        check_seq_len(ints.len()).map_err(S::Error::custom)?;
+        LittleEndian::write_u32(&mut bytes[..4], ints.len() as u32);
+    }
+
+    T::write_le_slice(ints, &mut bytes[len_size..]);
+
+    serializer.serialize_newtype_struct(PACKED_INTS_NEWTYPE_NAME, &Bytes::new(bytes))
+}
+
+/// Visitor for the length of an `IntVector` followed by its elements.
+struct IntVectorVisitor<T>(PhantomData<T>);
+
+impl<'de, T: PackedInt> Visitor<'de> for IntVectorVisitor<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a length followed by packed integers")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Vec<T>, A::Error>
+        where A: de::SeqAccess<'de>
+    {
+        let len: u32 = seq.next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let ints = seq.next_element_seed(PackedIntsSeed::new(safe_int_cast(len).map_err(pass_through)?))?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        Ok(ints)
+    }
+}
+
+/// Seed for a given number of packed integers which are read at once by `de::Deserializer`.
+struct PackedIntsSeed<T> {
+    len: usize,
+    phantom: PhantomData<T>,
+}
+
+impl<T> PackedIntsSeed<T> {
+    fn new(len: usize) -> PackedIntsSeed<T> {
+        PackedIntsSeed {
+            len: len,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'de, T: PackedInt> DeserializeSeed<'de> for PackedIntsSeed<T> {
+    type Value = Vec<T>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Vec<T>, D::Error>
+        where D: Deserializer<'de>
+    {
+        // The tuple struct consists of 32-bit words
+        let words_len = self.len.saturating_mul(T::SIZE / INT_SIZE);
+
+        deserializer.deserialize_tuple_struct(PACKED_INTS_NEWTYPE_NAME, words_len, self)
+    }
+}
+
+impl<'de, T: PackedInt> Visitor<'de> for PackedIntsSeed<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} little-endian bytes of packed integers", self.len.saturating_mul(T::SIZE))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Vec<T>, E>
+        where E: de::Error
+    {
+        if v.len() != self.len.saturating_mul(T::SIZE) {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+
+        let mut ints = vec![T::default(); self.len];
+        T::read_le_slice(v, &mut ints);
+
+        Ok(ints)
+    }
+}
+
+/// Visitor for little-endian bytes of `PackedInts` read at once by `de::Deserializer`.
+struct PackedIntsVisitor<T, const N: usize>(PhantomData<T>);
+
+impl<'de, T: PackedInt, const N: usize> Visitor<'de> for PackedIntsVisitor<T, N> {
+    type Value = [T; N];
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} little-endian bytes of packed integers", N * T::SIZE)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<[T; N], E>
+        where E: de::Error
+    {
+        if v.len() != N * T::SIZE {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+
+        let mut ints = [T::default(); N];
+        T::read_le_slice(v, &mut ints);
+
+        Ok(ints)
+    }
+}
+
+/// A vector of integers which is serialized by copying all of them at once.
+///
+/// It has the same MTProto representation as a `Vec<T>` of the same contents, the length
+/// followed by little-endian integers, but is written and read with a single call instead of
+/// an element at a time. This is useful for long lists like message or user ids.
+///
+/// Like `Raw`, it relies on `ser::Serializer` and `de::Deserializer` of this crate and isn't meant
+/// for other Serde formats.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct IntVector<T> {
+    inner: Vec<T>,
+}
+
+impl<T: PackedInt> IntVector<T> {
+    /// Wrap a vector of integers.
+    pub fn new(inner: Vec<T>) -> IntVector<T> {
+        IntVector {
+            inner: inner,
+        }
+    }
+
+    /// Return an immutable reference to the underlying vector.
+    pub fn inner(&self) -> &Vec<T> {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the underlying vector.
+    pub fn inner_mut(&mut self) -> &mut Vec<T> {
+        &mut self.inner
+    }
+
+    /// Unwrap the vector.
+    pub fn into_inner(self) -> Vec<T> {
+        self.inner
+    }
+}
+
+impl<T: PackedInt> From<Vec<T>> for IntVector<T> {
+    fn from(inner: Vec<T>) -> IntVector<T> {
+        IntVector::new(inner)
+    }
+}
+
+impl<T: PackedInt> Serialize for IntVector<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serialize_packed_ints(&self.inner, true, serializer)
+    }
+}
+
+impl<'de, T: PackedInt> Deserialize<'de> for IntVector<T> {
+    fn deserialize<D>(deserializer: D) -> Result<IntVector<T>, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserializer.deserialize_tuple(2, IntVectorVisitor(PhantomData))
+            .map(IntVector::new)
+    }
+}
+
+impl<T: PackedInt> MtProtoSized for IntVector<T> {
+    fn size_hint(&self) -> error::Result<usize> {
+        check_seq_len(self.inner.len())?;
+
+        Ok(4 + self.inner.len() * T::SIZE)
+    }
+}
+
+impl<T> Identifiable for IntVector<T> {
+    fn all_type_ids() -> &'static [u32] {
+        <Vec<T> as Identifiable>::all_type_ids()
+    }
+
+    fn type_id(&self) -> u32 {
+        VECTOR_ID
+    }
+
+    fn enum_variant_id(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a, T> ::arbitrary::Arbitrary<'a> for IntVector<T>
+    where T: ::arbitrary::Arbitrary<'a> + PackedInt
+{
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<IntVector<T>> {
+        ::arbitrary::Arbitrary::arbitrary(u).map(IntVector::new)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl<T> ::proptest::arbitrary::Arbitrary for IntVector<T>
+    where T: ::proptest::arbitrary::Arbitrary + PackedInt
+{
+    type Parameters = <Vec<T> as ::proptest::arbitrary::Arbitrary>::Parameters;
+    type Strategy = ::proptest::strategy::Map<
+        <Vec<T> as ::proptest::arbitrary::Arbitrary>::Strategy,
+        fn(Vec<T>) -> IntVector<T>,
+    >;
+
+    fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(
+            <Vec<T> as ::proptest::arbitrary::Arbitrary>::arbitrary_with(args),
+            IntVector::new as fn(Vec<T>) -> IntVector<T>)
+    }
+}
+
+
+/// A fixed number of integers which is serialized by copying all of them at once.
+///
+/// It has the same MTProto representation as an array `[T; N]`, little-endian integers without
+/// length, but is written and read with a single call instead of an element at a time. This is
+/// useful for values like `int128` message keys and `int256` nonces.
+///
+/// Like `Raw`, it relies on `ser::Serializer` and `de::Deserializer` of this crate and isn't meant
+/// for other Serde formats.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PackedInts<T, const N: usize> {
+    inner: [T; N],
+}
+
+impl<T: PackedInt, const N: usize> PackedInts<T, N> {
+    /// Wrap an array of integers.
+    pub fn new(inner: [T; N]) -> PackedInts<T, N> {
+        PackedInts {
+            inner: inner,
+        }
+    }
+
+    /// Return an immutable reference to the underlying array.
+    pub fn inner(&self) -> &[T; N] {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the underlying array.
+    pub fn inner_mut(&mut self) -> &mut [T; N] {
+        &mut self.inner
+    }
+
+    /// Unwrap the array.
+    pub fn into_inner(self) -> [T; N] {
+        self.inner
+    }
+}
+
+impl<T: PackedInt, const N: usize> From<[T; N]> for PackedInts<T, N> {
+    fn from(inner: [T; N]) -> PackedInts<T, N> {
+        PackedInts::new(inner)
+    }
+}
+
+impl<T: PackedInt, const N: usize> Serialize for PackedInts<T, N> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serialize_packed_ints(&self.inner, false, serializer)
+    }
+}
+
+impl<'de, T: PackedInt, const N: usize> Deserialize<'de> for PackedInts<T, N> {
+    fn deserialize<D>(deserializer: D) -> Result<PackedInts<T, N>, D::Error>
+        where D: Deserializer<'de>
+    {
+        // The tuple struct consists of 32-bit words
+        let inner = deserializer.deserialize_tuple_struct(
+            PACKED_INTS_NEWTYPE_NAME, N * T::SIZE / INT_SIZE, PackedIntsVisitor(PhantomData))?;
+
+        Ok(PackedInts::new(inner))
+    }
+}
+
+impl<T: PackedInt, const N: usize> MtProtoSized for PackedInts<T, N> {
+    fn size_hint(&self) -> error::Result<usize> {
+        Ok(N * T::SIZE)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a, T, const N: usize> ::arbitrary::Arbitrary<'a> for PackedInts<T, N>
+    where T: ::arbitrary::Arbitrary<'a> + PackedInt
+{
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<PackedInts<T, N>> {
+        let mut inner = [T::default(); N];
+
+        for value in inner.iter_mut() {
+            *value = ::arbitrary::Arbitrary::arbitrary(u)?;
+        }
+
+        Ok(PackedInts::new(inner))
     }
 }
 
+#[cfg(feature = "proptest")]
+impl<T, const N: usize> ::proptest::arbitrary::Arbitrary for PackedInts<T, N>
+    where T: ::proptest::arbitrary::Arbitrary + PackedInt
+{
+    type Parameters = T::Parameters;
+    type Strategy = ::proptest::strategy::Map<
+        ::proptest::collection::VecStrategy<T::Strategy>,
+        fn(Vec<T>) -> PackedInts<T, N>,
+    >;
+
+    fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
+        fn from_vec<T: PackedInt, const N: usize>(values: Vec<T>) -> PackedInts<T, N> {
+            let mut inner = [T::default(); N];
+            inner.copy_from_slice(&values);
+
+            PackedInts::new(inner)
+        }
+
+        ::proptest::strategy::Strategy::prop_map(
+            ::proptest::collection::vec(T::arbitrary_with(args), N),
+            from_vec::<T, N> as fn(Vec<T>) -> PackedInts<T, N>)
+    }
+}
+
+
+/// Serialize bytes padded with zeros to `alignment` as a tuple of little-endian `u32` words.
+fn serialize_unsized_bytes<S>(bytes: &[u8], alignment: Alignment, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    let padded_len = alignment.padded_len(bytes.len());
+    let mut serialize_tuple = serializer.serialize_tuple(padded_len / 4)?;
+
+    for chunk in bytes.chunks(4) {
+        // The last chunk may be shorter than a word, the rest of it is padding
+        let mut buf = [0; 4];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        serialize_tuple.serialize_element(&LittleEndian::read_u32(&buf))?;
+    }
+
+    for _ in (bytes.len() + 3) / 4..padded_len / 4 {
+        serialize_tuple.serialize_element(&0u32)?;
+    }
+
+    serialize_tuple.end()
+}
+
 
 /// Helper function for everything naturally representable as a byte sequence.
 ///
 /// This version **doesn't take** into account the byte sequence length since it is not contained
 /// in the serialized representation of the byte sequence.
 pub fn size_hint_from_unsized_byte_seq_len(len: usize) -> error::Result<usize> {
-    let size = len + (16 - len % 16) % 16;
-    assert!(size % 16 == 0);
+    size_hint_from_unsized_byte_seq_len_with_alignment(len, Alignment::default())
+}
+
+/// Same as `size_hint_from_unsized_byte_seq_len`, but for a byte sequence padded to `alignment`.
+pub fn size_hint_from_unsized_byte_seq_len_with_alignment(len: usize, alignment: Alignment)
+    -> error::Result<usize>
+{
+    let size = alignment.padded_len(len);
+    assert!(size % alignment.bytes() == 0);
 
     Ok(size)
 }
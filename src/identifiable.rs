@@ -2,6 +2,9 @@
 
 #![cfg_attr(feature = "cargo-clippy", allow(unreadable_literal))]  // To match the look & feel from TL schema
 
+use std::rc::Rc;
+use std::sync::Arc;
+
 
 /// Type id of the bool true value.
 pub const BOOL_TRUE_ID: u32 = 0x997275b5;
@@ -11,6 +14,8 @@ pub const BOOL_FALSE_ID: u32 = 0xbc799737;
 pub const INT_ID: u32 = 0xa8509bda;
 /// Type id of the long type.
 pub const LONG_ID: u32 = 0x22076cba;
+/// Type id of the int128 type.
+pub const INT128_ID: u32 = 0x84ccf7b7;
 /// Type id of the double type.
 pub const DOUBLE_ID: u32 = 0x2210c154;
 /// Type id of the string type.
@@ -22,11 +27,23 @@ pub const VECTOR_ID: u32 = 0x1cb5c415;
 const BOOL_IDS: &'static [u32] = &[BOOL_TRUE_ID, BOOL_FALSE_ID];
 const INT_IDS: &'static [u32] = &[INT_ID];
 const LONG_IDS: &'static [u32] = &[LONG_ID];
+const INT128_IDS: &'static [u32] = &[INT128_ID];
 const DOUBLE_IDS: &'static [u32] = &[DOUBLE_ID];
 const STRING_IDS: &'static [u32] = &[STRING_ID];
 const VECTOR_IDS: &'static [u32] = &[VECTOR_ID];
 
 
+/// A constructor of an identifiable type found by its type id.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TypeIdVariant {
+    /// Position of the type id in `Identifiable::all_type_ids()`.
+    pub index: usize,
+    /// Name of the enum variant with this type id, the same as returned by
+    /// `Identifiable::enum_variant_id()`, or `None` if not an enum variant.
+    pub name: Option<&'static str>,
+}
+
+
 /// A trait for a Rust data structure that can have an id.
 pub trait Identifiable {
     /// Get all possible ids (known at compile time) of an identifiable type.
@@ -45,9 +62,7 @@ pub trait Identifiable {
     /// checking if an arbitrary id is in this set - in this case, a reliable
     /// counting routine must traverse the whole slice to eliminate duplicates.
     ///
-    /// Unfortunately, this worsens the time complexity from O(*1*) to O(*n*),
-    /// but for everyday use-case this is fine since product types we usually
-    /// use are relatively small to make this a big concern.
+    /// Use `variant_for_id()` to check if an arbitrary id is in this set.
     ///
     /// # Compatibility note
     ///
@@ -56,6 +71,27 @@ pub trait Identifiable {
     fn all_type_ids() -> &'static [u32]
         where Self: Sized;
 
+    /// Find which of `all_type_ids()` is the given type id and which enum
+    /// variant it belongs to.
+    ///
+    /// The default implementation searches `all_type_ids()` linearly, which is
+    /// fine for types with a few ids. `#[derive(MtProtoIdentifiable)]` looks up
+    /// ids in a table sorted at compile time instead, so that checking ids of
+    /// enums with hundreds of variants stays cheap.
+    ///
+    /// # Implementation note
+    ///
+    /// This method **should** return `Some` exactly for ids contained in the
+    /// slice returned by `all_type_ids()` method, with `index` being the
+    /// position of the id in that slice.
+    fn variant_for_id(type_id: u32) -> Option<TypeIdVariant>
+        where Self: Sized
+    {
+        Self::all_type_ids().iter()
+            .position(|&id| id == type_id)
+            .map(|index| TypeIdVariant { index: index, name: None })
+    }
+
     /// Get id of a value of an identifiable type.
     ///
     /// Its signature is made `(&self) -> i32`, not `() -> i32` because of enum
@@ -76,6 +112,35 @@ pub trait Identifiable {
     /// `#[derive(Deserialize)]` call `Deserializer::deserialize_identifier()`
     /// to identify an enum variant.
     fn enum_variant_id(&self) -> Option<&'static str>;
+
+    /// Get the name of the catch-all enum variant which keeps values with ids
+    /// not known at compile time, if the type has one.
+    ///
+    /// `Boxed` and `BoxedWithSize` accept any type id for such types instead
+    /// of failing with `ErrorKind::InvalidTypeId`.
+    fn unknown_variant_id() -> Option<&'static str>
+        where Self: Sized
+    {
+        None
+    }
+
+    /// Construct a value of the catch-all variant from an unknown type id and
+    /// the raw bytes of the value that followed it.
+    ///
+    /// Returns `None` if the type doesn't have a catch-all variant.
+    fn from_unknown(_type_id: u32, _raw: Vec<u8>) -> Option<Self>
+        where Self: Sized
+    {
+        None
+    }
+
+    /// Get the raw bytes kept by the catch-all variant if this value is one.
+    ///
+    /// `Boxed` and `BoxedWithSize` serialize these bytes as is after the
+    /// type id so that unknown values are reproduced byte-exactly.
+    fn unknown_raw(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
 
@@ -84,6 +149,10 @@ impl<'a, T: Identifiable> Identifiable for &'a T {
         T::all_type_ids()
     }
 
+    fn variant_for_id(type_id: u32) -> Option<TypeIdVariant> {
+        T::variant_for_id(type_id)
+    }
+
     fn type_id(&self) -> u32 {
         (*self).type_id()
     }
@@ -91,22 +160,54 @@ impl<'a, T: Identifiable> Identifiable for &'a T {
     fn enum_variant_id(&self) -> Option<&'static str> {
         (*self).enum_variant_id()
     }
-}
 
-impl<T: Identifiable> Identifiable for Box<T> {
-    fn all_type_ids() -> &'static [u32] {
-        T::all_type_ids()
+    fn unknown_variant_id() -> Option<&'static str> {
+        T::unknown_variant_id()
     }
 
-    fn type_id(&self) -> u32 {
-        (**self).type_id()
+    fn unknown_raw(&self) -> Option<&[u8]> {
+        (*self).unknown_raw()
     }
+}
 
-    fn enum_variant_id(&self) -> Option<&'static str> {
-        (**self).enum_variant_id()
-    }
+macro_rules! impl_identifiable_for_pointers {
+    ($($pointer:ident),*) => {
+        $(
+            impl<T: Identifiable> Identifiable for $pointer<T> {
+                fn all_type_ids() -> &'static [u32] {
+                    T::all_type_ids()
+                }
+
+                fn variant_for_id(type_id: u32) -> Option<TypeIdVariant> {
+                    T::variant_for_id(type_id)
+                }
+
+                fn type_id(&self) -> u32 {
+                    (**self).type_id()
+                }
+
+                fn enum_variant_id(&self) -> Option<&'static str> {
+                    (**self).enum_variant_id()
+                }
+
+                fn unknown_variant_id() -> Option<&'static str> {
+                    T::unknown_variant_id()
+                }
+
+                fn from_unknown(type_id: u32, raw: Vec<u8>) -> Option<$pointer<T>> {
+                    T::from_unknown(type_id, raw).map($pointer::new)
+                }
+
+                fn unknown_raw(&self) -> Option<&[u8]> {
+                    (**self).unknown_raw()
+                }
+            }
+        )*
+    };
 }
 
+impl_identifiable_for_pointers!(Box, Rc, Arc);
+
 #[cfg_attr(feature = "cargo-clippy", allow(match_bool))]  // match looks better here
 impl Identifiable for bool {
     fn all_type_ids() -> &'static [u32] {
@@ -163,10 +264,20 @@ impl_identifiable_for_primitives! {
     u32 => (INT_IDS,  INT_ID),
     u64 => (LONG_IDS, LONG_ID),
 
+    i128 => (INT128_IDS, INT128_ID),
+    u128 => (INT128_IDS, INT128_ID),
+
     f32 => (DOUBLE_IDS, DOUBLE_ID),
     f64 => (DOUBLE_IDS, DOUBLE_ID),
 
     String => (STRING_IDS, STRING_ID),
+    Box<str> => (STRING_IDS, STRING_ID),
+}
+
+#[cfg(feature = "extprim")]
+impl_identifiable_for_primitives! {
+    ::extprim::i128::i128 => (INT128_IDS, INT128_ID),
+    ::extprim::u128::u128 => (INT128_IDS, INT128_ID),
 }
 
 impl<'a> Identifiable for &'a str {
@@ -196,3 +307,17 @@ impl<T> Identifiable for Vec<T> {
         None
     }
 }
+
+impl<'a, T> Identifiable for &'a [T] {
+    fn all_type_ids() -> &'static [u32] {
+        VECTOR_IDS
+    }
+
+    fn type_id(&self) -> u32 {
+        VECTOR_ID
+    }
+
+    fn enum_variant_id(&self) -> Option<&'static str> {
+        None
+    }
+}
@@ -34,30 +34,58 @@
 ))]
 
 
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+#[cfg(feature = "backtrace")]
+extern crate backtrace;
+#[cfg(feature = "bytes")]
+extern crate bytes;
 extern crate byteorder;
-#[macro_use]
-extern crate error_chain;
+#[cfg(feature = "chrono")]
+extern crate chrono;
 #[cfg(feature = "extprim")]
 extern crate extprim;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "num-bigint")]
+extern crate num_bigint;
 extern crate num_traits;
+#[cfg(feature = "proptest")]
+extern crate proptest;
 #[cfg(feature = "quickcheck")]
 extern crate quickcheck;
+#[macro_use]
 extern crate serde;
 extern crate serde_bytes;
 #[macro_use]
 extern crate serde_derive;
 
 
+/// Return early with an error (like `error_chain`'s macro of the same name).
+macro_rules! bail {
+    ($e:expr) => {
+        return Err(::std::convert::From::from($e))
+    };
+}
+
+
 mod utils;
 
+#[cfg(feature = "bytes")]
+pub mod buf;
+pub mod config;
 pub mod de;
+pub mod decoder;
 pub mod error;
+#[cfg(all(feature = "extprim", any(feature = "arbitrary", feature = "proptest")))]
+pub mod generators;
 pub mod helpers;
 pub mod identifiable;
+pub mod native;
+pub mod schema;
 pub mod ser;
 pub mod sized;
+pub mod with;
 pub mod wrappers;
 
 
@@ -65,14 +93,27 @@ pub mod wrappers;
 pub use serde_bytes::{ByteBuf, Bytes};
 
 // Serde essential re-exports
-pub use ser::{Serializer, to_bytes, to_writer, unsized_bytes_pad_to_bytes, unsized_bytes_pad_to_writer};
-pub use de::{Deserializer, from_bytes, from_bytes_reuse, from_reader, from_reader_reuse};
+pub use ser::{Serializer, SizeCounter, serialized_size, to_bytes, to_bytes_with_size, to_writer,
+              to_writer_with_size, unsized_bytes_pad_to_bytes, unsized_bytes_pad_to_bytes_with_alignment,
+              unsized_bytes_pad_to_writer, unsized_bytes_pad_to_writer_with_alignment};
+#[cfg(feature = "bytes")]
+pub use buf::{SharedBytes, from_buf, to_buf_mut};
+pub use de::{Deserializer, VectorIter, from_bytes, from_bytes_into, from_bytes_reuse, from_reader, from_reader_reuse,
+             peek_bytes_len, peek_type_id};
+
+pub use config::{CharEncoding, Config, FloatNarrowing};
+pub use decoder::{DecodeStatus, Decoder};
 
 // Error types and typedefs
-pub use error::{Error, ErrorKind, Result, ResultExt};
+pub use error::{Error, ErrorKind, Result};
 
 // Other items generally useful for MTProto [de]serialization
-pub use helpers::{UnsizedByteBuf, UnsizedByteBufSeed, UnsizedBytes, size_hint_from_unsized_byte_seq_len};
-pub use identifiable::Identifiable;
-pub use sized::{MtProtoSized, size_hint_from_byte_seq_len};
-pub use wrappers::{Boxed, BoxedWithSize, WithSize};
+pub use helpers::{Alignment, IntVector, PackedInt, PackedInts, SerializeIter, StreamedBytes, TlString, UnsizedByteBuf,
+                  UnsizedByteBufSeed, UnsizedBytes,
+                  size_hint_from_unsized_byte_seq_len, size_hint_from_unsized_byte_seq_len_with_alignment};
+pub use identifiable::{Identifiable, TypeIdVariant};
+pub use native::{MtProtoDeserialize, MtProtoSerialize};
+pub use schema::{Schema, TlDescribe};
+pub use sized::{MtProtoSized, assert_size_hint_matches, size_hint_from_byte_seq_len};
+pub use with::msg_id::MsgId;
+pub use wrappers::{Boxed, BoxedWithSize, Packed, Raw, WithRaw, WithSize};
@@ -0,0 +1,330 @@
+//! Native MTProto [de]serialization which bypasses the Serde data model.
+//!
+//! Serde has no notion of MTProto constructor ids: `#[derive(Serialize)]` on an enum writes
+//! nothing for unit variants and no id for other variants, so values have to be wrapped in
+//! `Boxed` to keep their identity. `MtProtoSerialize` and `MtProtoDeserialize` instead follow the
+//! TL distinction between boxed and bare types directly:
+//!
+//! * boxed values of types with ids (like the ones which derive these traits and `Vec<T>`) are
+//!   prefixed with their ids, bare values are not;
+//! * built-in bare types like integers, floats and strings are encoded the same either way and
+//!   exactly like `Serializer` and `Deserializer` encode them;
+//! * `bool` is always written as `boolTrue` or `boolFalse` ids.
+//!
+//! `#[derive(MtProtoSerialize, MtProtoDeserialize)]` from `serde_mtproto_derive` takes ids from
+//! the same `#[id = ...]` attributes as `#[derive(MtProtoIdentifiable)]` and reads fields as boxed
+//! unless they are marked with `#[mtproto(bare)]`. Fields of types which only implement Serde
+//! traits can be marked with `#[mtproto(serde)]` to be [de]serialized by Serde instead.
+//!
+//! ```
+//! # extern crate serde_mtproto;
+//! # #[macro_use] extern crate serde_mtproto_derive;
+//! use serde_mtproto::native;
+//!
+//! #[derive(Debug, PartialEq, MtProtoSerialize, MtProtoDeserialize)]
+//! enum Attachment {
+//!     #[id = 0xdef19e00]
+//!     Nothing,
+//!     #[id = 0xbadf00d0]
+//!     Link {
+//!         url: String,
+//!     },
+//! }
+//!
+//! #[derive(Debug, PartialEq, MtProtoSerialize, MtProtoDeserialize)]
+//! #[id = 0xbeefdead]
+//! struct Message {
+//!     id: u32,
+//!     attachments: Vec<Attachment>,
+//!     #[mtproto(bare)]
+//!     tags: Vec<String>,
+//! }
+//!
+//! # fn run() -> serde_mtproto::Result<()> {
+//! let message = Message {
+//!     id: 1,
+//!     attachments: vec![Attachment::Nothing],
+//!     tags: vec![],
+//! };
+//!
+//! let bytes = native::to_bytes(&message)?;
+//! assert_eq!(bytes, [
+//!     0xad, 0xde, 0xef, 0xbe,  // Message id
+//!     1, 0, 0, 0,              // id
+//!     0x15, 0xc4, 0xb5, 0x1c,  // Vector id
+//!     1, 0, 0, 0,              // attachments count
+//!     0x00, 0x9e, 0xf1, 0xde,  // Attachment::Nothing id
+//!     0, 0, 0, 0,              // tags count, bare vector has no id
+//! ]);
+//!
+//! let decoded: Message = native::from_bytes(&bytes)?;
+//! assert_eq!(decoded, message);
+//! #     Ok(())
+//! # }
+//! # fn main() { run().unwrap(); }
+//! ```
+
+use std::cmp;
+use std::io;
+
+use serde::de::{Deserialize, DeserializeOwned};
+use serde::ser::Serialize;
+use serde_bytes::ByteBuf;
+
+use de::Deserializer;
+use error::{self, ErrorKind};
+use helpers::TlString;
+use identifiable::{Identifiable, VECTOR_ID};
+use ser::Serializer;
+use utils::{check_seq_len, safe_int_cast};
+
+
+/// Upper bound of elements preallocated for a deserialized vector, so that a bogus count can't
+/// exhaust memory before the input runs out.
+const MAX_PREALLOCATED_ELEMENTS: usize = 4096;
+
+
+/// A data structure which can be serialized to MTProto without going through Serde.
+pub trait MtProtoSerialize {
+    /// Serialize the value as boxed, i.e. prefixed with its type id if it has one.
+    ///
+    /// Types without ids are serialized the same way as bare ones.
+    fn serialize_boxed<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        self.serialize_bare(ser)
+    }
+
+    /// Serialize the value as bare, i.e. without its type id.
+    fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()>;
+}
+
+/// A data structure which can be deserialized from MTProto without going through Serde.
+pub trait MtProtoDeserialize: Sized {
+    /// Deserialize a boxed value, i.e. prefixed with its type id if it has one.
+    ///
+    /// Types without ids are deserialized the same way as bare ones.
+    fn deserialize_boxed<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<Self> {
+        Self::deserialize_bare(de)
+    }
+
+    /// Deserialize a bare value, i.e. without its type id.
+    fn deserialize_bare<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<Self>;
+}
+
+
+macro_rules! impl_native_via_serde {
+    ($($type:ty,)*) => {
+        $(
+            impl MtProtoSerialize for $type {
+                fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+                    Serialize::serialize(self, ser)
+                }
+            }
+
+            impl MtProtoDeserialize for $type {
+                fn deserialize_bare<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<$type> {
+                    Deserialize::deserialize(de)
+                }
+            }
+        )*
+    };
+}
+
+impl_native_via_serde! {
+    (),
+    bool,
+    i8, i16, i32, i64, i128,
+    u8, u16, u32, u64, u128,
+    f32, f64,
+    String,
+    TlString,
+    ByteBuf,
+}
+
+impl<'a, T: MtProtoSerialize + ?Sized> MtProtoSerialize for &'a T {
+    fn serialize_boxed<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        (**self).serialize_boxed(ser)
+    }
+
+    fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        (**self).serialize_bare(ser)
+    }
+}
+
+impl<T: MtProtoSerialize + ?Sized> MtProtoSerialize for Box<T> {
+    fn serialize_boxed<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        (**self).serialize_boxed(ser)
+    }
+
+    fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        (**self).serialize_bare(ser)
+    }
+}
+
+impl<T: MtProtoDeserialize> MtProtoDeserialize for Box<T> {
+    fn deserialize_boxed<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<Box<T>> {
+        T::deserialize_boxed(de).map(Box::new)
+    }
+
+    fn deserialize_bare<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<Box<T>> {
+        T::deserialize_bare(de).map(Box::new)
+    }
+}
+
+impl MtProtoSerialize for str {
+    fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        Serialize::serialize(self, ser)
+    }
+}
+
+/// Elements are always boxed, like in `Vector<T>` of TL schemas. A boxed vector is prefixed with
+/// the vector type id, a bare one starts right with the count of elements.
+impl<T: MtProtoSerialize> MtProtoSerialize for [T] {
+    fn serialize_boxed<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        VECTOR_ID.serialize_bare(ser)?;
+        self.serialize_bare(ser)
+    }
+
+    fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        check_seq_len(self.len())?;
+        let len: u32 = safe_int_cast(self.len())?;
+        len.serialize_bare(ser)?;
+
+        for elem in self {
+            elem.serialize_boxed(ser)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: MtProtoSerialize> MtProtoSerialize for Vec<T> {
+    fn serialize_boxed<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        self[..].serialize_boxed(ser)
+    }
+
+    fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+        self[..].serialize_bare(ser)
+    }
+}
+
+impl<T: MtProtoDeserialize> MtProtoDeserialize for Vec<T> {
+    fn deserialize_boxed<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<Vec<T>> {
+        let type_id = u32::deserialize_bare(de)?;
+
+        if type_id != VECTOR_ID {
+            bail!(ErrorKind::InvalidTypeId { found: type_id, expected: <Vec<T> as Identifiable>::all_type_ids() });
+        }
+
+        Vec::deserialize_bare(de)
+    }
+
+    fn deserialize_bare<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<Vec<T>> {
+        let len = u32::deserialize_bare(de)?;
+        debug!("Deserializing native vector of len {}", len);
+
+        let mut result = Vec::with_capacity(cmp::min(safe_int_cast(len)?, MAX_PREALLOCATED_ELEMENTS));
+        for _ in 0..len {
+            result.push(T::deserialize_boxed(de)?);
+        }
+
+        Ok(result)
+    }
+}
+
+macro_rules! impl_native_for_tuple {
+    ($($ident:ident : $ty:ident ,)+) => {
+        impl<$($ty),*> MtProtoSerialize for ($($ty,)*)
+            where $($ty: MtProtoSerialize,)*
+        {
+            fn serialize_bare<W: io::Write>(&self, ser: &mut Serializer<W>) -> error::Result<()> {
+                let &($(ref $ident,)*) = self;
+                $( $ident.serialize_boxed(ser)?; )*
+                Ok(())
+            }
+        }
+
+        impl<$($ty),*> MtProtoDeserialize for ($($ty,)*)
+            where $($ty: MtProtoDeserialize,)*
+        {
+            fn deserialize_bare<R: io::Read>(de: &mut Deserializer<R>) -> error::Result<($($ty,)*)> {
+                $( let $ident = $ty::deserialize_boxed(de)?; )*
+                Ok(($($ident,)*))
+            }
+        }
+    };
+}
+
+impl_native_for_tuple! { x1: T1, }
+impl_native_for_tuple! { x1: T1, x2: T2, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6, x7: T7, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6, x7: T7, x8: T8, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6, x7: T7, x8: T8,
+                         x9: T9, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6, x7: T7, x8: T8,
+                         x9: T9, x10: T10, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6, x7: T7, x8: T8,
+                         x9: T9, x10: T10, x11: T11, }
+impl_native_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6, x7: T7, x8: T8,
+                         x9: T9, x10: T10, x11: T11, x12: T12, }
+
+
+/// Serialize a value through its Serde `Serialize` implementation.
+///
+/// This is what `#[mtproto(serde)]` fields are serialized with.
+pub fn serialize_with_serde<W, T>(value: &T, ser: &mut Serializer<W>) -> error::Result<()>
+    where W: io::Write,
+          T: ?Sized + Serialize,
+{
+    value.serialize(ser)
+}
+
+/// Deserialize a value through its Serde `Deserialize` implementation.
+///
+/// This is what `#[mtproto(serde)]` fields are deserialized with.
+pub fn deserialize_with_serde<R, T>(de: &mut Deserializer<R>) -> error::Result<T>
+    where R: io::Read,
+          T: DeserializeOwned,
+{
+    T::deserialize(de)
+}
+
+
+/// Serialize a boxed value natively as a byte vector of binary MTProto.
+pub fn to_bytes<T>(value: &T) -> error::Result<Vec<u8>>
+    where T: ?Sized + MtProtoSerialize
+{
+    let mut ser = Serializer::new(Vec::new());
+    value.serialize_boxed(&mut ser)?;
+
+    Ok(ser.into_writer())
+}
+
+/// Serialize a boxed value natively as binary MTProto into the IO stream.
+pub fn to_writer<W, T>(writer: W, value: &T) -> error::Result<()>
+    where W: io::Write,
+          T: ?Sized + MtProtoSerialize,
+{
+    let mut ser = Serializer::new(writer);
+    value.serialize_boxed(&mut ser)
+}
+
+/// Deserialize a boxed value natively from bytes of binary MTProto.
+pub fn from_bytes<T>(bytes: &[u8]) -> error::Result<T>
+    where T: MtProtoDeserialize
+{
+    let mut de = Deserializer::new(bytes, None);
+    T::deserialize_boxed(&mut de)
+}
+
+/// Deserialize a boxed value natively from an IO stream of binary MTProto.
+pub fn from_reader<R, T>(reader: R) -> error::Result<T>
+    where R: io::Read,
+          T: MtProtoDeserialize,
+{
+    let mut de = Deserializer::new(reader, None);
+    T::deserialize_boxed(&mut de)
+}
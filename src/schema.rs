@@ -0,0 +1,312 @@
+//! Descriptions of Rust types in terms of TL schemas.
+//!
+//! `TlDescribe` exposes TL constructors of a type: their names, ids, fields and result types.
+//! It is usually implemented by `#[derive(TlDescribe)]` from `serde_mtproto_derive` using the same
+//! `#[id = ...]` and `#[mtproto(bare)]` attributes as other derives, so that `Schema` can emit
+//! a `.tl` schema which matches the Rust definitions.
+//!
+//! The derive names constructors after the Rust type in `lowerCamelCase`, prefixing names of enum
+//! variants with the name of the enum, like `messageMediaEmpty` for `MessageMedia::Empty`. The
+//! following attributes customize the description:
+//!
+//! * `#[tl(name = "...")]` on a struct or an enum variant sets the constructor name;
+//! * `#[tl(type_name = "...")]` on a struct or an enum sets the type name, which is the Rust
+//!   type name by default;
+//! * `#[tl(function, result = "Type")]` on a struct describes it as a function which returns
+//!   `Type`, a Rust type which implements `TlDescribe`;
+//! * `#[tl(flags)]` on a field describes it as a field of flags (`#`);
+//! * `#[tl(flag = "flags.0")]` on a field describes it as present only if the given bit of the
+//!   given field of flags is set. `bool` fields become `true`, `Option<T>` fields are described
+//!   by `T`.
+//!
+//! ```
+//! # extern crate serde_mtproto;
+//! # #[macro_use] extern crate serde_mtproto_derive;
+//! use serde_mtproto::Schema;
+//!
+//! #[derive(TlDescribe)]
+//! enum User {
+//!     #[id = 0xd3bc4b7a]
+//!     Empty {
+//!         id: i64,
+//!     },
+//!     #[id = 0x2e13f4c3]
+//!     #[tl(name = "user")]
+//!     Full {
+//!         #[tl(flags)]
+//!         flags: u32,
+//!         #[tl(flag = "flags.0")]
+//!         bot: bool,
+//!         id: i64,
+//!         #[tl(flag = "flags.1")]
+//!         username: Option<String>,
+//!     },
+//! }
+//!
+//! #[derive(TlDescribe)]
+//! #[id = 0x0d91a548]
+//! #[tl(name = "users.getUsers", function, result = "Vec<User>")]
+//! struct GetUsers {
+//!     id: Vec<i64>,
+//! }
+//!
+//! let schema = Schema::new().add::<User>().add::<GetUsers>().to_string();
+//! assert_eq!(schema, "\
+//! userEmpty#d3bc4b7a id:long = User;
+//! user#2e13f4c3 flags:# bot:flags.0?true id:long username:flags.1?string = User;
+//!
+//! ---functions---
+//!
+//! users.getUsers#d91a548 id:Vector<long> = Vector<User>;
+//! ");
+//! ```
+
+use std::fmt;
+
+use serde_bytes::ByteBuf;
+
+use helpers::TlString;
+
+
+/// A type as it is written in TL schemas.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum TlType {
+    /// A bare built-in type like `int`, `long`, `double`, `string`, `bytes` or `true`.
+    Builtin(&'static str),
+    /// A boxed type like `Bool` or `User`.
+    Boxed(&'static str),
+    /// A bare version of a boxed type, written like `%User`.
+    Bare(&'static str),
+    /// A vector, written as `Vector<T>` if boxed and as `vector<T>` if bare.
+    Vector {
+        /// The type of elements.
+        elem: Box<TlType>,
+        /// Whether the vector is prefixed with its type id.
+        boxed: bool,
+    },
+    /// A field of flags for conditional fields, written as `#`.
+    Flags,
+    /// A field which is present only if a bit of a field of flags is set, written like
+    /// `flags.0?T`.
+    Conditional {
+        /// The name of the field of flags.
+        flags: &'static str,
+        /// The number of the bit.
+        bit: u32,
+        /// The type of the field if it is present.
+        ty: Box<TlType>,
+    },
+}
+
+impl TlType {
+    /// The bare version of this type.
+    pub fn bare(self) -> TlType {
+        match self {
+            TlType::Boxed(name) => TlType::Bare(name),
+            TlType::Vector { elem, .. } => TlType::Vector { elem: elem, boxed: false },
+            TlType::Conditional { flags, bit, ty } => TlType::Conditional { flags: flags, bit: bit, ty: Box::new(ty.bare()) },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for TlType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlType::Builtin(name) | TlType::Boxed(name) => f.write_str(name),
+            TlType::Bare(name) => write!(f, "%{}", name),
+            TlType::Vector { ref elem, boxed: true } => write!(f, "Vector<{}>", elem),
+            TlType::Vector { ref elem, boxed: false } => write!(f, "vector<{}>", elem),
+            TlType::Flags => f.write_str("#"),
+            TlType::Conditional { flags, bit, ref ty } => write!(f, "{}.{}?{}", flags, bit, ty),
+        }
+    }
+}
+
+/// A field of a TL constructor.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TlField {
+    /// The name of the field.
+    pub name: &'static str,
+    /// The type of the field.
+    pub ty: TlType,
+}
+
+/// A TL constructor of a type or a TL function.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TlConstructor {
+    /// The name of the constructor, like `user` or `users.getUsers`.
+    pub name: &'static str,
+    /// The id of the constructor.
+    pub id: u32,
+    /// Fields in the order they are serialized.
+    pub fields: Vec<TlField>,
+    /// The type constructed by a constructor or returned by a function.
+    pub result: TlType,
+    /// Whether this is a function rather than a constructor of a type.
+    pub is_function: bool,
+}
+
+impl fmt::Display for TlConstructor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}#{:x}", self.name, self.id)?;
+
+        for field in &self.fields {
+            write!(f, " {}:{}", field.name, field.ty)?;
+        }
+
+        write!(f, " = {};", self.result)
+    }
+}
+
+
+/// A Rust data structure which can be described in terms of TL schemas.
+pub trait TlDescribe {
+    /// The TL type of boxed values of this type.
+    fn tl_type() -> TlType;
+
+    /// TL constructors of this type, or the function it represents.
+    ///
+    /// Built-in TL types have no constructors to describe.
+    fn tl_constructors() -> Vec<TlConstructor> {
+        Vec::new()
+    }
+}
+
+macro_rules! impl_tl_describe {
+    ($($type:ty => $tl_type:expr,)*) => {
+        $(
+            impl TlDescribe for $type {
+                fn tl_type() -> TlType {
+                    $tl_type
+                }
+            }
+        )*
+    };
+}
+
+impl_tl_describe! {
+    bool => TlType::Boxed("Bool"),
+    i8 => TlType::Builtin("int"),
+    i16 => TlType::Builtin("int"),
+    i32 => TlType::Builtin("int"),
+    i64 => TlType::Builtin("long"),
+    u8 => TlType::Builtin("int"),
+    u16 => TlType::Builtin("int"),
+    u32 => TlType::Builtin("int"),
+    u64 => TlType::Builtin("long"),
+    i128 => TlType::Builtin("int128"),
+    u128 => TlType::Builtin("int128"),
+    f32 => TlType::Builtin("double"),
+    f64 => TlType::Builtin("double"),
+    String => TlType::Builtin("string"),
+    TlString => TlType::Builtin("string"),
+    ByteBuf => TlType::Builtin("bytes"),
+}
+
+impl<'a> TlDescribe for &'a str {
+    fn tl_type() -> TlType {
+        TlType::Builtin("string")
+    }
+}
+
+impl<T: TlDescribe> TlDescribe for Vec<T> {
+    fn tl_type() -> TlType {
+        TlType::Vector { elem: Box::new(T::tl_type()), boxed: true }
+    }
+}
+
+impl<T: TlDescribe> TlDescribe for Box<T> {
+    fn tl_type() -> TlType {
+        T::tl_type()
+    }
+
+    fn tl_constructors() -> Vec<TlConstructor> {
+        T::tl_constructors()
+    }
+}
+
+/// Describes the type of an optional value, which is a conditional field in TL schemas.
+impl<T: TlDescribe> TlDescribe for Option<T> {
+    fn tl_type() -> TlType {
+        T::tl_type()
+    }
+}
+
+
+/// A set of TL constructors and functions which can be written as a `.tl` schema.
+///
+/// Constructors are written in the order they are added, followed by functions in a separate
+/// section. Adding a constructor with an already known id again has no effect. Built-in types
+/// like `Bool` and `Vector t` are not described.
+#[derive(Clone, Debug, Default)]
+pub struct Schema {
+    constructors: Vec<TlConstructor>,
+    functions: Vec<TlConstructor>,
+}
+
+impl Schema {
+    /// Create an empty schema.
+    pub fn new() -> Schema {
+        Schema::default()
+    }
+
+    /// Add constructors of `T`, or the function it represents.
+    pub fn add<T: TlDescribe>(mut self) -> Schema {
+        for constructor in T::tl_constructors() {
+            self.add_constructor(constructor);
+        }
+
+        self
+    }
+
+    /// Add a single constructor or function.
+    pub fn add_constructor(&mut self, constructor: TlConstructor) {
+        let is_known = self.constructors.iter()
+            .chain(&self.functions)
+            .any(|c| c.id == constructor.id);
+
+        if is_known {
+            return;
+        }
+
+        if constructor.is_function {
+            self.functions.push(constructor);
+        } else {
+            self.constructors.push(constructor);
+        }
+    }
+
+    /// Constructors of types in the schema.
+    pub fn constructors(&self) -> &[TlConstructor] {
+        &self.constructors
+    }
+
+    /// Functions in the schema.
+    pub fn functions(&self) -> &[TlConstructor] {
+        &self.functions
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for constructor in &self.constructors {
+            writeln!(f, "{}", constructor)?;
+        }
+
+        if !self.functions.is_empty() {
+            if !self.constructors.is_empty() {
+                writeln!(f)?;
+            }
+
+            writeln!(f, "---functions---")?;
+            writeln!(f)?;
+
+            for function in &self.functions {
+                writeln!(f, "{}", function)?;
+            }
+        }
+
+        Ok(())
+    }
+}
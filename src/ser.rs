@@ -5,21 +5,34 @@ use std::io;
 use byteorder::{WriteBytesExt, LittleEndian};
 use serde::ser::{self, Serialize};
 
-use error::{self, SerErrorKind, SerSerdeType};
+use config::{CharEncoding, Config};
+use error::{self, ErrorKind, SerdeType};
+use helpers::Alignment;
 use identifiable::Identifiable;
-use utils::safe_int_cast;
+use utils::{PACKED_INTS_NEWTYPE_NAME, PACKED_NEWTYPE_NAME, STREAMED_BYTES_NEWTYPE_NAME, safe_int_cast};
 
 
 /// A structure for serializing Rust values into MTProto binary representation.
 #[derive(Debug)]
 pub struct Serializer<W: io::Write> {
     writer: W,
+    config: Config,
+    packed_ints: bool,
 }
 
 impl<W: io::Write> Serializer<W> {
     /// Create a MTProto serializer from an `io::Write`.
     pub fn new(writer: W) -> Serializer<W> {
-        Serializer { writer: writer }
+        Serializer::with_config(writer, Config::default())
+    }
+
+    /// Create a MTProto serializer from an `io::Write` which follows the given encoding policies.
+    pub fn with_config(writer: W, config: Config) -> Serializer<W> {
+        Serializer {
+            writer: writer,
+            config: config,
+            packed_ints: false,
+        }
     }
 
     /// Unwraps the `Serializer` and returns the underlying `io::Write`.
@@ -27,8 +40,34 @@ impl<W: io::Write> Serializer<W> {
         self.writer
     }
 
+    /// Write bytes as is, without length and padding.
+    ///
+    /// This is useful to reproduce previously captured serialized values.
+    pub fn write_raw_bytes(&mut self, bytes: &[u8]) -> error::Result<()> {
+        self.writer.write_all(bytes)?;
+        debug!("Serialized {} raw bytes", bytes.len());
+        Ok(())
+    }
+
     fn impl_serialize_bytes(&mut self, value: &[u8]) -> error::Result<()> {
-        let len = value.len();
+        if self.packed_ints {
+            // Little-endian integers of `IntVector` or `PackedInts`, written in one go
+            self.writer.write_all(value)?;
+
+            return Ok(());
+        }
+
+        let padding = self.write_bytes_len(value.len())?;
+
+        // Write each character in the string
+        self.writer.write_all(value)?;
+
+        self.write_bytes_padding(padding)
+    }
+
+    /// Write the length of a byte sequence and return the length of padding which must follow
+    /// the byte sequence.
+    fn write_bytes_len(&mut self, len: usize) -> error::Result<usize> {
         let rem;
 
         if len <= 253 {
@@ -53,22 +92,160 @@ impl<W: io::Write> Serializer<W> {
 
             rem = len % 4;
         } else {
-            bail!(SerErrorKind::StringTooLong(len));
+            bail!(ErrorKind::StringTooLong { len: len });
         }
 
-        // Write each character in the string
-        self.writer.write_all(value)?;
+        Ok((4 - rem) % 4)
+    }
 
+    fn write_bytes_padding(&mut self, padding: usize) -> error::Result<()> {
         // [...] string followed by 0 to 3 characters containing 0,
         // such that the overall length of the value be divisible by 4 [...]
-        if rem > 0 {
-            assert!(rem < 4);
-            let padding = 4 - rem;
+        if padding > 0 {
+            assert!(padding < 4);
             self.writer.write_uint::<LittleEndian>(0, padding)?;
         }
 
         Ok(())
     }
+
+    /// Serialize the value of `StreamedBytes` which is a tuple of the length followed by chunks
+    /// of bytes and write it as a single byte sequence.
+    fn serialize_streamed_bytes<T>(&mut self, value: &T) -> error::Result<()>
+        where T: ?Sized + Serialize
+    {
+        let mut streamed = StreamedBytesSerializer::new(self);
+        value.serialize(&mut streamed)?;
+
+        let (len, padding) = match streamed.len {
+            Some(len_and_padding) => len_and_padding,
+            None => bail!(ErrorKind::SerCustom("streamed bytes length is missing".to_owned())),
+        };
+
+        if streamed.written != u64::from(len) {
+            bail!(ErrorKind::SerCustom(
+                format!("streamed {} bytes instead of the declared {}", streamed.written, len)));
+        }
+
+        self.write_bytes_padding(padding)?;
+
+        debug!("Serialized {} streamed bytes", len);
+        Ok(())
+    }
+}
+
+impl<W: io::Write + io::Seek> Serializer<W> {
+    /// Serialize a value prepended with the size of its serialized representation, exactly like
+    /// `WithSize` does, but without computing the size beforehand.
+    ///
+    /// A placeholder is written first and back-patched with the real byte count after the value
+    /// has been serialized. This way the value is traversed only once and doesn't need to
+    /// implement `MtProtoSized`.
+    pub fn serialize_with_size<T>(&mut self, value: &T) -> error::Result<()>
+        where T: ?Sized + Serialize
+    {
+        let size_pos = self.writer.seek(io::SeekFrom::Current(0))?;
+        self.writer.write_u32::<LittleEndian>(0)?;
+
+        value.serialize(&mut *self)?;
+        let end_pos = self.writer.seek(io::SeekFrom::Current(0))?;
+
+        // `size_pos + 4 <= end_pos` since we wrote at least the placeholder after `size_pos`
+        let size = safe_int_cast::<u64, u32>(end_pos - size_pos - 4)?;
+
+        self.writer.seek(io::SeekFrom::Start(size_pos))?;
+        self.writer.write_u32::<LittleEndian>(size)?;
+        self.writer.seek(io::SeekFrom::Start(end_pos))?;
+
+        debug!("Back-patched size {} at position {}", size, size_pos);
+        Ok(())
+    }
+
+    /// Serialize a value prepended with its id and the size of its serialized representation,
+    /// exactly like `BoxedWithSize` does, but without computing the size beforehand.
+    ///
+    /// See `serialize_with_size` for details.
+    pub fn serialize_boxed_with_size<T>(&mut self, value: &T) -> error::Result<()>
+        where T: ?Sized + Identifiable + Serialize
+    {
+        self.writer.write_u32::<LittleEndian>(value.type_id())?;
+
+        if let Some(raw) = value.unknown_raw() {
+            // The size of a value of the catch-all variant is already known
+            self.writer.write_u32::<LittleEndian>(safe_int_cast(raw.len())?)?;
+            self.writer.write_all(raw)?;
+            debug!("Serialized {} raw bytes of a value with unknown type id", raw.len());
+
+            return Ok(());
+        }
+
+        self.serialize_with_size(value)
+    }
+}
+
+
+/// An `io::Write` sink which discards all data written to it and only counts
+/// the number of bytes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ByteCounter {
+    count: usize,
+}
+
+impl ByteCounter {
+    /// Create a new counter starting from 0 bytes.
+    pub fn new() -> ByteCounter {
+        ByteCounter { count: 0 }
+    }
+
+    /// Number of bytes written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count = self.count.checked_add(buf.len())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "byte count overflow"))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A serializer which runs values through the same MTProto encoding rules as
+/// `Serializer` does, but only counts the bytes instead of writing them.
+///
+/// Unlike `MtProtoSized`, the computed size can never disagree with the actual
+/// serialized representation.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate serde;
+/// # extern crate serde_mtproto;
+/// use serde::Serialize;
+/// use serde_mtproto::ser::{ByteCounter, SizeCounter};
+///
+/// # fn run() -> serde_mtproto::Result<()> {
+/// let mut counter = SizeCounter::new(ByteCounter::new());
+/// ("John Smith", 2000u16).serialize(&mut counter)?;
+///
+/// assert_eq!(counter.size(), 16);
+/// #     Ok(())
+/// # }
+/// # fn main() { run().unwrap(); }
+/// ```
+pub type SizeCounter = Serializer<ByteCounter>;
+
+impl Serializer<ByteCounter> {
+    /// Number of bytes counted so far.
+    pub fn size(&self) -> usize {
+        self.writer.count()
+    }
 }
 
 
@@ -120,9 +297,17 @@ impl<'a, W> ser::Serializer for &'a mut Serializer<W>
 
     impl_serialize_small_int!(u8,  serialize_u8,  u32, serialize_u32);
     impl_serialize_small_int!(u16, serialize_u16, u32, serialize_u32);
-    impl_serialize_big_int!(u32, serialize_u32, WriteBytesExt::write_u32<LittleEndian>);
+
+    fn serialize_u32(self, value: u32) -> error::Result<()> {
+        WriteBytesExt::write_u32::<LittleEndian>(&mut self.writer, value)?;
+        debug!("Serialized u32: {:#x}", value);
+        Ok(())
+    }
     impl_serialize_big_int!(u64, serialize_u64, WriteBytesExt::write_u64<LittleEndian>);
 
+    impl_serialize_big_int!(i128, serialize_i128, WriteBytesExt::write_i128<LittleEndian>);
+    impl_serialize_big_int!(u128, serialize_u128, WriteBytesExt::write_u128<LittleEndian>);
+
     fn serialize_f32(self, value: f32) -> error::Result<()> {
         // There is only one floating-point type, and it's double precision
         WriteBytesExt::write_f64::<LittleEndian>(&mut self.writer, f64::from(value))?;
@@ -136,8 +321,21 @@ impl<'a, W> ser::Serializer for &'a mut Serializer<W>
         Ok(())
     }
 
-    fn serialize_char(self, _value: char) -> error::Result<()> {
-        bail!(SerErrorKind::UnsupportedSerdeType(SerSerdeType::Char));
+    fn serialize_char(self, value: char) -> error::Result<()> {
+        match self.config.char_encoding {
+            CharEncoding::Unsupported => {
+                bail!(ErrorKind::UnsupportedSerdeType { serde_type: SerdeType::Char });
+            },
+            CharEncoding::Int => {
+                WriteBytesExt::write_u32::<LittleEndian>(&mut self.writer, u32::from(value))?;
+            },
+            CharEncoding::Utf8 => {
+                self.impl_serialize_bytes(value.encode_utf8(&mut [0; 4]).as_bytes())?;
+            },
+        }
+
+        debug!("Serialized char: {:?}", value);
+        Ok(())
     }
 
     fn serialize_str(self, value: &str) -> error::Result<()> {
@@ -153,17 +351,22 @@ impl<'a, W> ser::Serializer for &'a mut Serializer<W>
     }
 
     fn serialize_none(self) -> error::Result<()> {
-        bail!(SerErrorKind::UnsupportedSerdeType(SerSerdeType::None));
+        // An absent conditional field (`flags.N?T`) takes no space at all
+        debug!("Serialized none");
+        Ok(())
     }
 
-    fn serialize_some<T>(self, _value: &T) -> error::Result<()>
+    fn serialize_some<T>(self, value: &T) -> error::Result<()>
         where T: ?Sized + Serialize
     {
-        bail!(SerErrorKind::UnsupportedSerdeType(SerSerdeType::Some));
+        // A present conditional field (`flags.N?T`) is written as is
+        value.serialize(&mut *self)?;
+        debug!("Serialized some");
+        Ok(())
     }
 
     fn serialize_unit(self) -> error::Result<()> {
-        bail!(SerErrorKind::UnsupportedSerdeType(SerSerdeType::Unit));
+        bail!(ErrorKind::UnsupportedSerdeType { serde_type: SerdeType::Unit });
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> error::Result<()> {
@@ -183,6 +386,25 @@ impl<'a, W> ser::Serializer for &'a mut Serializer<W>
     fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> error::Result<()>
         where T: ?Sized + Serialize
     {
+        if name == STREAMED_BYTES_NEWTYPE_NAME {
+            return self.serialize_streamed_bytes(value);
+        }
+
+        if name == PACKED_NEWTYPE_NAME {
+            let bytes = self.config.to_bytes(value)?;
+            debug!("Serializing {} bytes of a packed value", bytes.len());
+
+            return self.impl_serialize_bytes(&bytes);
+        }
+
+        if name == PACKED_INTS_NEWTYPE_NAME {
+            self.packed_ints = true;
+            let result = value.serialize(&mut *self);
+            self.packed_ints = false;
+
+            return result;
+        }
+
         debug!("Serializing newtype variant {}", name);
         value.serialize(self)
     }
@@ -204,7 +426,7 @@ impl<'a, W> ser::Serializer for &'a mut Serializer<W>
             debug!("Serializing seq of len {}", len);
             SerializeFixedLengthSeq::with_serialize_len(self, safe_int_cast(len)?)
         } else {
-            bail!(SerErrorKind::SeqsWithUnknownLengthUnsupported);
+            bail!(ErrorKind::SeqsWithUnknownLengthUnsupported);
         }
     }
 
@@ -237,7 +459,7 @@ impl<'a, W> ser::Serializer for &'a mut Serializer<W>
             debug!("Serializing map of len {}", len);
             SerializeFixedLengthMap::with_serialize_len(self, safe_int_cast(len)?)
         } else {
-            bail!(SerErrorKind::MapsWithUnknownLengthUnsupported);
+            bail!(ErrorKind::MapsWithUnknownLengthUnsupported);
         }
     }
 
@@ -295,7 +517,7 @@ impl<'a, W: io::Write> SerializeFixedLengthSeq<'a, W> {
             debug!("{}::serialize_element() is called when no elements is left to serialize",
                 serializer_type);
 
-            bail!(SerErrorKind::ExcessElements(self.len));
+            bail!(ErrorKind::ExcessElements { expected: self.len });
         }
 
         if let Some(key) = key {
@@ -309,7 +531,7 @@ impl<'a, W: io::Write> SerializeFixedLengthSeq<'a, W> {
 
     fn impl_serialize_end(self, data_type: &'static str) -> error::Result<()> {
         if self.next_index < self.len {
-            bail!(SerErrorKind::NotEnoughElements(self.next_index, self.len))
+            bail!(ErrorKind::NotEnoughElements { found: self.next_index, expected: self.len })
         }
 
         // `self.index > self.len` here is a programming error
@@ -430,6 +652,8 @@ pub struct SerializeFixedLengthMap<'a, W: 'a + io::Write> {
     ser: &'a mut Serializer<W>,
     len: u32,
     next_index: u32,
+    /// Serialized keys and values buffered until the end of the map if it is to be sorted.
+    sorted_entries: Option<Vec<(Vec<u8>, Vec<u8>)>>,
 }
 
 impl<'a, W: io::Write> SerializeFixedLengthMap<'a, W> {
@@ -437,15 +661,27 @@ impl<'a, W: io::Write> SerializeFixedLengthMap<'a, W> {
                           len: u32)
                          -> error::Result<SerializeFixedLengthMap<'a, W>> {
         ser::Serializer::serialize_u32(&mut *ser, len)?;
+        let sorted_entries = if ser.config.sort_maps { Some(Vec::new()) } else { None };
 
         Ok(SerializeFixedLengthMap {
             ser: ser,
             len: len,
             next_index: 0,
+            sorted_entries: sorted_entries,
         })
     }
 }
 
+/// Serialize a key or a value of a sorted map into a separate buffer.
+fn serialize_to_buffer<T>(value: &T, config: Config) -> error::Result<Vec<u8>>
+    where T: ?Sized + Serialize
+{
+    let mut ser = Serializer::with_config(Vec::new(), config);
+    value.serialize(&mut ser)?;
+
+    Ok(ser.writer)
+}
+
 impl<'a, W> ser::SerializeMap for SerializeFixedLengthMap<'a, W>
     where W: io::Write
 {
@@ -460,10 +696,15 @@ impl<'a, W> ser::SerializeMap for SerializeFixedLengthMap<'a, W>
         } else {
             debug!("SerializeMap::serialize_key() is called when no elements is left to serialize");
 
-            bail!(SerErrorKind::ExcessElements(self.len));
+            bail!(ErrorKind::ExcessElements { expected: self.len });
         }
 
         debug!("Serializing key");
+        if let Some(ref mut entries) = self.sorted_entries {
+            entries.push((serialize_to_buffer(key, self.ser.config)?, Vec::new()));
+            return Ok(());
+        }
+
         key.serialize(&mut *self.ser)
     }
 
@@ -471,16 +712,180 @@ impl<'a, W> ser::SerializeMap for SerializeFixedLengthMap<'a, W>
         where T: ?Sized + Serialize
     {
         debug!("Serializing value");
+        if let Some(ref mut entries) = self.sorted_entries {
+            if let Some(entry) = entries.last_mut() {
+                entry.1 = serialize_to_buffer(value, self.ser.config)?;
+            }
+
+            return Ok(());
+        }
+
         value.serialize(&mut *self.ser)
     }
 
     fn end(self) -> error::Result<()> {
+        if let Some(mut entries) = self.sorted_entries {
+            entries.sort();
+
+            for (key, value) in entries {
+                self.ser.writer.write_all(&key)?;
+                self.ser.writer.write_all(&value)?;
+            }
+
+            debug!("Wrote map entries sorted by keys");
+        }
+
         debug!("Finished serializing map");
         Ok(())
     }
 }
 
 
+/// Serializer for the value of `StreamedBytes`: a tuple of the byte sequence length, which is
+/// written right away, followed by chunks of bytes.
+struct StreamedBytesSerializer<'a, W: 'a + io::Write> {
+    ser: &'a mut Serializer<W>,
+    /// The length and the padding of the byte sequence, once it is written.
+    len: Option<(u32, usize)>,
+    written: u64,
+}
+
+impl<'a, W: io::Write> StreamedBytesSerializer<'a, W> {
+    fn new(ser: &'a mut Serializer<W>) -> StreamedBytesSerializer<'a, W> {
+        StreamedBytesSerializer {
+            ser: ser,
+            len: None,
+            written: 0,
+        }
+    }
+}
+
+fn unexpected_streamed_bytes_value() -> error::Error {
+    ErrorKind::SerCustom("streamed bytes must be a tuple of the length followed by chunks of bytes".to_owned())
+        .into()
+}
+
+macro_rules! impl_unexpected_streamed_bytes_values {
+    ($($method:ident($($arg:ident: $type:ty),*) -> $ret:ty;)*) => {
+        $(
+            fn $method(self, $($arg: $type),*) -> error::Result<$ret> {
+                $(let _ = $arg;)*
+                Err(unexpected_streamed_bytes_value())
+            }
+        )*
+    };
+}
+
+impl<'a, 'b, W> ser::Serializer for &'a mut StreamedBytesSerializer<'b, W>
+    where W: io::Write
+{
+    type Ok = ();
+    type Error = error::Error;
+
+    type SerializeSeq = ser::Impossible<(), error::Error>;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = ser::Impossible<(), error::Error>;
+    type SerializeTupleVariant = ser::Impossible<(), error::Error>;
+    type SerializeMap = ser::Impossible<(), error::Error>;
+    type SerializeStruct = ser::Impossible<(), error::Error>;
+    type SerializeStructVariant = ser::Impossible<(), error::Error>;
+
+    fn serialize_u32(self, value: u32) -> error::Result<()> {
+        if self.len.is_some() {
+            return Err(unexpected_streamed_bytes_value());
+        }
+
+        let padding = self.ser.write_bytes_len(safe_int_cast(value)?)?;
+        self.len = Some((value, padding));
+        debug!("Serialized streamed bytes length: {}", value);
+
+        Ok(())
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> error::Result<()> {
+        if self.len.is_none() {
+            return Err(unexpected_streamed_bytes_value());
+        }
+
+        self.ser.writer.write_all(value)?;
+        self.written += value.len() as u64;
+
+        Ok(())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> error::Result<Self> {
+        Ok(self)
+    }
+
+    impl_unexpected_streamed_bytes_values! {
+        serialize_bool(v: bool) -> ();
+        serialize_i8(v: i8) -> ();
+        serialize_i16(v: i16) -> ();
+        serialize_i32(v: i32) -> ();
+        serialize_i64(v: i64) -> ();
+        serialize_u8(v: u8) -> ();
+        serialize_u16(v: u16) -> ();
+        serialize_u64(v: u64) -> ();
+        serialize_f32(v: f32) -> ();
+        serialize_f64(v: f64) -> ();
+        serialize_char(v: char) -> ();
+        serialize_str(v: &str) -> ();
+        serialize_none() -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(name: &'static str) -> ();
+        serialize_unit_variant(name: &'static str, variant_index: u32, variant: &'static str) -> ();
+        serialize_seq(len: Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple_struct(name: &'static str, len: usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(name: &'static str, variant_index: u32, variant: &'static str, len: usize)
+            -> Self::SerializeTupleVariant;
+        serialize_map(len: Option<usize>) -> Self::SerializeMap;
+        serialize_struct(name: &'static str, len: usize) -> Self::SerializeStruct;
+        serialize_struct_variant(name: &'static str, variant_index: u32, variant: &'static str, len: usize)
+            -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> error::Result<()>
+        where T: ?Sized + Serialize
+    {
+        Err(unexpected_streamed_bytes_value())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, _value: &T) -> error::Result<()>
+        where T: ?Sized + Serialize
+    {
+        Err(unexpected_streamed_bytes_value())
+    }
+
+    fn serialize_newtype_variant<T>(self,
+                                    _name: &'static str,
+                                    _variant_index: u32,
+                                    _variant: &'static str,
+                                    _value: &T)
+                                   -> error::Result<()>
+        where T: ?Sized + Serialize
+    {
+        Err(unexpected_streamed_bytes_value())
+    }
+}
+
+impl<'a, 'b, W> ser::SerializeTuple for &'a mut StreamedBytesSerializer<'b, W>
+    where W: io::Write
+{
+    type Ok = ();
+    type Error = error::Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> error::Result<()>
+        where T: ?Sized + Serialize
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> error::Result<()> {
+        Ok(())
+    }
+}
+
+
 /// Serialize the given data structure as a byte vector of binary MTProto.
 pub fn to_bytes<T>(value: &T) -> error::Result<Vec<u8>>
     where T: Serialize
@@ -491,15 +896,26 @@ pub fn to_bytes<T>(value: &T) -> error::Result<Vec<u8>>
     Ok(ser.writer)
 }
 
+/// Compute the size of MTProto binary representation of the given data structure by running it
+/// through `SizeCounter`.
+pub fn serialized_size<T>(value: &T) -> error::Result<usize>
+    where T: ?Sized + Serialize
+{
+    let mut counter = SizeCounter::new(ByteCounter::new());
+    value.serialize(&mut counter)?;
+
+    Ok(counter.size())
+}
+
 /// Serialize bytes with padding to 16 bytes as a byte vector of binary MTProto.
 pub fn unsized_bytes_pad_to_bytes(value: &[u8]) -> error::Result<Vec<u8>> {
-    let padding = (16 - value.len() % 16) % 16;
-    let mut result = Vec::with_capacity(value.len() + padding);
+    unsized_bytes_pad_to_bytes_with_alignment(value, Alignment::Sixteen)
+}
 
-    result.extend(value);
-    for _ in 0..padding {
-        result.push(0);
-    }
+/// Serialize bytes with padding to `alignment` as a byte vector of binary MTProto.
+pub fn unsized_bytes_pad_to_bytes_with_alignment(value: &[u8], alignment: Alignment) -> error::Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(alignment.padded_len(value.len()));
+    unsized_bytes_pad_to_writer_with_alignment(&mut result, value, alignment)?;
 
     Ok(result)
 }
@@ -515,11 +931,42 @@ pub fn to_writer<W, T>(writer: W, value: &T) -> error::Result<()>
     Ok(())
 }
 
+/// Serialize the given data structure prepended with its serialized size as a byte vector of
+/// binary MTProto.
+///
+/// The output is the same as for `to_bytes(&WithSize::new(value)?)`, but the value is
+/// traversed only once and doesn't need to implement `MtProtoSized`.
+pub fn to_bytes_with_size<T>(value: &T) -> error::Result<Vec<u8>>
+    where T: ?Sized + Serialize
+{
+    Config::default().to_bytes_with_size(value)
+}
+
+/// Serialize the given data structure prepended with its serialized size as binary MTProto into
+/// the seekable IO stream.
+///
+/// The output is the same as for `to_writer(writer, &WithSize::new(value)?)`, but the value is
+/// traversed only once and doesn't need to implement `MtProtoSized`.
+pub fn to_writer_with_size<W, T>(writer: W, value: &T) -> error::Result<()>
+    where W: io::Write + io::Seek,
+          T: ?Sized + Serialize,
+{
+    Config::default().to_writer_with_size(writer, value)
+}
+
 /// Serialize bytes with padding to 16 bytes into the IO stream.
-pub fn unsized_bytes_pad_to_writer<W>(mut writer: W, value: &[u8]) -> error::Result<()>
+pub fn unsized_bytes_pad_to_writer<W>(writer: W, value: &[u8]) -> error::Result<()>
+    where W: io::Write
+{
+    unsized_bytes_pad_to_writer_with_alignment(writer, value, Alignment::Sixteen)
+}
+
+/// Serialize bytes with padding to `alignment` into the IO stream.
+pub fn unsized_bytes_pad_to_writer_with_alignment<W>(mut writer: W, value: &[u8], alignment: Alignment)
+    -> error::Result<()>
     where W: io::Write
 {
-    let padding = (16 - value.len() % 16) % 16;
+    let padding = alignment.padded_len(value.len()) - value.len();
 
     writer.write_all(value)?;
     for _ in 0..padding {
@@ -69,14 +69,17 @@
 //!
 //! The derived implementation is the same as the one shown above.
 
-use std::cmp;
-use std::collections::{HashMap, BTreeMap};
+use std::borrow::{Cow, ToOwned};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::hash::{BuildHasher, Hash};
-use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
 
+use serde::ser::Serialize;
 use serde_bytes::{ByteBuf, Bytes};
 
 use error::{self, ErrorKind};
+use ser::serialized_size;
 use utils::check_seq_len;
 
 
@@ -104,6 +107,28 @@ pub trait MtProtoSized {
 }
 
 
+/// Assert that `MtProtoSized::size_hint` of the given value is equal to the size of its actual
+/// serialized representation as computed by `serialized_size`.
+///
+/// Intended to be used in tests and debug assertions to ensure that a manually implemented or
+/// derived `MtProtoSized` doesn't disagree with the `Serialize` implementation.
+///
+/// # Panics
+///
+/// Panics if sizes differ or if only one of them can be computed.
+pub fn assert_size_hint_matches<T>(value: &T)
+    where T: ?Sized + MtProtoSized + Serialize
+{
+    match (value.size_hint(), serialized_size(value)) {
+        (Ok(hint), Ok(size)) => assert!(hint == size,
+            "size_hint() is {} bytes, but the value serializes to {} bytes", hint, size),
+        (Err(_), Err(_)) => (),
+        (Ok(hint), Err(e)) => panic!("size_hint() is {} bytes, but the value fails to serialize: {}", hint, e),
+        (Err(e), Ok(size)) => panic!("size_hint() failed: {}, but the value serializes to {} bytes", e, size),
+    }
+}
+
+
 macro_rules! impl_mt_proto_sized_for_primitives {
     ($($type:ty => $size:expr,)*) => {
         $(
@@ -119,15 +144,14 @@ macro_rules! impl_mt_proto_sized_for_primitives {
 impl_mt_proto_sized_for_primitives! {
     bool => BOOL_SIZE,
 
-    // Minimum MTProto integer size is 4 bytes
-    isize => cmp::max(mem::size_of::<isize>(), INT_SIZE),
+    // Serde always serializes `isize` and `usize` as 64-bit integers
+    isize => LONG_SIZE,
     i8    => INT_SIZE,
     i16   => INT_SIZE,
     i32   => INT_SIZE,
     i64   => LONG_SIZE,
 
-    // Same here
-    usize => cmp::max(mem::size_of::<usize>(), INT_SIZE),
+    usize => LONG_SIZE,
     u8    => INT_SIZE,
     u16   => INT_SIZE,
     u32   => INT_SIZE,
@@ -135,6 +159,9 @@ impl_mt_proto_sized_for_primitives! {
 
     f32 => DOUBLE_SIZE,
     f64 => DOUBLE_SIZE,
+
+    i128 => INT128_SIZE,
+    u128 => INT128_SIZE,
 }
 
 #[cfg(feature = "extprim")]
@@ -154,7 +181,7 @@ pub fn size_hint_from_byte_seq_len(len: usize) -> error::Result<usize> {
     } else if len <= 0xff_ff_ff {
         (4, len, (4 - len % 4) % 4)
     } else {
-        bail!(ErrorKind::StringTooLong(len));
+        bail!(ErrorKind::StringTooLong { len: len });
     };
 
     let size = len_info + data + padding;
@@ -163,7 +190,7 @@ pub fn size_hint_from_byte_seq_len(len: usize) -> error::Result<usize> {
     Ok(size)
 }
 
-impl<'a> MtProtoSized for &'a str {
+impl MtProtoSized for str {
     fn size_hint(&self) -> error::Result<usize> {
         size_hint_from_byte_seq_len(self.as_bytes().len())
     }
@@ -187,18 +214,56 @@ impl<T: ?Sized + MtProtoSized> MtProtoSized for Box<T> {
     }
 }
 
-impl<'a, T: MtProtoSized> MtProtoSized for &'a [T] {
+impl<T: ?Sized + MtProtoSized> MtProtoSized for Rc<T> {
     fn size_hint(&self) -> error::Result<usize> {
-        // If len >= 2 ** 32, it's not serializable at all.
-        check_seq_len(self.len())?;
+        (**self).size_hint()
+    }
+}
 
-        let mut result = 4;    // 4 for slice length
+impl<T: ?Sized + MtProtoSized> MtProtoSized for Arc<T> {
+    fn size_hint(&self) -> error::Result<usize> {
+        (**self).size_hint()
+    }
+}
 
-        for elem in self.iter() {
-            result += elem.size_hint()?;
+impl<'a, B> MtProtoSized for Cow<'a, B>
+    where B: ?Sized + ToOwned + MtProtoSized
+{
+    fn size_hint(&self) -> error::Result<usize> {
+        (**self).size_hint()
+    }
+}
+
+/// Size of a conditional field, which takes no space at all if it is absent.
+impl<T: MtProtoSized> MtProtoSized for Option<T> {
+    fn size_hint(&self) -> error::Result<usize> {
+        match *self {
+            Some(ref value) => value.size_hint(),
+            None => Ok(0),
         }
+    }
+}
 
-        Ok(result)
+/// Helper function for sequences with a known number of elements.
+fn size_hint_from_seq<I>(len: usize, elems: I) -> error::Result<usize>
+    where I: IntoIterator,
+          I::Item: MtProtoSized,
+{
+    // If len >= 2 ** 32, it's not serializable at all.
+    check_seq_len(len)?;
+
+    let mut result = 4;    // 4 for sequence length
+
+    for elem in elems {
+        result += elem.size_hint()?;
+    }
+
+    Ok(result)
+}
+
+impl<T: MtProtoSized> MtProtoSized for [T] {
+    fn size_hint(&self) -> error::Result<usize> {
+        size_hint_from_seq(self.len(), self)
     }
 }
 
@@ -208,6 +273,27 @@ impl<T: MtProtoSized> MtProtoSized for Vec<T> {
     }
 }
 
+impl<T: MtProtoSized> MtProtoSized for VecDeque<T> {
+    fn size_hint(&self) -> error::Result<usize> {
+        size_hint_from_seq(self.len(), self)
+    }
+}
+
+impl<T, S> MtProtoSized for HashSet<T, S>
+    where T: Eq + Hash + MtProtoSized,
+          S: BuildHasher,
+{
+    fn size_hint(&self) -> error::Result<usize> {
+        size_hint_from_seq(self.len(), self)
+    }
+}
+
+impl<T: MtProtoSized> MtProtoSized for BTreeSet<T> {
+    fn size_hint(&self) -> error::Result<usize> {
+        size_hint_from_seq(self.len(), self)
+    }
+}
+
 impl<K, V, S> MtProtoSized for HashMap<K, V, S>
     where K: Eq + Hash + MtProtoSized,
           V: MtProtoSized,
@@ -297,33 +383,15 @@ impl_mt_proto_sized_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6,
 impl_mt_proto_sized_for_tuple! { x1: T1, x2: T2, x3: T3, x4: T4, x5: T5, x6: T6, x7: T7, x8: T8,
                                  x9: T9, x10: T10, x11: T11, x12: T12, }
 
-macro_rules! impl_mt_proto_sized_for_arrays {
-    (__impl 0) => {
-        impl<T> MtProtoSized for [T; 0] {
-            fn size_hint(&self) -> error::Result<usize> {
-                Ok(0)
-            }
-        }
-    };
-
-    (__impl $size:expr) => {
-        impl<T: MtProtoSized> MtProtoSized for [T; $size] {
-            fn size_hint(&self) -> error::Result<usize> {
-                let mut result = 0;
-
-                for elem in self {
-                    result += elem.size_hint()?;
-                }
+// Arrays are serialized as tuples, without length
+impl<T: MtProtoSized, const N: usize> MtProtoSized for [T; N] {
+    fn size_hint(&self) -> error::Result<usize> {
+        let mut result = 0;
 
-                Ok(result)
-            }
+        for elem in self {
+            result += elem.size_hint()?;
         }
-    };
 
-    ($($size:expr),*) => {
-        $( impl_mt_proto_sized_for_arrays!(__impl $size); )*
-    };
+        Ok(result)
+    }
 }
-
-impl_mt_proto_sized_for_arrays!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
-                                19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32);
@@ -1,29 +1,50 @@
 use num_traits::cast::cast;
-use num_traits::float::Float;
 use num_traits::int::PrimInt;
 use num_traits::sign::Unsigned;
 
-use error::{self, ErrorKind, ResultExt};
+use error::{self, ErrorKind};
 
 
+/// Newtype struct name used by `WithSize` and `BoxedWithSize` to announce the declared size of
+/// the following value to `de::Deserializer` which then enforces it as a hard limit.
+pub const DECLARED_SIZE_NEWTYPE_NAME: &str = "$serde_mtproto::DeclaredSize";
+
+/// Newtype struct name used by `Raw` and `WithRaw` to ask `de::Deserializer` to capture the
+/// bytes of the following value.
+pub const RAW_NEWTYPE_NAME: &str = "$serde_mtproto::Raw";
+
+/// Newtype struct name used by `Packed` to ask `ser::Serializer` and `de::Deserializer` to
+/// [de]serialize the following value nested in a byte sequence with the same `Config`.
+pub const PACKED_NEWTYPE_NAME: &str = "$serde_mtproto::Packed";
+
+/// Newtype struct name used by `UnsizedByteBufSeed::to_end` to ask `de::Deserializer` for all
+/// bytes up to the declared size limit or, if there is none, to the end of input.
+pub const REMAINING_BYTES_NEWTYPE_NAME: &str = "$serde_mtproto::RemainingBytes";
+
+/// Newtype struct name used by `Boxed` and `BoxedWithSize` to ask `de::Deserializer` for raw bytes
+/// of a value with unknown type id, which are only available up to the declared size limit.
+pub const UNKNOWN_VALUE_NEWTYPE_NAME: &str = "$serde_mtproto::UnknownValue";
+
+/// Newtype struct name used by `StreamedBytes` to ask `ser::Serializer` to write the following
+/// length and chunks of bytes as a single byte sequence.
+pub const STREAMED_BYTES_NEWTYPE_NAME: &str = "$serde_mtproto::StreamedBytes";
+
+/// Newtype and tuple struct name used by `IntVector` and `PackedInts` to ask `ser::Serializer` to
+/// write the following bytes as is and `de::Deserializer` to read all 32-bit words of the tuple
+/// struct at once.
+pub const PACKED_INTS_NEWTYPE_NAME: &str = "$serde_mtproto::PackedInts";
+
 pub fn safe_int_cast<T: PrimInt + Copy, U: PrimInt>(n: T) -> error::Result<U> {
     cast(n).ok_or_else(|| {
         let upcasted = cast::<T, u64>(n).unwrap();    // Shouldn't panic
-        ErrorKind::IntegerCast(upcasted).into()
-    })
-}
-
-pub fn safe_float_cast<T: Float + Copy, U: Float>(n: T) -> error::Result<U> {
-    cast(n).ok_or_else(|| {
-        let upcasted = cast::<T, f64>(n).unwrap();    // Shouldn't panic
-        ErrorKind::FloatCast(upcasted).into()
+        ErrorKind::IntegerCast { value: upcasted }.into()
     })
 }
 
 pub fn check_seq_len(len: usize) -> error::Result<()> {
     safe_int_cast::<usize, u32>(len)
         .map(|_| ())
-        .chain_err(|| ErrorKind::SeqTooLong(len))
+        .map_err(|_| ErrorKind::SeqTooLong { len: len }.into())
 }
 
 pub fn safe_uint_cmp<T, U>(x: T, y: U) -> bool
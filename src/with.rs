@@ -0,0 +1,365 @@
+//! Adapters for `#[serde(with = "...")]` which encode common Rust types as MTProto values.
+//!
+//! Each module provides `serialize` and `deserialize` functions to be used by Serde and a
+//! `size_hint` function which computes the size of the same representation for `MtProtoSized`
+//! implementations.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate serde_mtproto;
+//! # #[macro_use] extern crate serde_derive;
+//! use std::net::Ipv4Addr;
+//! use std::time::SystemTime;
+//!
+//! use serde_mtproto::with::msg_id::MsgId;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Session {
+//!     #[serde(with = "serde_mtproto::with::unix_time")]
+//!     date: SystemTime,
+//!     #[serde(with = "serde_mtproto::with::ipv4")]
+//!     ip: Ipv4Addr,
+//!     #[serde(with = "serde_mtproto::with::msg_id")]
+//!     first_msg_id: MsgId,
+//!     #[serde(with = "serde_mtproto::with::tl_bool")]
+//!     official_app: bool,
+//! }
+//!
+//! impl serde_mtproto::MtProtoSized for Session {
+//!     fn size_hint(&self) -> serde_mtproto::Result<usize> {
+//!         Ok(serde_mtproto::with::unix_time::size_hint(&self.date)? +
+//!            serde_mtproto::with::ipv4::size_hint(&self.ip)? +
+//!            serde_mtproto::with::msg_id::size_hint(&self.first_msg_id)? +
+//!            serde_mtproto::with::tl_bool::size_hint(&self.official_app)?)
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use error::{self, ErrorKind};
+
+
+/// Convert seconds since the Unix epoch into an `int` timestamp.
+fn int_timestamp(secs: i64) -> error::Result<i32> {
+    if secs < i64::from(i32::min_value()) || secs > i64::from(i32::max_value()) {
+        bail!(ErrorKind::SerCustom(format!("timestamp {} doesn't fit into `int`", secs)));
+    }
+
+    // Cannot truncate since the range is checked above
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    Ok(secs as i32)
+}
+
+
+/// `SystemTime` as an `int` Unix timestamp in seconds.
+///
+/// Fractions of a second are rounded down when serialized.
+pub mod unix_time {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde::de::{Deserialize, Deserializer};
+    use serde::ser::{Error as SerError, Serializer};
+
+    use error;
+    use sized::INT_SIZE;
+    use utils::safe_int_cast;
+
+    /// Serialize `SystemTime` as an `int` timestamp.
+    pub fn serialize<S>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let timestamp = to_timestamp(time).map_err(S::Error::custom)?;
+        serializer.serialize_i32(timestamp)
+    }
+
+    /// Deserialize `SystemTime` from an `int` timestamp.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+        where D: Deserializer<'de>
+    {
+        let timestamp = i32::deserialize(deserializer)?;
+
+        // `i32` always fits into `i64` and its absolute value into `u64`
+        #[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+        let secs = i64::from(timestamp).abs() as u64;
+
+        if timestamp >= 0 {
+            Ok(UNIX_EPOCH + Duration::from_secs(secs))
+        } else {
+            Ok(UNIX_EPOCH - Duration::from_secs(secs))
+        }
+    }
+
+    /// Size of `SystemTime` serialized as an `int` timestamp.
+    pub fn size_hint(_time: &SystemTime) -> error::Result<usize> {
+        Ok(INT_SIZE)
+    }
+
+    fn to_timestamp(time: &SystemTime) -> error::Result<i32> {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => safe_int_cast::<u64, i64>(after.as_secs())?,
+            Err(e) => {
+                // Round down, i.e. away from the epoch
+                let before = e.duration();
+                let secs = before.as_secs() + u64::from(before.subsec_nanos() > 0);
+                -safe_int_cast::<u64, i64>(secs)?
+            },
+        };
+
+        super::int_timestamp(secs)
+    }
+}
+
+/// `chrono::DateTime<Utc>` as an `int` Unix timestamp in seconds.
+///
+/// Fractions of a second are rounded down when serialized. This module is available with the
+/// `chrono` feature enabled.
+#[cfg(feature = "chrono")]
+pub mod chrono_unix_time {
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde::de::{Deserialize, Deserializer, Error as DeError};
+    use serde::ser::{Error as SerError, Serializer};
+
+    use error;
+    use sized::INT_SIZE;
+
+    /// Serialize `DateTime<Utc>` as an `int` timestamp.
+    pub fn serialize<S>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let timestamp = super::int_timestamp(time.timestamp()).map_err(S::Error::custom)?;
+        serializer.serialize_i32(timestamp)
+    }
+
+    /// Deserialize `DateTime<Utc>` from an `int` timestamp.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let timestamp = i32::deserialize(deserializer)?;
+
+        Utc.timestamp_opt(i64::from(timestamp), 0)
+            .single()
+            .ok_or_else(|| D::Error::custom(format!("invalid timestamp {}", timestamp)))
+    }
+
+    /// Size of `DateTime<Utc>` serialized as an `int` timestamp.
+    pub fn size_hint(_time: &DateTime<Utc>) -> error::Result<usize> {
+        Ok(INT_SIZE)
+    }
+}
+
+/// `Ipv4Addr` as an `int` holding the address in the host byte order, as in `ipPort`.
+pub mod ipv4 {
+    use std::net::Ipv4Addr;
+
+    use serde::de::{Deserialize, Deserializer};
+    use serde::ser::Serializer;
+
+    use error;
+    use sized::INT_SIZE;
+
+    /// Serialize `Ipv4Addr` as an `int`.
+    pub fn serialize<S>(addr: &Ipv4Addr, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_u32(u32::from(*addr))
+    }
+
+    /// Deserialize `Ipv4Addr` from an `int`.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Ipv4Addr, D::Error>
+        where D: Deserializer<'de>
+    {
+        u32::deserialize(deserializer).map(Ipv4Addr::from)
+    }
+
+    /// Size of `Ipv4Addr` serialized as an `int`.
+    pub fn size_hint(_addr: &Ipv4Addr) -> error::Result<usize> {
+        Ok(INT_SIZE)
+    }
+}
+
+/// `Ipv6Addr` as `bytes` holding 16 octets of the address.
+pub mod ipv6 {
+    use std::net::Ipv6Addr;
+
+    use serde::de::{Deserialize, Deserializer, Error as DeError};
+    use serde::ser::Serializer;
+    use serde_bytes::ByteBuf;
+
+    use error;
+    use sized::size_hint_from_byte_seq_len;
+
+    /// Serialize `Ipv6Addr` as `bytes`.
+    pub fn serialize<S>(addr: &Ipv6Addr, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_bytes(&addr.octets())
+    }
+
+    /// Deserialize `Ipv6Addr` from `bytes`.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Ipv6Addr, D::Error>
+        where D: Deserializer<'de>
+    {
+        let bytes = ByteBuf::deserialize(deserializer)?;
+
+        if bytes.len() != 16 {
+            return Err(D::Error::invalid_length(bytes.len(), &"16 octets of an IPv6 address"));
+        }
+
+        let mut octets = [0; 16];
+        octets.copy_from_slice(&bytes);
+
+        Ok(Ipv6Addr::from(octets))
+    }
+
+    /// Size of `Ipv6Addr` serialized as `bytes`.
+    pub fn size_hint(_addr: &Ipv6Addr) -> error::Result<usize> {
+        size_hint_from_byte_seq_len(16)
+    }
+}
+
+/// `num_bigint::BigUint` as `bytes` holding a big-endian number, like `g_a` or `dh_prime` of
+/// the Diffie-Hellman key exchange.
+///
+/// This module is available with the `num-bigint` feature enabled.
+#[cfg(feature = "num-bigint")]
+pub mod big_uint {
+    use std::cmp;
+
+    use num_bigint::BigUint;
+    use serde::de::{Deserialize, Deserializer};
+    use serde::ser::Serializer;
+    use serde_bytes::ByteBuf;
+
+    use error;
+    use sized::size_hint_from_byte_seq_len;
+    use utils::safe_int_cast;
+
+    /// Serialize `BigUint` as big-endian `bytes`.
+    pub fn serialize<S>(value: &BigUint, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_bytes(&value.to_bytes_be())
+    }
+
+    /// Deserialize `BigUint` from big-endian `bytes`.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BigUint, D::Error>
+        where D: Deserializer<'de>
+    {
+        ByteBuf::deserialize(deserializer).map(|bytes| BigUint::from_bytes_be(&bytes))
+    }
+
+    /// Size of `BigUint` serialized as big-endian `bytes`.
+    pub fn size_hint(value: &BigUint) -> error::Result<usize> {
+        // Zero is serialized as a single byte
+        let len = cmp::max(1, (value.bits() + 7) / 8);
+
+        size_hint_from_byte_seq_len(safe_int_cast(len)?)
+    }
+}
+
+/// `MsgId` as a `long`.
+pub mod msg_id {
+    use serde::de::{Deserialize, Deserializer};
+    use serde::ser::Serializer;
+
+    use error;
+    use sized::LONG_SIZE;
+
+    /// An MTProto message identifier.
+    ///
+    /// Message identifiers depend on the time the message was created at, so that its
+    /// approximate Unix time is stored in the higher 32 bits.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub struct MsgId {
+        inner: i64,
+    }
+
+    impl MsgId {
+        /// Wrap a message identifier.
+        pub fn new(inner: i64) -> MsgId {
+            MsgId {
+                inner: inner,
+            }
+        }
+
+        /// Return the message identifier as is.
+        pub fn get(&self) -> i64 {
+            self.inner
+        }
+
+        /// Approximate Unix time the message was created at.
+        pub fn unix_time(&self) -> i64 {
+            self.inner >> 32
+        }
+    }
+
+    impl From<i64> for MsgId {
+        fn from(inner: i64) -> MsgId {
+            MsgId::new(inner)
+        }
+    }
+
+    impl From<MsgId> for i64 {
+        fn from(msg_id: MsgId) -> i64 {
+            msg_id.inner
+        }
+    }
+
+    /// Serialize `MsgId` as a `long`.
+    pub fn serialize<S>(msg_id: &MsgId, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_i64(msg_id.inner)
+    }
+
+    /// Deserialize `MsgId` from a `long`.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<MsgId, D::Error>
+        where D: Deserializer<'de>
+    {
+        i64::deserialize(deserializer).map(MsgId::new)
+    }
+
+    /// Size of `MsgId` serialized as a `long`.
+    pub fn size_hint(_msg_id: &MsgId) -> error::Result<usize> {
+        Ok(LONG_SIZE)
+    }
+}
+
+/// `bool` as a `Bool` type id, either `boolTrue` or `boolFalse`.
+///
+/// `serde_mtproto` serializes `bool` this way anyway, but other Serde data formats don't. With
+/// this module the representation is always an `int` holding the type id.
+pub mod tl_bool {
+    use serde::de::{Deserialize, Deserializer, Error as DeError, Unexpected};
+    use serde::ser::Serializer;
+
+    use error;
+    use identifiable::{BOOL_FALSE_ID, BOOL_TRUE_ID};
+    use sized::BOOL_SIZE;
+
+    /// Serialize `bool` as a `Bool` type id.
+    pub fn serialize<S>(value: &bool, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_u32(if *value { BOOL_TRUE_ID } else { BOOL_FALSE_ID })
+    }
+
+    /// Deserialize `bool` from a `Bool` type id.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<bool, D::Error>
+        where D: Deserializer<'de>
+    {
+        match u32::deserialize(deserializer)? {
+            BOOL_TRUE_ID => Ok(true),
+            BOOL_FALSE_ID => Ok(false),
+            id => Err(D::Error::invalid_value(
+                Unexpected::Unsigned(u64::from(id)),
+                &"either boolTrue or boolFalse type id")),
+        }
+    }
+
+    /// Size of `bool` serialized as a `Bool` type id.
+    pub fn size_hint(_value: &bool) -> error::Result<usize> {
+        Ok(BOOL_SIZE)
+    }
+}
@@ -15,19 +15,30 @@
 //!
 //! This crate uses `Boxed*` family as the default, whereas `WithId*`
 //! are type aliases.
+//!
+//! `Raw` and `WithRaw` types keep the exact bytes a value was
+//! deserialized from for routing, hashing or signing purposes.
+//!
+//! `Packed` embeds a serialized value into an MTProto `bytes` value.
 
 use std::fmt;
 use std::marker::PhantomData;
 
+use byteorder::{ByteOrder, LittleEndian};
 #[cfg(feature = "quickcheck")]
 use quickcheck::{Arbitrary, Gen};
-use serde::de::{Deserialize, DeserializeSeed, Deserializer,
+use serde::de::{Deserialize, DeserializeOwned, DeserializeSeed, Deserializer,
                 Error as DeError, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Error as SerError, Serialize, SerializeStruct, SerializeTuple, Serializer};
+use serde_bytes::ByteBuf;
 
-use error::{self, DeErrorKind};
-use identifiable::Identifiable;
-use sized::MtProtoSized;
-use utils::{safe_int_cast, safe_uint_cmp};
+use config::Config;
+use de::Deserializer as MtProtoDeserializer;
+use error::{self, ErrorKind, pass_through};
+use identifiable::{Identifiable, STRING_ID, TypeIdVariant};
+use sized::{MtProtoSized, size_hint_from_byte_seq_len};
+use utils::{DECLARED_SIZE_NEWTYPE_NAME, PACKED_NEWTYPE_NAME, RAW_NEWTYPE_NAME, UNKNOWN_VALUE_NEWTYPE_NAME,
+            safe_int_cast, safe_uint_cmp};
 
 
 /// A struct that wraps an `Identifiable` type value to serialize and
@@ -35,7 +46,13 @@ use utils::{safe_int_cast, safe_uint_cmp};
 ///
 /// Note: if you want to attach both id and serialized size to the
 /// underlying data (in this order), see `BoxedWithSize`.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+///
+/// If `T` has a catch-all variant (see `Identifiable::unknown_variant_id`),
+/// values with unknown type ids are deserialized into it by taking all
+/// bytes up to the declared size of an enclosing value, and are serialized
+/// back byte-exactly. Without a declared size their extent is unknown, so
+/// deserialization fails with `ErrorKind::InvalidTypeId` as usual.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Boxed<T> {
     id: u32,
     inner: T,
@@ -67,6 +84,31 @@ impl<T: Identifiable> Boxed<T> {
     pub fn into_inner(self) -> T {
         self.inner
     }
+
+    /// Update cached data after the wrapped value was deserialized in place and check it
+    /// against the deserialized type id.
+    fn refresh_in_place(&mut self, type_id: u32) -> error::Result<()> {
+        self.id = self.inner.type_id();
+
+        if type_id != self.id {
+            bail!(type_id_mismatch::<T>(type_id, self.id));
+        }
+
+        Ok(())
+    }
+}
+
+// Using a custom implementation instead of the derived one because values of the catch-all
+// variant must be serialized as raw bytes they were deserialized from.
+impl<T: Serialize + Identifiable> Serialize for Boxed<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut ser = serializer.serialize_struct("Boxed", 2)?;
+        ser.serialize_field("id", &self.id)?;
+        serialize_inner_field(&mut ser, &self.inner)?;
+        ser.end()
+    }
 }
 
 // Using a custom implementation instead of the derived one because we need to check validity
@@ -94,20 +136,28 @@ impl<'de, T> Deserialize<'de> for Boxed<T>
                 where A: SeqAccess<'de>
             {
                 let type_id = next_seq_element(&mut seq, 0, 2)?;
-                check_type_id::<T>(type_id).map_err(A::Error::custom)?;
-
-                let value = next_seq_element(&mut seq, 1, 2)?;
-                checked_boxed_value::<T>(type_id, value).map_err(A::Error::custom)
+                let value = if check_type_id::<T>(type_id).map_err(pass_through)? {
+                    next_seq_element(&mut seq, 1, 2)?
+                } else {
+                    let raw = next_seq_element(&mut seq, 1, 2)?;
+                    unknown_value::<T>(type_id, raw).map_err(pass_through)?
+                };
+
+                checked_boxed_value::<T>(type_id, value).map_err(pass_through)
             }
 
             fn visit_map<A>(self, mut map: A) -> Result<Boxed<T>, A::Error>
                 where A: MapAccess<'de>
             {
                 let type_id = next_struct_element(&mut map, "id", 0, 2)?;
-                check_type_id::<T>(type_id).map_err(A::Error::custom)?;
-
-                let value = next_struct_element(&mut map, "inner", 1, 2)?;
-                checked_boxed_value::<T>(type_id, value).map_err(A::Error::custom)
+                let value = if check_type_id::<T>(type_id).map_err(pass_through)? {
+                    next_struct_element(&mut map, "inner", 1, 2)?
+                } else {
+                    let raw = next_struct_element(&mut map, "inner", 1, 2)?;
+                    unknown_value::<T>(type_id, raw).map_err(pass_through)?
+                };
+
+                checked_boxed_value::<T>(type_id, value).map_err(pass_through)
             }
         }
 
@@ -115,15 +165,60 @@ impl<'de, T> Deserialize<'de> for Boxed<T>
             let boxed_value = Boxed::new(value);
 
             if type_id != boxed_value.id {
-                bail!(DeErrorKind::TypeIdMismatch(type_id, boxed_value.id));
+                bail!(type_id_mismatch::<T>(type_id, boxed_value.id));
             }
 
             Ok(boxed_value)
         }
 
-        // Use rvalue static promotion after bumping minimal Rust version to 1.21
-        const FIELDS: &[&str] = &["id", "inner"];
-        deserializer.deserialize_struct("Boxed", FIELDS, BoxedVisitor(PhantomData))
+        deserializer.deserialize_struct("Boxed", &["id", "inner"], BoxedVisitor(PhantomData))
+    }
+
+    fn deserialize_in_place<D>(deserializer: D, place: &mut Boxed<T>) -> Result<(), D::Error>
+        where D: Deserializer<'de>
+    {
+        struct BoxedInPlaceVisitor<'a, T: 'a>(&'a mut Boxed<T>);
+
+        impl<'de, 'a, T> Visitor<'de> for BoxedInPlaceVisitor<'a, T>
+            where T: Deserialize<'de> + Identifiable
+        {
+            type Value = ();
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("type id and an `Identifiable` value")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+                where A: SeqAccess<'de>
+            {
+                let type_id = next_seq_element(&mut seq, 0, 2)?;
+                if check_type_id::<T>(type_id).map_err(pass_through)? {
+                    next_seq_element_seed(&mut seq, InPlaceSeed(&mut self.0.inner), 1, 2)?;
+                } else {
+                    let raw = next_seq_element(&mut seq, 1, 2)?;
+                    self.0.inner = unknown_value::<T>(type_id, raw).map_err(pass_through)?;
+                }
+
+                self.0.refresh_in_place(type_id).map_err(pass_through)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<(), A::Error>
+                where A: MapAccess<'de>
+            {
+                let type_id = next_struct_element(&mut map, "id", 0, 2)?;
+                if check_type_id::<T>(type_id).map_err(pass_through)? {
+                    next_struct_element_seed(
+                        &mut map, PhantomData, InPlaceSeed(&mut self.0.inner), "inner", 1, 2)?;
+                } else {
+                    let raw = next_struct_element(&mut map, "inner", 1, 2)?;
+                    self.0.inner = unknown_value::<T>(type_id, raw).map_err(pass_through)?;
+                }
+
+                self.0.refresh_in_place(type_id).map_err(pass_through)
+            }
+        }
+
+        deserializer.deserialize_struct("Boxed", &["id", "inner"], BoxedInPlaceVisitor(place))
     }
 }
 
@@ -132,6 +227,10 @@ impl<T: Identifiable> Identifiable for Boxed<T> {
         T::all_type_ids()
     }
 
+    fn variant_for_id(type_id: u32) -> Option<TypeIdVariant> {
+        T::variant_for_id(type_id)
+    }
+
     fn type_id(&self) -> u32 {
         self.id
     }
@@ -163,11 +262,36 @@ impl<T> Arbitrary for Boxed<T>
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a, T> ::arbitrary::Arbitrary<'a> for Boxed<T>
+    where T: ::arbitrary::Arbitrary<'a> + Identifiable
+{
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<Boxed<T>> {
+        T::arbitrary(u).map(Boxed::new)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl<T> ::proptest::arbitrary::Arbitrary for Boxed<T>
+    where T: ::proptest::arbitrary::Arbitrary + Identifiable
+{
+    type Parameters = T::Parameters;
+    type Strategy = ::proptest::strategy::Map<T::Strategy, fn(T) -> Boxed<T>>;
+
+    fn arbitrary_with(args: T::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(T::arbitrary_with(args), Boxed::new as fn(T) -> Boxed<T>)
+    }
+}
+
 
 /// A struct that wraps a `MtProtoSized` type value to serialize and
 /// deserialize as a MTProto data type with the size of its serialized
 /// value.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+///
+/// Note: if the wrapped type doesn't implement `MtProtoSized` or you want
+/// to avoid traversing the value twice, see
+/// `Serializer::serialize_with_size` which back-patches the size instead.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct WithSize<T> {
     size: u32,
     inner: T,
@@ -198,42 +322,121 @@ impl<T: MtProtoSized> WithSize<T> {
     pub fn into_inner(self) -> T {
         self.inner
     }
+
+    /// Return the size of the wrapped value.
+    ///
+    /// For a deserialized value this is the declared size, which includes trailing bytes
+    /// skipped with `Deserializer::skip_trailing_bytes`. The size is always computed from the
+    /// wrapped value when serialized, so such bytes are not declared again.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Update cached data after the wrapped value was deserialized in place and check it
+    /// against the declared size.
+    fn refresh_in_place(&mut self, size: &DeclaredSize) -> error::Result<()> {
+        size.check(&self.inner)?;
+        self.size = size.size;
+
+        Ok(())
+    }
 }
 
-// Using a custom implementation instead of the derived one because we need to check validity
-// of the deserialized size against the size hint of a deserialized value.
+// Using a custom implementation instead of the derived one because the declared size of a
+// deserialized value may include skipped trailing bytes which are not serialized back.
+impl<T: Serialize + MtProtoSized> Serialize for WithSize<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let size: u32 = self.inner.size_hint().and_then(safe_int_cast).map_err(S::Error::custom)?;
+
+        let mut ser = serializer.serialize_struct("WithSize", 2)?;
+        ser.serialize_field("size", &size)?;
+        ser.serialize_field("inner", &self.inner)?;
+        ser.end()
+    }
+}
+
+// Using a custom implementation instead of the derived one because we need to let the
+// deserializer know the declared size __before__ deserializing the value.
 impl<'de, T> Deserialize<'de> for WithSize<T>
     where T: Deserialize<'de> + MtProtoSized
 {
     fn deserialize<D>(deserializer: D) -> Result<WithSize<T>, D::Error>
         where D: Deserializer<'de>
     {
-        // Here we only implement through a helper struct because fully manual implementation
-        // (like what is present for `Boxed` and `BoxedWithSize`) won't provide us eny benefits
-        // over this solution - we can obtain a deserialized size beforehand, but we can't apply it
-        // since neither Serde deserializable types, nor Serde deserializers in general have any
-        // means to limit the amount of raw data to be processed.
-        #[derive(Deserialize)]
-        #[serde(rename = "WithSize")]
-        struct WithSizeHelper<T> {
-            size: u32,
-            inner: T,
+        struct WithSizeVisitor<T>(PhantomData<T>);
+
+        impl<'de, T> Visitor<'de> for WithSizeVisitor<T>
+            where T: Deserialize<'de> + MtProtoSized
+        {
+            type Value = WithSize<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("size and a `MtProtoSized` value")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<WithSize<T>, A::Error>
+                where A: SeqAccess<'de>
+            {
+                let size: DeclaredSize = next_seq_element(&mut seq, 0, 2)?;
+                let value = next_seq_element(&mut seq, 1, 2)?;
+                checked_with_size_value::<T>(size, value).map_err(pass_through)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<WithSize<T>, A::Error>
+                where A: MapAccess<'de>
+            {
+                let size: DeclaredSize = next_struct_element(&mut map, "size", 0, 2)?;
+                let value = next_struct_element(&mut map, "inner", 1, 2)?;
+                checked_with_size_value::<T>(size, value).map_err(pass_through)
+            }
         }
 
-        let helper = WithSizeHelper::<T>::deserialize(deserializer)?;
-        let helper_size_hint = helper.inner.size_hint().map_err(D::Error::custom)?;
+        fn checked_with_size_value<T>(size: DeclaredSize, value: T) -> error::Result<WithSize<T>>
+            where T: MtProtoSized
+        {
+            size.check(&value)?;
 
-        if !safe_uint_cmp(helper.size, helper_size_hint) {
-            bail!(errconv::<D::Error>(DeErrorKind::SizeMismatch(
-                helper.size,
-                safe_int_cast(helper_size_hint).map_err(D::Error::custom)?,
-            )));
+            Ok(WithSize { size: size.size, inner: value })
         }
 
-        Ok(WithSize {
-            size: helper.size,
-            inner: helper.inner,
-        })
+        deserializer.deserialize_struct("WithSize", &["size", "inner"], WithSizeVisitor(PhantomData))
+    }
+
+    fn deserialize_in_place<D>(deserializer: D, place: &mut WithSize<T>) -> Result<(), D::Error>
+        where D: Deserializer<'de>
+    {
+        struct WithSizeInPlaceVisitor<'a, T: 'a>(&'a mut WithSize<T>);
+
+        impl<'de, 'a, T> Visitor<'de> for WithSizeInPlaceVisitor<'a, T>
+            where T: Deserialize<'de> + MtProtoSized
+        {
+            type Value = ();
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("size and a `MtProtoSized` value")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+                where A: SeqAccess<'de>
+            {
+                let size: DeclaredSize = next_seq_element(&mut seq, 0, 2)?;
+                next_seq_element_seed(&mut seq, InPlaceSeed(&mut self.0.inner), 1, 2)?;
+                self.0.refresh_in_place(&size).map_err(pass_through)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<(), A::Error>
+                where A: MapAccess<'de>
+            {
+                let size: DeclaredSize = next_struct_element(&mut map, "size", 0, 2)?;
+                next_struct_element_seed(
+                    &mut map, PhantomData, InPlaceSeed(&mut self.0.inner), "inner", 1, 2)?;
+                self.0.refresh_in_place(&size).map_err(pass_through)
+            }
+        }
+
+        deserializer.deserialize_struct("WithSize", &["size", "inner"], WithSizeInPlaceVisitor(place))
     }
 }
 
@@ -261,6 +464,28 @@ impl<T> Arbitrary for WithSize<T>
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a, T> ::arbitrary::Arbitrary<'a> for WithSize<T>
+    where T: ::arbitrary::Arbitrary<'a> + MtProtoSized
+{
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<WithSize<T>> {
+        WithSize::new(T::arbitrary(u)?).map_err(|_| ::arbitrary::Error::IncorrectFormat)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl<T> ::proptest::arbitrary::Arbitrary for WithSize<T>
+    where T: ::proptest::arbitrary::Arbitrary + MtProtoSized
+{
+    type Parameters = T::Parameters;
+    type Strategy = ::proptest::strategy::Map<T::Strategy, fn(T) -> WithSize<T>>;
+
+    fn arbitrary_with(args: T::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(T::arbitrary_with(args), |x| WithSize::new(x)
+            .expect("failed to wrap a generated random value using `WithSize`"))
+    }
+}
+
 
 /// A struct that wraps an `Identifiable` and `MtProtoSized` type value
 /// to serialize and deserialize as a boxed MTProto data type with the
@@ -269,7 +494,14 @@ impl<T> Arbitrary for WithSize<T>
 /// This struct exists because `Boxed<WithSize<T>>` cannot be created
 /// due to `WithSize<T>` not being `Identifiable` (this restriction is
 /// made on purpose).
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+///
+/// Note: for a single-pass alternative which doesn't require
+/// `MtProtoSized`, see `Serializer::serialize_boxed_with_size`.
+///
+/// Like `Boxed`, it keeps values with unknown type ids in the catch-all
+/// variant of `T` if there is one; their extent is given by the declared
+/// size.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct BoxedWithSize<T> {
     id: u32,
     size: u32,
@@ -305,11 +537,51 @@ impl<T: Identifiable + MtProtoSized> BoxedWithSize<T> {
     pub fn into_inner(self) -> T {
         self.inner
     }
+
+    /// Return the size of the wrapped value.
+    ///
+    /// For a deserialized value this is the declared size, which includes trailing bytes
+    /// skipped with `Deserializer::skip_trailing_bytes`. The size is always computed from the
+    /// wrapped value when serialized, so such bytes are not declared again.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Update cached data after the wrapped value was deserialized in place and check it
+    /// against the deserialized type id and the declared size.
+    fn refresh_in_place(&mut self, type_id: u32, size: &DeclaredSize) -> error::Result<()> {
+        self.id = self.inner.type_id();
+        self.size = size.size;
+
+        // Same priority of errors as for `BoxedWithSize::deserialize`
+        if type_id != self.id {
+            bail!(type_id_mismatch::<T>(type_id, self.id));
+        }
+
+        size.check(&self.inner)
+    }
+}
+
+// Using a custom implementation instead of the derived one because values of the catch-all
+// variant must be serialized as raw bytes they were deserialized from and the declared size of
+// a deserialized value may include skipped trailing bytes which are not serialized back.
+impl<T: Serialize + Identifiable + MtProtoSized> Serialize for BoxedWithSize<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let size: u32 = self.inner.size_hint().and_then(safe_int_cast).map_err(S::Error::custom)?;
+
+        let mut ser = serializer.serialize_struct("BoxedWithSize", 3)?;
+        ser.serialize_field("id", &self.id)?;
+        ser.serialize_field("size", &size)?;
+        serialize_inner_field(&mut ser, &self.inner)?;
+        ser.end()
+    }
 }
 
 // Using a custom implementation instead of the derived one because we need to check validity
-// of the deserialized type id __before__ deserializing the value and also of the deserialized size
-// against the size hint of a deserialized value.
+// of the deserialized type id and to let the deserializer know the declared size __before__
+// deserializing the value.
 impl<'de, T> Deserialize<'de> for BoxedWithSize<T>
     where T: Deserialize<'de> + Identifiable + MtProtoSized
 {
@@ -334,52 +606,112 @@ impl<'de, T> Deserialize<'de> for BoxedWithSize<T>
                 where A: SeqAccess<'de>
             {
                 let type_id = next_seq_element(&mut seq, 0, 3)?;
-                check_type_id::<T>(type_id).map_err(A::Error::custom)?;
+                let is_known = check_type_id::<T>(type_id).map_err(pass_through)?;
+
+                let size: DeclaredSize = next_seq_element(&mut seq, 1, 3)?;
+                let value = if is_known {
+                    next_seq_element(&mut seq, 2, 3)?
+                } else {
+                    let raw = next_seq_element(&mut seq, 2, 3)?;
+                    unknown_value::<T>(type_id, raw).map_err(pass_through)?
+                };
 
-                let size = next_seq_element(&mut seq, 1, 3)?;
-                let value = next_seq_element(&mut seq, 2, 3)?;
-                checked_boxed_with_size_value::<T>(type_id, size, value).map_err(A::Error::custom)
+                checked_boxed_with_size_value::<T>(type_id, size, value).map_err(pass_through)
             }
 
             fn visit_map<A>(self, mut map: A) -> Result<BoxedWithSize<T>, A::Error>
                 where A: MapAccess<'de>
             {
                 let type_id = next_struct_element(&mut map, "id", 0, 3)?;
-                check_type_id::<T>(type_id).map_err(A::Error::custom)?;
+                let is_known = check_type_id::<T>(type_id).map_err(pass_through)?;
 
-                let size = next_struct_element(&mut map, "size", 1, 3)?;
-                let value = next_struct_element(&mut map, "inner", 2, 3)?;
-                checked_boxed_with_size_value::<T>(type_id, size, value).map_err(A::Error::custom)
+                let size: DeclaredSize = next_struct_element(&mut map, "size", 1, 3)?;
+                let value = if is_known {
+                    next_struct_element(&mut map, "inner", 2, 3)?
+                } else {
+                    let raw = next_struct_element(&mut map, "inner", 2, 3)?;
+                    unknown_value::<T>(type_id, raw).map_err(pass_through)?
+                };
+
+                checked_boxed_with_size_value::<T>(type_id, size, value).map_err(pass_through)
             }
         }
 
         fn checked_boxed_with_size_value<T>(type_id: u32,
-                                            size: u32,
+                                            size: DeclaredSize,
                                             value: T)
                                            -> error::Result<BoxedWithSize<T>>
             where T: Identifiable + MtProtoSized
         {
-            let boxed_with_size_value = BoxedWithSize::new(value)?;
+            let value_type_id = value.type_id();
 
             // Proritize type id mismatch errors over size mismatch ones since type id being
             // incorrect will likely lead to a wrong size too.
             // Also, without correct type information matching sizes don't mean anything anymore.
             // Data is corrupt. Period.
-            if type_id != boxed_with_size_value.id {
-                bail!(DeErrorKind::TypeIdMismatch(type_id, boxed_with_size_value.id));
+            if type_id != value_type_id {
+                bail!(type_id_mismatch::<T>(type_id, value_type_id));
             }
 
-            let boxed_with_size_size_hint = boxed_with_size_value.size_hint()?;
-            if !safe_uint_cmp(size, boxed_with_size_size_hint) {
-                bail!(DeErrorKind::SizeMismatch(size, safe_int_cast(boxed_with_size_size_hint)?));
+            size.check(&value)?;
+
+            Ok(BoxedWithSize { id: value_type_id, size: size.size, inner: value })
+        }
+
+        deserializer.deserialize_struct("BoxedWithSize", &["id", "size", "inner"], BoxedWithSizeVisitor(PhantomData))
+    }
+
+    fn deserialize_in_place<D>(deserializer: D, place: &mut BoxedWithSize<T>) -> Result<(), D::Error>
+        where D: Deserializer<'de>
+    {
+        struct BoxedWithSizeInPlaceVisitor<'a, T: 'a>(&'a mut BoxedWithSize<T>);
+
+        impl<'de, 'a, T> Visitor<'de> for BoxedWithSizeInPlaceVisitor<'a, T>
+            where T: Deserialize<'de> + Identifiable + MtProtoSized
+        {
+            type Value = ();
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("type id and an `Identifiable` value")
             }
 
-            Ok(boxed_with_size_value)
+            fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+                where A: SeqAccess<'de>
+            {
+                let type_id = next_seq_element(&mut seq, 0, 3)?;
+                let is_known = check_type_id::<T>(type_id).map_err(pass_through)?;
+
+                let size: DeclaredSize = next_seq_element(&mut seq, 1, 3)?;
+                if is_known {
+                    next_seq_element_seed(&mut seq, InPlaceSeed(&mut self.0.inner), 2, 3)?;
+                } else {
+                    let raw = next_seq_element(&mut seq, 2, 3)?;
+                    self.0.inner = unknown_value::<T>(type_id, raw).map_err(pass_through)?;
+                }
+
+                self.0.refresh_in_place(type_id, &size).map_err(pass_through)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<(), A::Error>
+                where A: MapAccess<'de>
+            {
+                let type_id = next_struct_element(&mut map, "id", 0, 3)?;
+                let is_known = check_type_id::<T>(type_id).map_err(pass_through)?;
+
+                let size: DeclaredSize = next_struct_element(&mut map, "size", 1, 3)?;
+                if is_known {
+                    next_struct_element_seed(
+                        &mut map, PhantomData, InPlaceSeed(&mut self.0.inner), "inner", 2, 3)?;
+                } else {
+                    let raw = next_struct_element(&mut map, "inner", 2, 3)?;
+                    self.0.inner = unknown_value::<T>(type_id, raw).map_err(pass_through)?;
+                }
+
+                self.0.refresh_in_place(type_id, &size).map_err(pass_through)
+            }
         }
 
-        // Use rvalue static promotion after bumping minimal Rust version to 1.21
-        const FIELDS: &[&str] = &["id", "size", "inner"];
-        deserializer.deserialize_struct("BoxedWithSize", FIELDS, BoxedWithSizeVisitor(PhantomData))
+        deserializer.deserialize_struct("BoxedWithSize", &["id", "size", "inner"], BoxedWithSizeInPlaceVisitor(place))
     }
 }
 
@@ -408,18 +740,734 @@ impl<T> Arbitrary for BoxedWithSize<T>
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a, T> ::arbitrary::Arbitrary<'a> for BoxedWithSize<T>
+    where T: ::arbitrary::Arbitrary<'a> + Identifiable + MtProtoSized
+{
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<BoxedWithSize<T>> {
+        BoxedWithSize::new(T::arbitrary(u)?).map_err(|_| ::arbitrary::Error::IncorrectFormat)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl<T> ::proptest::arbitrary::Arbitrary for BoxedWithSize<T>
+    where T: ::proptest::arbitrary::Arbitrary + Identifiable + MtProtoSized
+{
+    type Parameters = T::Parameters;
+    type Strategy = ::proptest::strategy::Map<T::Strategy, fn(T) -> BoxedWithSize<T>>;
+
+    fn arbitrary_with(args: T::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(T::arbitrary_with(args), |x| BoxedWithSize::new(x)
+            .expect("failed to wrap a generated random value using `BoxedWithSize`"))
+    }
+}
+
+
+/// A struct that holds the serialized bytes of a `T` value without
+/// keeping the decoded value itself.
+///
+/// This is useful for routing a message by its id and forwarding its
+/// body untouched. The value is decoded only when `decode` is called.
+///
+/// Note: when deserialized, the value is still traversed to find out
+/// where it ends, but the result is discarded. This requires a
+/// `serde_mtproto` deserializer; other deserializers only restore
+/// bytes which were serialized by `Raw` itself.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Raw<T> {
+    bytes: Vec<u8>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Raw<T> {
+    /// Serialize a value and keep only its bytes.
+    pub fn new(value: &T) -> error::Result<Raw<T>>
+        where T: Serialize
+    {
+        Raw::with_config(value, Config::default())
+    }
+
+    /// Serialize a value following the given encoding policies and keep only its bytes.
+    pub fn with_config(value: &T, config: Config) -> error::Result<Raw<T>>
+        where T: Serialize
+    {
+        Ok(Raw {
+            bytes: config.to_bytes(value)?,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Decode the captured bytes into a `T` value using an enum variant hint.
+    pub fn decode<'a>(&'a self, enum_variant_id: Option<&'static str>) -> error::Result<T>
+        where T: Deserialize<'a>
+    {
+        self.decode_with_config(enum_variant_id, Config::default())
+    }
+
+    /// Decode the captured bytes into a `T` value using an enum variant hint and following the
+    /// given encoding policies.
+    pub fn decode_with_config<'a>(&'a self, enum_variant_id: Option<&'static str>, config: Config)
+        -> error::Result<T>
+        where T: Deserialize<'a>
+    {
+        config.from_bytes(&self.bytes, enum_variant_id)
+    }
+
+    /// Return the captured bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Unwrap the captured bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl<T> Serialize for Raw<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serialize_raw_bytes(&self.bytes, serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Raw<T>
+    where T: Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Raw<T>, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct RawVisitor<T>(PhantomData<T>);
+
+        impl<'de, T> Visitor<'de> for RawVisitor<T>
+            where T: Deserialize<'de>
+        {
+            type Value = Raw<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a value along with bytes it was deserialized from")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Raw<T>, A::Error>
+                where A: SeqAccess<'de>
+            {
+                let _: T = next_seq_element(&mut seq, 0, 2)?;
+                let bytes: ByteBuf = next_seq_element(&mut seq, 1, 2)?;
+                check_raw_bytes::<T>(&bytes).map_err(pass_through)?;
+
+                Ok(Raw {
+                    bytes: bytes.into(),
+                    phantom: PhantomData,
+                })
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Raw<T>, D::Error>
+                where D: Deserializer<'de>
+            {
+                let bytes = deserialize_raw_bytes(deserializer)?;
+                check_raw_bytes::<T>(&bytes).map_err(pass_through)?;
+
+                Ok(Raw {
+                    bytes: bytes,
+                    phantom: PhantomData,
+                })
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(RAW_NEWTYPE_NAME, RawVisitor(PhantomData))
+    }
+}
+
+impl<T: Identifiable> Identifiable for Raw<Boxed<T>> {
+    fn all_type_ids() -> &'static [u32] {
+        T::all_type_ids()
+    }
+
+    fn variant_for_id(type_id: u32) -> Option<TypeIdVariant> {
+        T::variant_for_id(type_id)
+    }
+
+    fn type_id(&self) -> u32 {
+        peek_raw_type_id(&self.bytes)
+    }
+
+    fn enum_variant_id(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl<T: Identifiable> Identifiable for Raw<BoxedWithSize<T>> {
+    fn all_type_ids() -> &'static [u32] {
+        T::all_type_ids()
+    }
+
+    fn variant_for_id(type_id: u32) -> Option<TypeIdVariant> {
+        T::variant_for_id(type_id)
+    }
+
+    fn type_id(&self) -> u32 {
+        peek_raw_type_id(&self.bytes)
+    }
+
+    fn enum_variant_id(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl<T> MtProtoSized for Raw<T> {
+    fn size_hint(&self) -> error::Result<usize> {
+        Ok(self.bytes.len())
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a, T> ::arbitrary::Arbitrary<'a> for Raw<T>
+    where T: ::arbitrary::Arbitrary<'a> + Serialize
+{
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<Raw<T>> {
+        Raw::new(&T::arbitrary(u)?).map_err(|_| ::arbitrary::Error::IncorrectFormat)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl<T> ::proptest::arbitrary::Arbitrary for Raw<T>
+    where T: ::proptest::arbitrary::Arbitrary + Serialize
+{
+    type Parameters = T::Parameters;
+    type Strategy = ::proptest::strategy::Map<T::Strategy, fn(T) -> Raw<T>>;
+
+    fn arbitrary_with(args: T::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(T::arbitrary_with(args), |x| Raw::new(&x)
+            .expect("failed to wrap a generated random value using `Raw`"))
+    }
+}
+
+
+/// A struct that holds a decoded `T` value along with the exact bytes
+/// it was deserialized from.
+///
+/// Serializing a `WithRaw` writes the original bytes back as is, which
+/// makes it suitable for hashing or signing received data.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct WithRaw<T> {
+    inner: T,
+    bytes: Vec<u8>,
+}
+
+impl<T: Serialize> WithRaw<T> {
+    /// Wrap a value along with its serialized bytes.
+    pub fn new(inner: T) -> error::Result<WithRaw<T>> {
+        WithRaw::with_config(inner, Config::default())
+    }
+
+    /// Wrap a value along with its bytes serialized following the given encoding policies.
+    pub fn with_config(inner: T, config: Config) -> error::Result<WithRaw<T>> {
+        let with_raw = WithRaw {
+            bytes: config.to_bytes(&inner)?,
+            inner: inner,
+        };
+
+        Ok(with_raw)
+    }
+}
+
+impl<T> WithRaw<T> {
+    /// Return an immutable reference to the underlying data.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Return the bytes the underlying data was deserialized from.
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Unwrap the underlying data.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Unwrap both the underlying data and its bytes.
+    pub fn into_parts(self) -> (T, Vec<u8>) {
+        (self.inner, self.bytes)
+    }
+}
+
+impl<T> Serialize for WithRaw<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serialize_raw_bytes(&self.bytes, serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for WithRaw<T>
+    where T: DeserializeOwned
+{
+    fn deserialize<D>(deserializer: D) -> Result<WithRaw<T>, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct WithRawVisitor<T>(PhantomData<T>);
+
+        impl<'de, T> Visitor<'de> for WithRawVisitor<T>
+            where T: DeserializeOwned
+        {
+            type Value = WithRaw<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a value along with bytes it was deserialized from")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<WithRaw<T>, A::Error>
+                where A: SeqAccess<'de>
+            {
+                let inner = next_seq_element(&mut seq, 0, 2)?;
+                let bytes: ByteBuf = next_seq_element(&mut seq, 1, 2)?;
+
+                Ok(WithRaw {
+                    inner: inner,
+                    bytes: bytes.into(),
+                })
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<WithRaw<T>, D::Error>
+                where D: Deserializer<'de>
+            {
+                let bytes = deserialize_raw_bytes(deserializer)?;
+                let inner = unpack(&bytes).map_err(pass_through)?;
+
+                Ok(WithRaw {
+                    inner: inner,
+                    bytes: bytes,
+                })
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(RAW_NEWTYPE_NAME, WithRawVisitor(PhantomData))
+    }
+}
+
+impl<T: Identifiable> Identifiable for WithRaw<T> {
+    fn all_type_ids() -> &'static [u32] {
+        T::all_type_ids()
+    }
+
+    fn variant_for_id(type_id: u32) -> Option<TypeIdVariant> {
+        T::variant_for_id(type_id)
+    }
+
+    fn type_id(&self) -> u32 {
+        self.inner.type_id()
+    }
+
+    fn enum_variant_id(&self) -> Option<&'static str> {
+        self.inner.enum_variant_id()
+    }
+}
+
+impl<T> MtProtoSized for WithRaw<T> {
+    fn size_hint(&self) -> error::Result<usize> {
+        Ok(self.bytes.len())
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a, T> ::arbitrary::Arbitrary<'a> for WithRaw<T>
+    where T: ::arbitrary::Arbitrary<'a> + Serialize
+{
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<WithRaw<T>> {
+        WithRaw::new(T::arbitrary(u)?).map_err(|_| ::arbitrary::Error::IncorrectFormat)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl<T> ::proptest::arbitrary::Arbitrary for WithRaw<T>
+    where T: ::proptest::arbitrary::Arbitrary + Serialize
+{
+    type Parameters = T::Parameters;
+    type Strategy = ::proptest::strategy::Map<T::Strategy, fn(T) -> WithRaw<T>>;
+
+    fn arbitrary_with(args: T::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(T::arbitrary_with(args), |x| WithRaw::new(x)
+            .expect("failed to wrap a generated random value using `WithRaw`"))
+    }
+}
+
+
+/// A struct that wraps a `T` value to serialize and deserialize it
+/// nested inside of an MTProto `bytes` value.
+///
+/// The value is serialized with the usual rules first and the result
+/// is written as a length-prefixed and padded byte sequence. This is
+/// how objects like `p_q_inner_data` or `bind_auth_key_inner` are
+/// embedded into other constructors.
+///
+/// When deserialized, the length of the byte sequence is the declared
+/// size of the wrapped value, which must consume all of its bytes unless
+/// they are skipped with `Deserializer::skip_trailing_bytes`, otherwise
+/// `ErrorKind::UnconsumedBytes` is reported.
+///
+/// The nested value follows the same `Config` and enum variant hint as
+/// the enclosing one.
+/// Other Serde formats [de]serialize the wrapped value as is.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Packed<T> {
+    inner: T,
+}
+
+impl<T> Packed<T> {
+    /// Wrap a value.
+    pub fn new(inner: T) -> Packed<T> {
+        Packed {
+            inner: inner,
+        }
+    }
+
+    /// Return an immutable reference to the underlying data.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the underlying data.
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying data.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Serialize> Serialize for Packed<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_newtype_struct(PACKED_NEWTYPE_NAME, &self.inner)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Packed<T>
+    where T: Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Packed<T>, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct PackedVisitor<T>(PhantomData<T>);
+
+        impl<'de, T> Visitor<'de> for PackedVisitor<T>
+            where T: Deserialize<'de>
+        {
+            type Value = Packed<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a value nested in a byte sequence")
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Packed<T>, D::Error>
+                where D: Deserializer<'de>
+            {
+                T::deserialize(deserializer).map(Packed::new)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(PACKED_NEWTYPE_NAME, PackedVisitor(PhantomData))
+    }
+}
+
+// The wrapped value is hidden inside of a byte sequence which is boxed as `string`.
+impl<T> Identifiable for Packed<T> {
+    fn all_type_ids() -> &'static [u32] {
+        String::all_type_ids()
+    }
+
+    fn type_id(&self) -> u32 {
+        STRING_ID
+    }
+
+    fn enum_variant_id(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl<T: MtProtoSized> MtProtoSized for Packed<T> {
+    fn size_hint(&self) -> error::Result<usize> {
+        size_hint_from_byte_seq_len(self.inner.size_hint()?)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a, T> ::arbitrary::Arbitrary<'a> for Packed<T>
+    where T: ::arbitrary::Arbitrary<'a>
+{
+    fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<Packed<T>> {
+        T::arbitrary(u).map(Packed::new)
+    }
+}
+
+#[cfg(feature = "proptest")]
+impl<T> ::proptest::arbitrary::Arbitrary for Packed<T>
+    where T: ::proptest::arbitrary::Arbitrary
+{
+    type Parameters = T::Parameters;
+    type Strategy = ::proptest::strategy::Map<T::Strategy, fn(T) -> Packed<T>>;
+
+    fn arbitrary_with(args: T::Parameters) -> Self::Strategy {
+        ::proptest::strategy::Strategy::prop_map(T::arbitrary_with(args), Packed::new as fn(T) -> Packed<T>)
+    }
+}
+
 
 // ========== UTILS ========== //
 
-fn check_type_id<T: Identifiable>(type_id: u32) -> error::Result<()> {
-    let expected_type_ids = T::all_type_ids();
-    if expected_type_ids.iter().find(|&id| *id == type_id).is_none() {
-        bail!(DeErrorKind::InvalidTypeId(type_id, expected_type_ids));
+/// Serialize raw bytes as is, 4 bytes at a time (any MTProto value has such alignment).
+fn serialize_raw_bytes<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    if bytes.len() % 4 != 0 {
+        return Err(S::Error::custom(
+            format!("raw bytes length {} is not divisible by 4", bytes.len())));
+    }
+
+    let mut serialize_tuple = serializer.serialize_tuple(bytes.len() / 4)?;
+
+    for chunk in bytes.chunks(4) {
+        serialize_tuple.serialize_element(&LittleEndian::read_u32(chunk))?;
+    }
+
+    serialize_tuple.end()
+}
+
+fn deserialize_raw_bytes<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where D: Deserializer<'de>
+{
+    let words: Vec<u32> = Deserialize::deserialize(deserializer)?;
+    let mut bytes = vec![0; words.len() * 4];
+
+    for (chunk, word) in bytes.chunks_mut(4).zip(words) {
+        LittleEndian::write_u32(chunk, word);
+    }
+
+    Ok(bytes)
+}
+
+/// Deserialize a value which must consume all of the given bytes.
+fn unpack<'de, T: Deserialize<'de>>(bytes: &[u8]) -> error::Result<T> {
+    let mut de = MtProtoDeserializer::new(bytes, None);
+    let value = T::deserialize(&mut de)?;
+    let unconsumed = de.remaining_length();
+
+    if unconsumed > 0 {
+        bail!(ErrorKind::UnconsumedBytes {
+            unconsumed: safe_int_cast(unconsumed)?,
+            declared: safe_int_cast(bytes.len())?,
+        });
+    }
+
+    Ok(value)
+}
+
+/// Check that bytes of a `Raw` value which come from the deserializer can hold a `T` value.
+///
+/// Bytes shorter than a type id are decoded to make sure they don't belong to a boxed value, longer
+/// ones are left as is to not decode every value twice.
+fn check_raw_bytes<'de, T: Deserialize<'de>>(bytes: &[u8]) -> error::Result<()> {
+    if bytes.len() < 4 {
+        let _: T = unpack(bytes)?;
     }
 
     Ok(())
 }
 
+/// Read the type id from the first 4 bytes of a boxed value.
+///
+/// # Panics
+///
+/// Panics if there are less than 4 bytes, which `Raw::new` and `check_raw_bytes` rule out for
+/// bytes of a boxed value.
+fn peek_raw_type_id(bytes: &[u8]) -> u32 {
+    LittleEndian::read_u32(&bytes[..4])
+}
+
+/// The size declared in front of a `WithSize` or `BoxedWithSize` value.
+///
+/// `serde_mtproto` deserializer enforces it as a hard limit for the value that follows and
+/// reports that via `visit_u32`, other deserializers go through `visit_newtype_struct` and the
+/// size is checked against the size hint of the deserialized value afterwards.
+struct DeclaredSize {
+    size: u32,
+    enforced: bool,
+}
+
+impl DeclaredSize {
+    /// Check the declared size against the size hint of the deserialized value unless the
+    /// deserializer has already enforced it.
+    fn check<T: MtProtoSized>(&self, value: &T) -> error::Result<()> {
+        if !self.enforced {
+            let size_hint = value.size_hint()?;
+
+            if !safe_uint_cmp(self.size, size_hint) {
+                bail!(ErrorKind::SizeMismatch {
+                    found: self.size,
+                    expected: safe_int_cast(size_hint)?,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for DeclaredSize {
+    fn deserialize<D>(deserializer: D) -> Result<DeclaredSize, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct DeclaredSizeVisitor;
+
+        impl<'de> Visitor<'de> for DeclaredSizeVisitor {
+            type Value = DeclaredSize;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a size of the serialized value")
+            }
+
+            fn visit_u32<E>(self, size: u32) -> Result<DeclaredSize, E>
+                where E: DeError
+            {
+                Ok(DeclaredSize { size: size, enforced: true })
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<DeclaredSize, D::Error>
+                where D: Deserializer<'de>
+            {
+                let size = u32::deserialize(deserializer)?;
+                Ok(DeclaredSize { size: size, enforced: false })
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(DECLARED_SIZE_NEWTYPE_NAME, DeclaredSizeVisitor)
+    }
+}
+
+
+/// Check validity of the deserialized type id.
+///
+/// Returns `false` if the type id is unknown, but `T` has a catch-all variant to keep it.
+fn check_type_id<T: Identifiable>(type_id: u32) -> error::Result<bool> {
+    if T::variant_for_id(type_id).is_none() {
+        if T::unknown_variant_id().is_none() {
+            bail!(ErrorKind::InvalidTypeId { found: type_id, expected: T::all_type_ids() });
+        }
+
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Describe the mismatch of the deserialized type id and the one of the deserialized value,
+/// naming their enum variants if they are known.
+fn type_id_mismatch<T: Identifiable>(found: u32, expected: u32) -> ErrorKind {
+    let variant_name = |type_id| T::variant_for_id(type_id).and_then(|variant| variant.name);
+
+    ErrorKind::TypeIdMismatch {
+        found: found,
+        expected: expected,
+        found_variant: variant_name(found),
+        expected_variant: variant_name(expected),
+    }
+}
+
+fn unknown_value<T: Identifiable>(type_id: u32, raw: UnknownValueBytes) -> error::Result<T> {
+    let value = raw.0.and_then(|raw| {
+        debug!("Keeping {} bytes of a value with unknown type id {:#x}", raw.len(), type_id);
+        T::from_unknown(type_id, raw)
+    });
+
+    value.ok_or_else(|| ErrorKind::InvalidTypeId { found: type_id, expected: T::all_type_ids() }.into())
+}
+
+/// Serialize the wrapped value of `Boxed` or `BoxedWithSize`, or its raw bytes if it belongs to
+/// the catch-all variant.
+fn serialize_inner_field<S, T>(ser: &mut S, inner: &T) -> Result<(), S::Error>
+    where S: SerializeStruct,
+          T: Serialize + Identifiable,
+{
+    match inner.unknown_raw() {
+        Some(raw) => ser.serialize_field("inner", &UnknownRaw(raw)),
+        None => ser.serialize_field("inner", inner),
+    }
+}
+
+/// A seed which deserializes a value into an existing place reusing its allocations.
+struct InPlaceSeed<'a, T: 'a>(&'a mut T);
+
+impl<'de, 'a, T> DeserializeSeed<'de> for InPlaceSeed<'a, T>
+    where T: Deserialize<'de>
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+        where D: Deserializer<'de>
+    {
+        T::deserialize_in_place(deserializer, self.0)
+    }
+}
+
+/// Raw bytes of a value with unknown type id, serialized as is.
+struct UnknownRaw<'a>(&'a [u8]);
+
+impl<'a> Serialize for UnknownRaw<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serialize_raw_bytes(self.0, serializer)
+    }
+}
+
+/// Raw bytes of a value with unknown type id, if their extent is known.
+///
+/// `serde_mtproto` deserializer reads everything up to the declared size limit and reports that
+/// via `visit_byte_buf` or, if there is none, reports `visit_none`. Other deserializers go through
+/// `visit_newtype_struct` and restore bytes serialized by `UnknownRaw`.
+struct UnknownValueBytes(Option<Vec<u8>>);
+
+impl<'de> Deserialize<'de> for UnknownValueBytes {
+    fn deserialize<D>(deserializer: D) -> Result<UnknownValueBytes, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct UnknownValueBytesVisitor;
+
+        impl<'de> Visitor<'de> for UnknownValueBytesVisitor {
+            type Value = UnknownValueBytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("raw bytes of a value with unknown type id")
+            }
+
+            fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<UnknownValueBytes, E>
+                where E: DeError
+            {
+                Ok(UnknownValueBytes(Some(bytes)))
+            }
+
+            fn visit_none<E>(self) -> Result<UnknownValueBytes, E>
+                where E: DeError
+            {
+                Ok(UnknownValueBytes(None))
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<UnknownValueBytes, D::Error>
+                where D: Deserializer<'de>
+            {
+                deserialize_raw_bytes(deserializer).map(|bytes| UnknownValueBytes(Some(bytes)))
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(UNKNOWN_VALUE_NEWTYPE_NAME, UnknownValueBytesVisitor)
+    }
+}
 
 fn next_seq_element<'de, T, A>(seq: &mut A,
                                deserialized_count: u32,
@@ -440,7 +1488,10 @@ fn next_seq_element_seed<'de, S, A>(seq: &mut A,
           A: SeqAccess<'de>,
 {
     seq.next_element_seed(seed)?
-        .ok_or_else(|| errconv(DeErrorKind::NotEnoughElements(deserialized_count, expected_count)))
+        .ok_or_else(|| errconv(ErrorKind::NotEnoughElements {
+            found: deserialized_count,
+            expected: expected_count,
+        }))
 }
 
 
@@ -468,20 +1519,26 @@ fn next_struct_element_seed<'de, K, V, A>(map: &mut A,
           A: MapAccess<'de>,
 {
     let next_key = map.next_key_seed(string_key_seed)?
-        .ok_or_else(|| errconv(DeErrorKind::NotEnoughElements(deserialized_count, expected_count)))?;
+        .ok_or_else(|| errconv(ErrorKind::NotEnoughElements {
+            found: deserialized_count,
+            expected: expected_count,
+        }))?;
 
     // Don't even try to deserialize value if keys don't match
     // (the reason behind not using `.next_entry_seed()`)
     if next_key != expected_key {
-        bail!(errconv::<A::Error>(DeErrorKind::InvalidMapKey(next_key, expected_key)));
+        bail!(errconv::<A::Error>(ErrorKind::InvalidMapKey {
+            found: next_key,
+            expected: expected_key,
+        }));
     }
 
     map.next_value_seed(value_seed)
 }
 
 
-fn errconv<E>(kind: DeErrorKind) -> E
+fn errconv<E>(kind: ErrorKind) -> E
     where E: DeError
 {
-    E::custom(error::Error::from(kind))
+    pass_through(error::Error::from(kind))
 }
@@ -1,5 +1,9 @@
 //! Integration & regression tests.
 
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "chrono")]
+extern crate chrono;
 //#[cfg(feature = "extprim")]
 //extern crate extprim;
 #[macro_use]
@@ -8,25 +12,38 @@ extern crate derivative;
 extern crate lazy_static;
 #[macro_use]
 extern crate maplit;
+#[cfg(feature = "num-bigint")]
+extern crate num_bigint;
 #[macro_use]
 extern crate pretty_assertions;
 extern crate serde;
 extern crate serde_bytes;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate serde_mtproto as serde_mtproto_other_name;    // Tests `serde_mtproto_derive`
 #[macro_use]
 extern crate serde_mtproto_derive;
 
 
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Cursor;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 //#[cfg(feature = "extprim")]
 //use extprim::i128::i128;
-use serde::de::{Deserializer, DeserializeSeed};
+use serde::de::{Deserialize, Deserializer, DeserializeSeed};
 use serde_bytes::ByteBuf;
-use serde_mtproto_other_name::{Boxed, MtProtoSized, UnsizedByteBuf, UnsizedByteBufSeed,
-                               to_bytes, to_writer, from_bytes, from_reader};
+use serde_mtproto_other_name::Deserializer as MtProtoDeserializer;
+use serde_mtproto_other_name::{Alignment, Boxed, BoxedWithSize, CharEncoding, Config, DecodeStatus, Decoder, Error, ErrorKind, FloatNarrowing, Identifiable, IntVector, MsgId, MtProtoDeserialize, MtProtoSerialize, MtProtoSized, Packed, PackedInts,
+                               Raw, Schema, SerializeIter, Serializer, StreamedBytes, TlDescribe, TlString, TypeIdVariant, UnsizedByteBuf, UnsizedByteBufSeed, UnsizedBytes, WithRaw, WithSize,
+                               assert_size_hint_matches, serialized_size, to_bytes, to_bytes_with_size, to_writer, from_bytes, from_bytes_into,
+                               from_bytes_reuse, from_reader, peek_bytes_len, peek_type_id,
+                               unsized_bytes_pad_to_bytes_with_alignment};
 
 
 #[derive(Debug, Derivative, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
@@ -39,7 +56,6 @@ struct Foo {
 
     #[derivative(PartialEq = "ignore")]
     #[serde(skip)]
-    #[mtproto_sized(skip)]
     to_be_skipped: i8,
 }
 
@@ -98,7 +114,8 @@ enum Cafebabe<T> {
     Bar {
         byte_id: i8,
         position: (u64, u32),
-        #[serde(bound(deserialize = "T: ::serde_mtproto_other_name::Identifiable"))]
+        #[serde(bound(serialize = "T: ::serde::Serialize + ::serde_mtproto_other_name::Identifiable",
+                      deserialize = "T: ::serde_mtproto_other_name::Identifiable"))]
         data: Boxed<T>,
         //#[cfg(feature = "extprim")]
         //bignum: i128,
@@ -529,3 +546,1647 @@ fn test_serialization_alignment() {
     assert!(CAFEBABE_QUUX_SERIALIZED_BOXED.len() % 4 == 0);
     assert!(CAFEBABE_SPAM_SERIALIZED_BOXED.len() % 4 == 0);
 }
+
+
+#[derive(Serialize)]
+struct NotSized {
+    name: String,
+    values: Vec<i64>,
+}
+
+#[test]
+fn test_with_size_back_patching() {
+    let expected = to_bytes(&WithSize::new(&*FOO).unwrap()).unwrap();
+    assert_eq!(to_bytes_with_size(&*FOO).unwrap(), expected);
+
+    let expected = to_bytes(&WithSize::new(&*CAFEBABE_BAZ).unwrap()).unwrap();
+    assert_eq!(to_bytes_with_size(&*CAFEBABE_BAZ).unwrap(), expected);
+}
+
+#[test]
+fn test_boxed_with_size_back_patching() {
+    let expected = to_bytes(&BoxedWithSize::new(&*POINT_3I).unwrap()).unwrap();
+
+    let mut ser = Serializer::new(Cursor::new(Vec::new()));
+    ser.serialize_boxed_with_size(&*POINT_3I).unwrap();
+
+    assert_eq!(ser.into_writer().into_inner(), expected);
+}
+
+#[test]
+fn test_with_size_back_patching_not_sized() {
+    let not_sized = NotSized {
+        name: "abc".to_owned(),
+        values: vec![1, -1],
+    };
+
+    let mut expected = vec![24, 0, 0, 0];    // size of the rest as little-endian 32-bit int
+    expected.extend(to_bytes(&not_sized).unwrap());
+
+    assert_eq!(to_bytes_with_size(&not_sized).unwrap(), expected);
+}
+
+
+fn with_declared_size(size: u32, data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8];
+    bytes.extend_from_slice(data);
+
+    bytes
+}
+
+#[test]
+fn test_with_size_enforced() {
+    let bytes = with_declared_size(12, &POINT_3I_SERIALIZED_BARE);
+    let with_size: WithSize<Point3I> = from_bytes(&bytes, None).unwrap();
+
+    assert_eq!(with_size.inner(), &*POINT_3I);
+}
+
+#[test]
+fn test_with_size_too_small() {
+    let mut bytes = with_declared_size(8, &POINT_3I_SERIALIZED_BARE);
+    bytes.extend_from_slice(&[0; 4]);
+
+    let err = from_bytes::<WithSize<Point3I>>(&bytes, None).unwrap_err();
+    match *err.kind() {
+        ErrorKind::SizeLimitExceeded { declared: 8, offset: 4, exceeded_by: 4 } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    // A read which starts within the declared size and crosses it
+    let mut bytes = with_declared_size(10, &POINT_3I_SERIALIZED_BARE);
+    bytes.extend_from_slice(&[0; 4]);
+
+    let err = from_bytes::<WithSize<Point3I>>(&bytes, None).unwrap_err();
+    match *err.kind() {
+        ErrorKind::SizeLimitExceeded { declared: 10, offset: 4, exceeded_by: 2 } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_with_size_too_large() {
+    let mut bytes = with_declared_size(16, &POINT_3I_SERIALIZED_BARE);
+    bytes.extend_from_slice(&[0; 4]);
+
+    let err = from_bytes::<WithSize<Point3I>>(&bytes, None).unwrap_err();
+    match *err.kind() {
+        ErrorKind::UnconsumedBytes { unconsumed: 4, declared: 16 } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_with_size_skip_trailing_bytes() {
+    let mut bytes = with_declared_size(16, &POINT_3I_SERIALIZED_BARE);
+    bytes.extend_from_slice(&[0xff; 4]);    // unknown trailing field
+    bytes.extend_from_slice(&[7, 0, 0, 0]);
+
+    let mut de = serde_mtproto_other_name::Deserializer::new(bytes.as_slice(), None)
+        .skip_trailing_bytes(true);
+    let with_size = WithSize::<Point3I>::deserialize(&mut de).unwrap();
+    let next = u32::deserialize(&mut de).unwrap();
+
+    assert_eq!(with_size.inner(), &*POINT_3I);
+    assert_eq!(with_size.size(), 16);
+    assert_eq!(next, 7);
+    assert_eq!(de.remaining_length(), 0);
+
+    // Skipped bytes are not declared again when serialized back
+    assert_eq!(to_bytes(&with_size).unwrap(), with_declared_size(12, &POINT_3I_SERIALIZED_BARE));
+    assert_eq!(from_bytes::<WithSize<Point3I>>(&to_bytes(&with_size).unwrap(), None).unwrap().inner(), &*POINT_3I);
+
+    let mut bytes = POINT_3I_SERIALIZED_BOXED[0..4].to_vec();
+    bytes.extend(with_declared_size(16, &POINT_3I_SERIALIZED_BARE));
+    bytes.extend_from_slice(&[0xff; 4]);
+
+    let mut de = serde_mtproto_other_name::Deserializer::new(bytes.as_slice(), None)
+        .skip_trailing_bytes(true);
+    let boxed_with_size = BoxedWithSize::<Point3I>::deserialize(&mut de).unwrap();
+
+    assert_eq!(boxed_with_size.size(), 16);
+    let mut expected = POINT_3I_SERIALIZED_BOXED[0..4].to_vec();
+    expected.extend(with_declared_size(12, &POINT_3I_SERIALIZED_BARE));
+    assert_eq!(to_bytes(&boxed_with_size).unwrap(), expected);
+}
+
+#[test]
+fn test_boxed_with_size_too_small() {
+    let mut bytes = POINT_3I_SERIALIZED_BOXED[0..4].to_vec();
+    bytes.extend(with_declared_size(4, &POINT_3I_SERIALIZED_BARE));
+
+    let err = from_bytes::<BoxedWithSize<Point3I>>(&bytes, None).unwrap_err();
+    match *err.kind() {
+        ErrorKind::SizeLimitExceeded { declared: 4, offset: 8, exceeded_by: 4 } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    msg_id: u64,
+    body: Raw<Boxed<Point3I>>,
+}
+
+#[test]
+fn test_raw_captures_bytes() {
+    let mut bytes = POINT_3I_SERIALIZED_BOXED.clone();
+    bytes.extend_from_slice(&[1, 2, 3, 4]);
+
+    let (raw, rest): (Raw<Boxed<Point3I>>, _) = from_bytes_reuse(&bytes, None).unwrap();
+
+    assert_eq!(raw.as_bytes(), POINT_3I_SERIALIZED_BOXED.as_slice());
+    assert_eq!(rest, &[1, 2, 3, 4]);
+    assert_eq!(raw.type_id(), 0xb01dface);
+    assert_eq!(raw.size_hint().unwrap(), POINT_3I_SERIALIZED_BOXED.len());
+    assert_eq!(raw.decode(None).unwrap().into_inner(), *POINT_3I);
+    assert_eq!(to_bytes(&raw).unwrap(), *POINT_3I_SERIALIZED_BOXED);
+}
+
+#[test]
+fn test_raw_bytes_from_foreign_deserializer() {
+    let json = serde_json::to_string(&Raw::new(&Boxed::new(Point3I(-35_000, 846, 1_029_748))).unwrap()).unwrap();
+    let raw: Raw<Boxed<Point3I>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(raw.as_bytes(), POINT_3I_SERIALIZED_BOXED.as_slice());
+    assert_eq!(raw.type_id(), 0xb01dface);
+
+    // Boxed values can't be shorter than their type id
+    assert!(serde_json::from_str::<Raw<Boxed<Point3I>>>("[]").is_err());
+    assert!(serde_json::from_str::<Raw<BoxedWithSize<Point3I>>>("[]").is_err());
+}
+
+#[test]
+fn test_with_raw_from_foreign_deserializer() {
+    let point = Point3I(-35_000, 846, 1_029_748);
+    let json = serde_json::to_string(&WithRaw::new(point).unwrap()).unwrap();
+    let with_raw: WithRaw<Point3I> = serde_json::from_str(&json).unwrap();
+    assert_eq!(with_raw.raw_bytes(), &POINT_3I_SERIALIZED_BOXED[4..]);
+
+    // All of the raw bytes must belong to the value
+    let json = serde_json::to_string(&Raw::new(&(Point3I(-35_000, 846, 1_029_748), 7u32)).unwrap()).unwrap();
+    let err = serde_json::from_str::<WithRaw<Point3I>>(&json).unwrap_err();
+    assert_eq!(err.to_string(), "4 bytes left unconsumed out of the declared size of 16 bytes");
+}
+
+#[test]
+fn test_raw_in_struct() {
+    let mut bytes = vec![42, 0, 0, 0, 0, 0, 0, 0];
+    bytes.extend_from_slice(&POINT_3I_SERIALIZED_BOXED);
+
+    let envelope: Envelope = from_bytes(&bytes, None).unwrap();
+
+    assert_eq!(envelope.msg_id, 42);
+    assert_eq!(envelope.body.as_bytes(), POINT_3I_SERIALIZED_BOXED.as_slice());
+    assert_eq!(to_bytes(&envelope).unwrap(), bytes);
+}
+
+#[test]
+fn test_with_raw_keeps_bytes() {
+    let with_raw: WithRaw<Foo> = from_bytes(&FOO_SERIALIZED_BARE, None).unwrap();
+
+    assert_eq!(with_raw.inner(), &*FOO);
+    assert_eq!(with_raw.raw_bytes(), FOO_SERIALIZED_BARE.as_slice());
+    assert_eq!(to_bytes(&with_raw).unwrap(), *FOO_SERIALIZED_BARE);
+    assert_eq!(WithRaw::new(Point3I(-35_000, 846, 1_029_748)).unwrap().raw_bytes(),
+               POINT_3I_SERIALIZED_BARE.as_slice());
+}
+
+
+#[test]
+fn test_error_is_send_sync() {
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+
+    assert_send_sync::<Error>();
+}
+
+#[test]
+fn test_error_passed_through_foreign_deserializer() {
+    use serde::de::Error as DeError;
+    use serde::de::value::{Error as ValueError, SeqDeserializer};
+
+    // Wrappers report structured errors through the error type of whatever deserializer they run in
+    let de = SeqDeserializer::<_, ValueError>::new(vec![0xdeadbeefu32].into_iter());
+    let foreign_err = Boxed::<CLike>::deserialize(de).unwrap_err();
+
+    // An unrelated error with the same message must not pick up the structured error
+    match *Error::custom(foreign_err.to_string()).kind() {
+        ErrorKind::DeCustom(_) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    // With `serde_mtproto` the structured error is kept intact
+    match *from_bytes::<Boxed<CLike>>(&[0xef, 0xbe, 0xad, 0xde], None).unwrap_err().kind() {
+        ErrorKind::InvalidTypeId { found: 0xdeadbeef, .. } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    // Errors created while formatting the message of another one don't interfere
+    struct Nested;
+
+    impl ::std::fmt::Display for Nested {
+        fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+            let inner = from_bytes::<Boxed<CLike>>(&[0xef, 0xbe, 0xad, 0xde], None).unwrap_err();
+            write!(f, "nested: {}", inner)
+        }
+    }
+
+    match *Error::custom(Nested).kind() {
+        ErrorKind::DeCustom(ref msg) => assert!(msg.starts_with("nested: ")),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_unexpected_eof_offset() {
+    let bytes = &POINT_3I_SERIALIZED_BOXED[0..10];
+
+    let err = from_bytes::<Boxed<Point3I>>(bytes, None).unwrap_err();
+    assert!(err.is_eof());
+    assert!(!err.is_data());
+    match *err.kind() {
+        ErrorKind::UnexpectedEof { offset: 10 } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_invalid_type_id() {
+    let mut bytes = POINT_3I_SERIALIZED_BOXED.clone();
+    bytes[0] = 0x00;
+
+    let err = from_bytes::<Boxed<Point3I>>(&bytes, None).unwrap_err();
+    assert!(err.is_data());
+    match *err.kind() {
+        ErrorKind::InvalidTypeId { found: 0xb01dfa00, expected } => {
+            assert_eq!(expected, &[0xb01dface]);
+        },
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_variant_for_id() {
+    for (index, &id) in Cafebabe::<u32>::all_type_ids().iter().enumerate() {
+        assert_eq!(Cafebabe::<u32>::variant_for_id(id).unwrap().index, index);
+    }
+
+    assert_eq!(CLike::variant_for_id(0xca55e77e), Some(TypeIdVariant { index: 1, name: Some("B") }));
+    assert_eq!(CLike::variant_for_id(0xca55e77f), None);
+    assert_eq!(Point3I::variant_for_id(0xb01dface), Some(TypeIdVariant { index: 0, name: None }));
+    assert_eq!(Boxed::<Point3I>::variant_for_id(0xb01dfa00), None);
+    assert_eq!(<bool as Identifiable>::variant_for_id(0xbc799737), Some(TypeIdVariant { index: 1, name: None }));
+}
+
+#[test]
+fn test_type_id_mismatch() {
+    // The type id of `CLike::A` followed by `CLike::B` selected by the variant hint
+    let err = from_bytes::<Boxed<CLike>>(&[0x1e, 0xab, 0xa1, 0x5c], Some("B")).unwrap_err();
+    match *err.kind() {
+        ErrorKind::TypeIdMismatch { found: 0x5ca1ab1e, expected: 0xca55e77e, found_variant, expected_variant } => {
+            assert_eq!((found_variant, expected_variant), (Some("A"), Some("B")));
+        },
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+    assert_eq!(err.to_string(),
+               "type id mismatch: deserialized 0x5ca1ab1e (`A`), but 0xca55e77e (`B`) found from value");
+}
+
+
+#[test]
+fn test_serialized_size() {
+    assert_eq!(serialized_size(&*FOO).unwrap(), FOO_SERIALIZED_BARE.len());
+    assert_eq!(serialized_size(&Boxed::new(&*FOO)).unwrap(), FOO_SERIALIZED_BOXED.len());
+    assert_eq!(serialized_size(&*POINT_3I).unwrap(), POINT_3I_SERIALIZED_BARE.len());
+    assert_eq!(serialized_size(&Boxed::new(&*CAFEBABE_SPAM)).unwrap(), CAFEBABE_SPAM_SERIALIZED_BOXED.len());
+
+    let not_sized = NotSized { name: "abc".to_owned(), values: vec![1, 2, 3] };
+    assert_eq!(serialized_size(&not_sized).unwrap(), to_bytes(&not_sized).unwrap().len());
+}
+
+#[test]
+fn test_size_hint_matches_serialized_size() {
+    assert_size_hint_matches(&*FOO);
+    assert_size_hint_matches(&Boxed::new(&*FOO));
+    assert_size_hint_matches(&*MESSAGE);
+    assert_size_hint_matches(&*POINT_3I);
+    assert_size_hint_matches(&*WRAPPER);
+    assert_size_hint_matches(&*CAFEBABE_SPAM);
+    assert_size_hint_matches(&-5isize);
+    assert_size_hint_matches(&(10usize, b'x'));
+}
+
+#[test]
+fn test_size_hint_std_types() {
+    assert_size_hint_matches("the str");
+    assert_size_hint_matches(&[1u16, 2, 3][..]);
+    assert_size_hint_matches(&vec![-1i64, 2].into_boxed_slice());
+    assert_size_hint_matches(&"boxed".to_owned().into_boxed_str());
+    assert_size_hint_matches(&VecDeque::from(vec![1u32, 2, 3]));
+    assert_size_hint_matches(&hashset!{ "a".to_owned(), "bcdef".to_owned() });
+    assert_size_hint_matches(&btreeset!{ 1i8, -2 });
+    assert_size_hint_matches(&Cow::Borrowed("cow"));
+    assert_size_hint_matches(&Cow::<[u8]>::Owned(vec![1, 2, 3]));
+    assert_size_hint_matches(&[7u8; 32]);
+    assert_size_hint_matches(&-5i128);
+    assert_size_hint_matches(&u128::max_value());
+    assert_size_hint_matches(&Rc::new(vec!["shared".to_owned()]));
+    assert_size_hint_matches(&Arc::new(vec!["shared".to_owned()]));
+    assert_size_hint_matches(&Some(vec!["present".to_owned()]));
+    assert_size_hint_matches(&None::<Vec<String>>);
+}
+
+#[test]
+fn test_conditional_fields() {
+    #[derive(Serialize, MtProtoSized)]
+    struct User {
+        flags: u32,
+        id: i64,
+        username: Option<String>,
+        access_hash: Option<i64>,
+    }
+
+    let user = User { flags: 0b01, id: 5, username: Some("abc".to_owned()), access_hash: None };
+    assert_size_hint_matches(&user);
+    assert_eq!(to_bytes(&user).unwrap(), [
+        0x01, 0x00, 0x00, 0x00,
+        0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x03, b'a', b'b', b'c',
+    ]);
+}
+
+#[test]
+fn test_identifiable_std_types() {
+    assert_eq!(<&[u32]>::all_type_ids(), &[0x1cb5c415]);
+    assert_eq!((&[1u32, 2][..]).type_id(), 0x1cb5c415);
+    assert_eq!("str".to_owned().into_boxed_str().type_id(), 0xb5286e24);
+    assert_eq!(Rc::new(CLike::B).type_id(), 0xca55e77e);
+    assert_eq!(Arc::new(CLike::C).enum_variant_id(), Some("C"));
+    assert_eq!(Arc::<CLike>::variant_for_id(0xf007ba11).map(|v| v.index), Some(2));
+
+    let value = -0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10i128;
+    assert_eq!(value.type_id(), 0x84ccf7b7);
+    assert_eq!(u128::all_type_ids(), &[0x84ccf7b7]);
+
+    let bytes = to_bytes(&Boxed::new(value)).unwrap();
+    assert_eq!(bytes, [
+        0xb7, 0xf7, 0xcc, 0x84,
+        0xf0, 0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe,
+    ]);
+    assert_eq!(from_bytes::<Boxed<i128>>(&bytes, None).unwrap().into_inner(), value);
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, MtProtoSized)]
+struct MessageIds {
+    nonce: PackedInts<u32, 4>,
+    ids: IntVector<i32>,
+    random_ids: IntVector<i64>,
+    pts: u32,
+}
+
+#[test]
+fn test_packed_ints() {
+    let ids = vec![1, -2, 0x0304_0506, i32::min_value()];
+    let random_ids = vec![0x0102_0304_0506_0708, -1];
+    let nonce = [0xdead_beef, 0, 1, u32::max_value()];
+
+    let bytes = to_bytes(&IntVector::new(ids.clone())).unwrap();
+    assert_eq!(bytes, to_bytes(&ids).unwrap());
+    assert_eq!(from_bytes::<IntVector<i32>>(&bytes, None).unwrap().into_inner(), ids);
+    assert_eq!(to_bytes(&IntVector::new(random_ids.clone())).unwrap(), to_bytes(&random_ids).unwrap());
+    assert_eq!(to_bytes(&PackedInts::new(nonce)).unwrap(), to_bytes(&nonce).unwrap());
+
+    let message_ids = MessageIds {
+        nonce: PackedInts::new(nonce),
+        ids: IntVector::new(ids),
+        random_ids: random_ids.into(),
+        pts: 42,
+    };
+
+    let bytes = to_bytes(&message_ids).unwrap();
+    assert_eq!(bytes.len(), 16 + (4 + 16) + (4 + 16) + 4);
+    assert_eq!(&bytes[bytes.len() - 4..], &[42, 0, 0, 0]);
+    assert_eq!(from_bytes::<MessageIds>(&bytes, None).unwrap(), message_ids);
+    assert_size_hint_matches(&message_ids);
+
+    let err = from_bytes::<MessageIds>(&bytes[..30], None).unwrap_err();
+    assert!(err.is_eof());
+    let err = from_bytes::<PackedInts<u32, 4>>(&bytes[..10], None).unwrap_err();
+    assert!(err.is_eof());
+
+    // A forged length fails on the missing bytes instead of allocating gigabytes up front
+    let err = from_bytes::<IntVector<i64>>(&[0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 7, 8], None).unwrap_err();
+    match *err.kind() {
+        ErrorKind::UnexpectedEof { offset: 12 } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[derive(Serialize, MtProtoSized)]
+struct WrongSizeHint {
+    #[serde(serialize_with = "as_long::serialize")]
+    #[mtproto_sized(size_with = "u8_size_hint")]
+    sized_with_wrong_hook: u32,
+    value: u32,
+}
+
+#[test]
+#[should_panic(expected = "size_hint() is 8 bytes, but the value serializes to 12 bytes")]
+fn test_size_hint_mismatch_detected() {
+    assert_size_hint_matches(&WrongSizeHint { sized_with_wrong_hook: 1, value: 2 });
+}
+
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+enum Media {
+    #[id = "0x3ded6320"]
+    Empty,
+    #[id = "0x7912b71f"]
+    Geo {
+        lat: f64,
+        long: f64,
+    },
+    #[mtproto(other)]
+    Unknown {
+        id: u32,
+        raw: Vec<u8>,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct MediaMessage {
+    media: BoxedWithSize<Media>,
+    views: u32,
+}
+
+lazy_static! {
+    static ref MEDIA_UNKNOWN_SERIALIZED_BOXED: Vec<u8> = vec![
+        0x78, 0x56, 0x34, 0x12,    // unknown type id
+        0x01, 0x02, 0x03, 0x04,    // raw data
+        0x05, 0x06, 0x07, 0x08,
+    ];
+
+    static ref MEDIA_MESSAGE_SERIALIZED: Vec<u8> = vec![
+        0x78, 0x56, 0x34, 0x12,    // unknown type id
+        0x08, 0x00, 0x00, 0x00,    // declared size
+        0x01, 0x02, 0x03, 0x04,    // raw data
+        0x05, 0x06, 0x07, 0x08,
+        0x2a, 0x00, 0x00, 0x00,    // views
+    ];
+}
+
+#[test]
+fn test_unknown_variant_boxed() {
+    // The declared size of `WithSize` determines the extent of the unknown value
+    let mut bytes = vec![0x0c, 0x00, 0x00, 0x00];
+    bytes.extend_from_slice(&MEDIA_UNKNOWN_SERIALIZED_BOXED);
+    let mut media = from_bytes::<WithSize<Boxed<Media>>>(&bytes, None).unwrap().into_inner();
+
+    assert_eq!(media.type_id(), 0x12345678);
+    assert_eq!(*media.inner(), Media::Unknown {
+        id: 0x12345678,
+        raw: vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+    });
+    assert_eq!(to_bytes(&media).unwrap(), *MEDIA_UNKNOWN_SERIALIZED_BOXED);
+    assert_size_hint_matches(&media);
+
+    // Raw bytes are always taken from the wrapped value
+    if let Media::Unknown { ref mut raw, .. } = *media.inner_mut() {
+        raw.truncate(4);
+    }
+    assert_eq!(to_bytes(&media).unwrap(), &MEDIA_UNKNOWN_SERIALIZED_BOXED[..8]);
+}
+
+#[test]
+fn test_unknown_variant_boxed_with_size() {
+    let message: MediaMessage = from_bytes(&*MEDIA_MESSAGE_SERIALIZED, None).unwrap();
+
+    assert_eq!(message.media.inner().type_id(), 0x12345678);
+    assert_eq!(message.views, 42);
+    assert_eq!(to_bytes(&message).unwrap(), *MEDIA_MESSAGE_SERIALIZED);
+    assert_size_hint_matches(&message.media);
+
+    let mut ser = Serializer::new(Cursor::new(Vec::new()));
+    ser.serialize_boxed_with_size(message.media.inner()).unwrap();
+    assert_eq!(ser.into_writer().into_inner(), &MEDIA_MESSAGE_SERIALIZED[..16]);
+}
+
+#[derive(Debug, Deserialize)]
+struct BoxedMediaMessage {
+    media: Boxed<Media>,
+    views: u32,
+}
+
+#[test]
+fn test_unknown_variant_without_declared_size() {
+    fn assert_invalid_type_id(err: Error) {
+        match *err.kind() {
+            ErrorKind::InvalidTypeId { found: 0x12345678, .. } => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    assert_invalid_type_id(from_bytes::<Boxed<Media>>(&*MEDIA_UNKNOWN_SERIALIZED_BOXED, None).unwrap_err());
+
+    // An unknown value must not swallow the following field...
+    let mut bytes = MEDIA_UNKNOWN_SERIALIZED_BOXED.clone();
+    bytes.extend_from_slice(&[0x2a, 0x00, 0x00, 0x00]);
+    assert_invalid_type_id(from_bytes::<BoxedMediaMessage>(&bytes, None).unwrap_err());
+
+    // ...or the following vector elements
+    let geo = Media::Geo { lat: 1.5, long: -2.5 };
+    let mut bytes = vec![0x02, 0x00, 0x00, 0x00];
+    bytes.extend_from_slice(&MEDIA_UNKNOWN_SERIALIZED_BOXED);
+    bytes.extend_from_slice(&to_bytes(&Boxed::new(&geo)).unwrap());
+    assert_invalid_type_id(from_bytes::<Vec<Boxed<Media>>>(&bytes, None).unwrap_err());
+
+    // Each declared size delimits its own unknown value
+    let media = vec![
+        BoxedWithSize::new(Media::Unknown { id: 0x12345678, raw: vec![0x01, 0x02, 0x03, 0x04] }).unwrap(),
+        BoxedWithSize::new(Media::Unknown { id: 0x9abcdef0, raw: vec![0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c] }).unwrap(),
+    ];
+    let bytes = to_bytes(&media).unwrap();
+    assert_eq!(from_bytes::<Vec<BoxedWithSize<Media>>>(&bytes, None).unwrap(), media);
+}
+
+#[test]
+fn test_unknown_variant_known_id() {
+    let geo = Boxed::new(Media::Geo { lat: 1.5, long: -2.5 });
+    let bytes = to_bytes(&geo).unwrap();
+
+    assert_eq!(bytes.len(), 20);
+    assert_eq!(from_bytes::<Boxed<Media>>(&bytes, Some("Geo")).unwrap(), geo);
+    assert_eq!(<Media as Identifiable>::all_type_ids(), &[0x3ded6320, 0x7912b71f]);
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[id = "0x2f3e4d5c"]
+struct Updates {
+    texts: Vec<String>,
+    seq: u32,
+}
+
+#[test]
+fn test_from_bytes_into_reuses_allocations() {
+    let updates = Boxed::new(Updates {
+        texts: vec!["first".to_owned(), "second".to_owned()],
+        seq: 1,
+    });
+    let bytes = to_bytes(&updates).unwrap();
+
+    let mut place = Boxed::new(Updates {
+        texts: vec![String::with_capacity(64), String::with_capacity(64), String::with_capacity(64)],
+        seq: 0,
+    });
+    let texts_ptr = place.inner().texts.as_ptr();
+    let text_ptr = place.inner().texts[0].as_ptr();
+
+    from_bytes_into(&mut place, &bytes, None).unwrap();
+
+    assert_eq!(place, updates);
+    assert_eq!(place.inner().texts.as_ptr(), texts_ptr);
+    assert_eq!(place.inner().texts[0].as_ptr(), text_ptr);
+}
+
+#[test]
+fn test_from_bytes_into_with_size() {
+    let value = WithSize::new(Updates { texts: vec!["abc".to_owned()], seq: 7 }).unwrap();
+    let bytes = to_bytes(&value).unwrap();
+
+    let mut place = WithSize::new(Updates { texts: vec![], seq: 0 }).unwrap();
+    from_bytes_into(&mut place, &bytes, None).unwrap();
+    assert_eq!(place, value);
+
+    let mut place = BoxedWithSize::new(Media::Empty).unwrap();
+    from_bytes_into(&mut place, &*MEDIA_MESSAGE_SERIALIZED, None).unwrap();
+    assert_eq!(to_bytes(&place).unwrap(), &MEDIA_MESSAGE_SERIALIZED[..16]);
+}
+
+#[test]
+fn test_from_bytes_into_invalid_type_id() {
+    let mut bytes = to_bytes(&Boxed::new(Updates { texts: vec![], seq: 0 })).unwrap();
+    bytes[0] = 0x00;
+
+    let mut place = Boxed::new(Updates { texts: vec![], seq: 0 });
+    let err = from_bytes_into(&mut place, &bytes, None).unwrap_err();
+    match *err.kind() {
+        ErrorKind::InvalidTypeId { found: 0x2f3e4d00, .. } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoSized)]
+struct Frame {
+    auth_key_id: i64,
+    #[serde(deserialize_with = "deserialize_frame_data")]
+    data: UnsizedByteBuf,
+}
+
+fn deserialize_frame_data<'de, D>(deserializer: D) -> Result<UnsizedByteBuf, D::Error>
+    where D: Deserializer<'de>
+{
+    UnsizedByteBufSeed::to_end().with_alignment(Alignment::Four).deserialize(deserializer)
+}
+
+#[test]
+fn test_unsized_bytes_serialization() {
+    let bytes = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18];
+
+    let serialized = to_bytes(&UnsizedBytes::new(&bytes)).unwrap();
+    assert_eq!(serialized, to_bytes(&UnsizedByteBuf::new(bytes.to_vec())).unwrap());
+    assert_eq!(serialized, &MESSAGE_SERIALIZED_BARE[24..]);
+
+    let aligned_to_4 = UnsizedBytes::with_alignment(&bytes, Alignment::Four);
+    assert_eq!(to_bytes(&aligned_to_4).unwrap(), &serialized[..20]);
+    assert_eq!(to_bytes(&aligned_to_4).unwrap(),
+               unsized_bytes_pad_to_bytes_with_alignment(&bytes, Alignment::Four).unwrap());
+    assert_size_hint_matches(&aligned_to_4);
+    assert_size_hint_matches(&UnsizedBytes::new(&bytes));
+}
+
+#[test]
+fn test_unsized_byte_buf_to_end() {
+    let frame = Frame {
+        auth_key_id: 0x0123_4567_89ab_cdef,
+        data: UnsizedByteBuf::with_alignment(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12], Alignment::Four),
+    };
+    let bytes = to_bytes(&frame).unwrap();
+    assert_eq!(bytes.len(), 20);
+    assert_eq!(from_bytes::<Frame>(&bytes, None).unwrap(), frame);
+
+    // Inside of a sized value only the declared size is read
+    let mut bytes = to_bytes(&WithSize::new(&frame).unwrap()).unwrap();
+    bytes.extend_from_slice(&[0xff; 8]);
+    let (deserialized, rest): (WithSize<Frame>, _) = from_bytes_reuse(&bytes, None).unwrap();
+    assert_eq!(*deserialized.inner(), frame);
+    assert_eq!(rest, &[0xff; 8]);
+}
+
+#[test]
+fn test_unsized_byte_buf_to_end_misaligned() {
+    let mut bytes = to_bytes(&0x0123_4567_89ab_cdef_i64).unwrap();
+    bytes.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+
+    assert!(from_bytes::<Frame>(&bytes, None).is_err());
+}
+
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, MtProtoSized)]
+struct BindAuthKey {
+    perm_auth_key_id: i64,
+    encrypted_message: Packed<Boxed<Foo>>,
+}
+
+#[test]
+fn test_packed() {
+    let value = BindAuthKey {
+        perm_auth_key_id: -1,
+        encrypted_message: Packed::new(Boxed::new(Foo {
+            has_receiver: true,
+            size: 57,
+            raw_info: ByteBuf::from(vec![56, 114, 200, 1]),
+            to_be_skipped: 0,
+        })),
+    };
+
+    let mut expected = vec![0xff; 8];
+    expected.push(24);
+    expected.extend_from_slice(&*FOO_SERIALIZED_BOXED);
+    expected.extend_from_slice(&[0, 0, 0]);
+
+    let bytes = to_bytes(&value).unwrap();
+    assert_eq!(bytes, expected);
+    assert_size_hint_matches(&value);
+    assert_eq!(from_bytes::<BindAuthKey>(&bytes, None).unwrap(), value);
+
+    // Boxed packed values are prefixed with the id of `bytes`, not of the wrapped value
+    let boxed = to_bytes(&Boxed::new(value.encrypted_message)).unwrap();
+    assert_eq!(&boxed[..4], &[0x24, 0x6e, 0x28, 0xb5]);
+    assert_eq!(&boxed[4..], &bytes[8..]);
+}
+
+#[test]
+fn test_packed_unconsumed_bytes() {
+    let mut inner = FOO_SERIALIZED_BOXED.clone();
+    inner.extend_from_slice(&[1, 2, 3, 4]);
+    let bytes = to_bytes(&ByteBuf::from(inner)).unwrap();
+
+    let err = from_bytes::<Packed<Boxed<Foo>>>(&bytes, None).unwrap_err();
+    match *err.kind() {
+        ErrorKind::UnconsumedBytes { unconsumed: 4, declared: 28 } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_packed_follows_deserializer_settings() {
+    // Bare enums in packed values get the enum variant hint
+    let bytes = to_bytes(&Packed::new(CLike::B)).unwrap();
+    assert_eq!(from_bytes::<Packed<CLike>>(&bytes, Some("B")).unwrap().into_inner(), CLike::B);
+
+    let mut inner = FOO_SERIALIZED_BOXED.clone();
+    inner.extend_from_slice(&[1, 2, 3, 4]);
+    let mut bytes = to_bytes(&ByteBuf::from(inner)).unwrap();
+    bytes.extend_from_slice(&[7, 0, 0, 0]);
+
+    let mut de = serde_mtproto_other_name::Deserializer::new(bytes.as_slice(), None)
+        .skip_trailing_bytes(true);
+    let packed = Packed::<Boxed<Foo>>::deserialize(&mut de).unwrap();
+
+    assert_eq!(*packed.inner().inner(), *FOO);
+    assert_eq!(u32::deserialize(&mut de).unwrap(), 7);
+}
+
+
+#[derive(Serialize, MtProtoSized)]
+struct FilePart {
+    file_id: i64,
+    bytes: StreamedBytes<Cursor<Vec<u8>>>,
+}
+
+#[derive(Serialize)]
+struct FilePartBuf {
+    file_id: i64,
+    bytes: ByteBuf,
+}
+
+#[test]
+fn test_streamed_bytes() {
+    for &len in &[0, 5, 300, 70_001] {
+        let data = (0..len).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+
+        let part = FilePart {
+            file_id: 42,
+            bytes: StreamedBytes::new(Cursor::new(data.clone()), len as u32),
+        };
+        let expected = to_bytes(&FilePartBuf { file_id: 42, bytes: ByteBuf::from(data.clone()) }).unwrap();
+
+        assert_eq!(part.size_hint().unwrap(), expected.len());
+        assert_eq!(to_bytes(&part).unwrap(), expected);
+
+        let mut de = MtProtoDeserializer::new(&expected[..], None);
+        let file_id = i64::deserialize(&mut de).unwrap();
+        let mut streamed = Vec::new();
+        assert_eq!(de.stream_bytes_to(&mut streamed).unwrap(), len as u64);
+        assert_eq!(file_id, 42);
+        assert_eq!(streamed, data);
+        assert_eq!(de.remaining_length(), 0);
+    }
+}
+
+#[test]
+fn test_streamed_bytes_reader_too_short() {
+    let part = FilePart {
+        file_id: 42,
+        bytes: StreamedBytes::new(Cursor::new(vec![1, 2, 3]), 4),
+    };
+
+    assert!(to_bytes(&part).is_err());
+}
+
+#[test]
+fn test_streamed_bytes_serialized_once() {
+    let part = FilePart {
+        file_id: 42,
+        bytes: StreamedBytes::new(Cursor::new(vec![1, 2, 3, 4, 5, 6, 7, 8]), 4),
+    };
+    let expected = to_bytes(&FilePartBuf { file_id: 42, bytes: ByteBuf::from(vec![1, 2, 3, 4]) }).unwrap();
+
+    assert_eq!(serialized_size(&part).unwrap(), expected.len());
+
+    // The reader has 4 more bytes, but they don't belong to the value anymore
+    assert!(to_bytes(&part).is_err());
+    assert!(part.size_hint().is_err());
+    assert_eq!(part.bytes.into_reader().position(), 4);
+}
+
+
+#[test]
+fn test_vector_iter() {
+    let texts = vec!["first".to_owned(), "second".to_owned(), "third".to_owned()];
+    let mut bytes = to_bytes(&texts).unwrap();
+    bytes.extend(to_bytes(&Boxed::new(vec![1u32, 2])).unwrap());
+
+    let mut de = MtProtoDeserializer::new(&bytes[..], None);
+    {
+        let mut iter = de.vector_iter::<String>().unwrap();
+        assert_eq!(iter.len(), 3);
+        assert_eq!(iter.next().unwrap().unwrap(), "first");
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.collect::<Result<Vec<_>, _>>().unwrap(), &texts[1..]);
+    }
+
+    let numbers = de.boxed_vector_iter::<u32>().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(numbers, vec![1, 2]);
+    assert_eq!(de.remaining_length(), 0);
+}
+
+#[test]
+fn test_vector_iter_stops_on_error() {
+    let bytes = to_bytes(&vec![1u32, 2, 3]).unwrap();
+
+    let mut de = MtProtoDeserializer::new(&bytes[..bytes.len() - 2], None);
+    let results = de.vector_iter::<u32>().unwrap().collect::<Vec<_>>();
+    assert_eq!(results.len(), 3);
+    assert!(results[2].as_ref().unwrap_err().is_eof());
+
+    let mut de = MtProtoDeserializer::new(&bytes[..], None);
+    assert!(de.boxed_vector_iter::<u32>().is_err());
+}
+
+#[test]
+fn test_serialize_iter() {
+    let iter = SerializeIter::new((0..5u32).map(|x| x * 2));
+    assert_eq!(to_bytes(&iter).unwrap(), to_bytes(&vec![0u32, 2, 4, 6, 8]).unwrap());
+
+    // The iterator is consumed at this point
+    assert!(to_bytes(&iter).is_err());
+
+    // Computing the serialized size consumes it as well
+    let iter = SerializeIter::new(vec![1u64, 2].into_iter());
+    assert_eq!(serialized_size(&iter).unwrap(), 20);
+    assert!(serialized_size(&iter).is_err());
+}
+
+
+#[cfg(feature = "bytes")]
+#[derive(Debug, PartialEq, Serialize, Deserialize, MtProtoSized, MtProtoSerialize, MtProtoDeserialize)]
+#[id = 0x096a18d5]
+struct UploadFile {
+    mtime: i32,
+    bytes: serde_mtproto_other_name::SharedBytes,
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_shared_bytes() {
+    use bytes::{Bytes, BytesMut};
+    use serde_mtproto_other_name::{from_buf, to_buf_mut};
+
+    let data = (0..300).map(|i| i as u8).collect::<Vec<u8>>();
+    let value = UploadFile { mtime: 7, bytes: Bytes::from(data.clone()).into() };
+
+    let mut buf = BytesMut::new();
+    to_buf_mut(&mut buf, &value).unwrap();
+    to_buf_mut(&mut buf, &5u32).unwrap();
+    assert_eq!(&buf[..buf.len() - 4], &to_bytes(&value).unwrap()[..]);
+
+    let mut input = buf.freeze();
+    let range = input.as_ptr() as usize..input.as_ptr() as usize + input.len();
+    let deserialized = {
+        let mut de = MtProtoDeserializer::from_buf(&mut input, None);
+        UploadFile::deserialize_bare(&mut de).unwrap()
+    };
+    assert_eq!(deserialized, value);
+    // The byte sequence is a slice of the input
+    assert!(range.contains(&(deserialized.bytes.as_ptr() as usize)));
+
+    assert_eq!(from_buf::<_, u32>(&mut input, None).unwrap(), 5);
+
+    // Serde can only copy the byte sequence
+    let mut input = Bytes::from(to_bytes(&value).unwrap());
+    let range = input.as_ptr() as usize..input.as_ptr() as usize + input.len();
+    let deserialized: UploadFile = from_buf(&mut input, None).unwrap();
+    assert_eq!(deserialized, value);
+    assert!(!range.contains(&(deserialized.bytes.as_ptr() as usize)));
+
+    // Without a `Buf` the byte sequence is copied as well
+    let bytes = to_bytes(&value).unwrap();
+    assert_eq!(from_bytes::<UploadFile>(&bytes, None).unwrap(), value);
+    let mut de = MtProtoDeserializer::new(&bytes[..], None);
+    assert_eq!(UploadFile::deserialize_bare(&mut de).unwrap(), value);
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_shared_bytes_unexpected_eof() {
+    use bytes::Bytes;
+    use serde_mtproto_other_name::from_buf;
+
+    let value = UploadFile { mtime: 7, bytes: Bytes::from(vec![1, 2, 3, 4, 5, 6]).into() };
+    let bytes = to_bytes(&value).unwrap();
+
+    let err = from_buf::<_, UploadFile>(Bytes::from(bytes[..8].to_vec()), None).unwrap_err();
+    assert!(err.is_eof());
+}
+
+
+#[test]
+fn test_peek_type_id() {
+    assert_eq!(peek_type_id(&*FOO_SERIALIZED_BOXED).unwrap(), 0xdeadbeef);
+    assert_eq!(peek_bytes_len(&to_bytes(&"x".repeat(300)).unwrap()).unwrap(), 300);
+    assert!(peek_type_id(&FOO_SERIALIZED_BOXED[..3]).unwrap_err().is_eof());
+
+    // A tiny buffer makes peeked bytes span several reads
+    let reader = ::std::io::BufReader::with_capacity(3, &FOO_SERIALIZED_BOXED[..]);
+    let mut de = MtProtoDeserializer::new(reader, None);
+    assert_eq!(de.peek_type_id().unwrap(), 0xdeadbeef);
+    assert_eq!(de.peek_u32().unwrap(), 0xdeadbeef);
+    assert_eq!(de.position(), 0);
+
+    let foo: Boxed<Foo> = Deserialize::deserialize(&mut de).unwrap();
+    assert_eq!(*foo.inner(), *FOO);
+    assert_eq!(de.position(), FOO_SERIALIZED_BOXED.len() as u64);
+}
+
+#[test]
+fn test_peek_lengths() {
+    let mut bytes = to_bytes(&vec![1u32, 2, 3]).unwrap();
+    bytes.extend(to_bytes(&"abc").unwrap());
+
+    let mut de = MtProtoDeserializer::new(&bytes[..], None);
+    assert_eq!(de.peek_u32().unwrap(), 3);
+    assert_eq!(de.remaining_length(), bytes.len());
+    assert_eq!(Vec::<u32>::deserialize(&mut de).unwrap(), vec![1, 2, 3]);
+
+    assert_eq!(de.peek_bytes_len().unwrap(), 3);
+    assert_eq!(String::deserialize(&mut de).unwrap(), "abc");
+    assert_eq!(de.remaining_length(), 0);
+}
+
+#[test]
+fn test_skip_value_by_type_id() {
+    let mut bytes = FOO_SERIALIZED_BOXED.clone();
+    bytes.extend(to_bytes(&Boxed::new(Updates { texts: vec!["abc".to_owned()], seq: 1 })).unwrap());
+
+    let mut de = MtProtoDeserializer::new(&bytes[..], None);
+    assert_eq!(de.skip_value_by_type_id::<Foo>().unwrap(), 0xdeadbeef);
+
+    match *de.skip_value_by_type_id::<Foo>().unwrap_err().kind() {
+        ErrorKind::InvalidTypeId { found: 0x2f3e4d5c, .. } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+    assert_eq!(de.position(), FOO_SERIALIZED_BOXED.len() as u64);
+
+    assert_eq!(de.skip_value_by_type_id::<Updates>().unwrap(), 0x2f3e4d5c);
+    assert_eq!(de.remaining_length(), 0);
+}
+
+
+#[test]
+fn test_decoder_byte_by_byte() {
+    let mut decoder = Decoder::<Boxed<Foo>>::new(None);
+    let (last, init) = FOO_SERIALIZED_BOXED.split_last().unwrap();
+
+    for byte in init {
+        match decoder.feed(&[*byte]).unwrap() {
+            DecodeStatus::Incomplete { .. } => (),
+            DecodeStatus::Complete(..) => panic!("decoded a value from incomplete input"),
+        }
+    }
+    assert_eq!(decoder.buffered_len(), init.len());
+
+    match decoder.feed(&[*last, 0xff]).unwrap() {
+        DecodeStatus::Complete(foo, 1) => assert_eq!(*foo.inner(), *FOO),
+        status => panic!("unexpected status: {:?}", status),
+    }
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn test_decoder_needed_from_declared_size() {
+    let value = WithSize::new(Updates { texts: vec!["x".repeat(100)], seq: 9 }).unwrap();
+    let mut bytes = to_bytes(&value).unwrap();
+    let len = bytes.len();
+    bytes.extend(to_bytes(&value).unwrap());
+
+    let mut decoder = Decoder::<WithSize<Updates>>::new(None);
+    assert_eq!(decoder.feed(&bytes[..6]).unwrap(), DecodeStatus::Incomplete { needed: Some(len - 6) });
+    assert_eq!(decoder.feed(&bytes[6..10]).unwrap(), DecodeStatus::Incomplete { needed: Some(len - 10) });
+    assert_eq!(decoder.feed(&bytes[10..]).unwrap(), DecodeStatus::Complete(value.clone(), len - 10));
+
+    // The rest of the last chunk is the next value
+    assert_eq!(decoder.feed(&bytes[len..]).unwrap(), DecodeStatus::Complete(value, len));
+}
+
+#[test]
+fn test_decoder_error_discards_input() {
+    let mut bytes = to_bytes(&Boxed::new(Updates { texts: vec![], seq: 0 })).unwrap();
+    bytes[0] = 0x00;
+
+    let mut decoder = Decoder::<Boxed<Updates>>::new(None);
+    assert_eq!(decoder.feed(&bytes[..2]).unwrap(), DecodeStatus::Incomplete { needed: None });
+    assert!(decoder.feed(&bytes[2..]).is_err());
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn test_decoder_unknown_variant_byte_by_byte() {
+    let mut decoder = Decoder::<MediaMessage>::new(None);
+    let (last, init) = MEDIA_MESSAGE_SERIALIZED.split_last().unwrap();
+
+    for byte in init {
+        match decoder.feed(&[*byte]).unwrap() {
+            DecodeStatus::Incomplete { .. } => (),
+            DecodeStatus::Complete(..) => panic!("decoded a value from incomplete input"),
+        }
+    }
+
+    match decoder.feed(&[*last]).unwrap() {
+        DecodeStatus::Complete(message, 1) => {
+            assert_eq!(message.views, 42);
+            assert_eq!(*message.media.inner(), Media::Unknown { id: 0x12345678, raw: vec![1, 2, 3, 4, 5, 6, 7, 8] });
+        },
+        status => panic!("unexpected status: {:?}", status),
+    }
+}
+
+#[test]
+fn test_decoder_reads_to_end_at_finish() {
+    let frame = Frame {
+        auth_key_id: 0x0123_4567_89ab_cdef,
+        data: UnsizedByteBuf::with_alignment(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12], Alignment::Four),
+    };
+    let bytes = to_bytes(&frame).unwrap();
+
+    let mut decoder = Decoder::<Frame>::new(None);
+    assert_eq!(decoder.feed(&bytes[..4]).unwrap(), DecodeStatus::Incomplete { needed: None });
+    assert_eq!(decoder.feed(&bytes[4..12]).unwrap(), DecodeStatus::Incomplete { needed: None });
+    assert_eq!(decoder.feed(&bytes[12..]).unwrap(), DecodeStatus::Incomplete { needed: None });
+    assert_eq!(decoder.buffered_len(), bytes.len());
+    assert_eq!(decoder.finish().unwrap(), DecodeStatus::Complete(frame.clone(), bytes.len()));
+    assert_eq!(decoder.buffered_len(), 0);
+
+    // The decoder can be reused for the next stream
+    assert_eq!(decoder.feed(&bytes).unwrap(), DecodeStatus::Incomplete { needed: None });
+    assert_eq!(decoder.finish().unwrap(), DecodeStatus::Complete(frame, bytes.len()));
+    assert_eq!(decoder.finish().unwrap(), DecodeStatus::Incomplete { needed: None });
+}
+
+#[test]
+fn test_decoder_truncated_packed_value() {
+    let value = BindAuthKey {
+        perm_auth_key_id: -1,
+        encrypted_message: Packed::new(Boxed::new(Foo {
+            has_receiver: true,
+            size: 57,
+            raw_info: ByteBuf::from(vec![56, 114, 200, 1]),
+            to_be_skipped: 0,
+        })),
+    };
+    let mut bytes = to_bytes(&value).unwrap();
+    // The packed value claims to be shorter than it is, more input won't help
+    assert_eq!(bytes[8], 24);
+    bytes[8] = 20;
+
+    let mut decoder = Decoder::<BindAuthKey>::new(None);
+    assert_eq!(decoder.feed(&bytes[..20]).unwrap(), DecodeStatus::Incomplete { needed: Some(12) });
+
+    let err = decoder.feed(&bytes[20..]).unwrap_err();
+    match *err.kind() {
+        ErrorKind::SizeLimitExceeded { declared: 20, offset: 9, .. } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn test_peek_past_declared_size() {
+    let bytes = [0x1e, 0xab, 0xa1, 0x5c];
+    let mut de = serde_mtproto_other_name::Deserializer::new(&bytes[..], None);
+
+    // Peeking past the declared size is a data error, not a lack of input
+    let err = de.with_declared_size(2, |de| de.peek_type_id()).unwrap_err();
+    match *err.kind() {
+        ErrorKind::SizeLimitExceeded { declared: 2, offset: 0, exceeded_by: 2 } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+
+#[test]
+fn test_config_float_narrowing() {
+    let exact = to_bytes(&0.5f64).unwrap();
+    let inexact = to_bytes(&0.1f64).unwrap();
+    let huge = to_bytes(&1e300f64).unwrap();
+
+    let checked = Config::new();
+    assert_eq!(checked.from_bytes::<f32>(&inexact, None).unwrap(), 0.1f32);
+    assert!(checked.from_bytes::<f32>(&huge, None).is_err());
+
+    let strict = Config::new().float_narrowing(FloatNarrowing::Strict);
+    assert_eq!(strict.from_bytes::<f32>(&exact, None).unwrap(), 0.5f32);
+    match *strict.from_bytes::<f32>(&inexact, None).unwrap_err().kind() {
+        ErrorKind::FloatCast { value } => assert_eq!(value, 0.1f64),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    let lenient = Config::new().float_narrowing(FloatNarrowing::Lenient);
+    assert_eq!(lenient.from_bytes::<f32>(&huge, None).unwrap(), std::f32::INFINITY);
+}
+
+#[test]
+fn test_config_reject_trailing_bytes() {
+    let mut bytes = to_bytes(&Boxed::new(Updates { texts: vec!["x".to_owned()], seq: 1 })).unwrap();
+    bytes.push(0);
+
+    let config = Config::new().reject_trailing_bytes(true);
+    assert!(config.from_bytes::<Boxed<Updates>>(&bytes[..bytes.len() - 1], None).is_ok());
+
+    let offset = (bytes.len() - 1) as u64;
+    match *config.from_bytes::<Boxed<Updates>>(&bytes, None).unwrap_err().kind() {
+        ErrorKind::TrailingBytes { offset: o } => assert_eq!(o, offset),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+    match *config.from_reader::<_, Boxed<Updates>>(Cursor::new(&bytes), None).unwrap_err().kind() {
+        ErrorKind::TrailingBytes { offset: o } => assert_eq!(o, offset),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_config_verify_padding() {
+    let mut bytes = to_bytes(&"ab").unwrap();
+    assert_eq!(bytes, [2, b'a', b'b', 0]);
+    bytes[3] = 1;
+
+    assert_eq!(Config::new().from_bytes::<String>(&bytes, None).unwrap(), "ab");
+
+    let config = Config::new().verify_padding(true);
+    match *config.from_bytes::<String>(&bytes, None).unwrap_err().kind() {
+        ErrorKind::NonZeroPadding { offset } => assert_eq!(offset, 3),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_config_sort_maps() {
+    let config = Config::new().sort_maps(true);
+    let mut expected = None;
+
+    // Different insertion orders and hashers still produce the same bytes
+    for i in 0..8 {
+        let mut map = HashMap::new();
+        for j in 0..16u32 {
+            let key = (i * 7 + j) % 16;
+            map.insert(format!("key {}", key), key);
+        }
+
+        let bytes = config.to_bytes(&map).unwrap();
+        assert_eq!(config.from_bytes::<HashMap<String, u32>>(&bytes, None).unwrap(), map);
+
+        match expected {
+            Some(ref expected) => assert_eq!(&bytes, expected),
+            None => expected = Some(bytes),
+        }
+    }
+
+    // Keys are ordered by their serialized representation
+    let map = btreemap!{ 0x0100u32 => 1u32, 0x0002 => 2 };
+    assert_eq!(config.to_bytes(&map).unwrap(), [
+        2, 0, 0, 0,
+        0, 1, 0, 0, 1, 0, 0, 0,
+        2, 0, 0, 0, 2, 0, 0, 0,
+    ]);
+}
+
+#[test]
+fn test_config_char_encoding() {
+    assert!(to_bytes(&'я').is_err());
+
+    let int = Config::new().char_encoding(CharEncoding::Int);
+    let bytes = int.to_bytes(&'я').unwrap();
+    assert_eq!(bytes, [0x4f, 0x04, 0, 0]);
+    assert_eq!(int.from_bytes::<char>(&bytes, None).unwrap(), 'я');
+    assert!(int.from_bytes::<char>(&[0x00, 0xd8, 0, 0], None).is_err());
+
+    let utf8 = Config::new().char_encoding(CharEncoding::Utf8);
+    let bytes = utf8.to_bytes(&'я').unwrap();
+    assert_eq!(bytes, [2, 0xd1, 0x8f, 0]);
+    assert_eq!(utf8.from_bytes::<char>(&bytes, None).unwrap(), 'я');
+    assert!(utf8.from_bytes::<char>(&to_bytes(&"ab").unwrap(), None).is_err());
+    assert!(utf8.from_bytes::<char>(&to_bytes(&"").unwrap(), None).is_err());
+}
+
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, MtProtoSized)]
+struct User {
+    id: u32,
+    first_name: TlString,
+}
+
+#[test]
+fn test_tl_string() {
+    let invalid = vec![b'J', 0xff, b'e'];
+    let user = User { id: 7, first_name: TlString::new(invalid.clone()) };
+
+    let bytes = to_bytes(&user).unwrap();
+    assert_eq!(bytes, [7, 0, 0, 0, 3, b'J', 0xff, b'e']);
+    assert_size_hint_matches(&user);
+    assert!(from_bytes::<(u32, String)>(&bytes, None).is_err());
+
+    let deserialized: User = from_bytes(&bytes, None).unwrap();
+    assert_eq!(deserialized, user);
+    assert_eq!(deserialized.first_name.as_bytes(), &invalid[..]);
+    assert!(deserialized.first_name.to_str().is_err());
+    assert_eq!(deserialized.first_name.to_string_lossy(), "J\u{fffd}e");
+    assert_eq!(deserialized.first_name.to_string(), "J\u{fffd}e");
+
+    // Valid strings are interchangeable with `String`
+    let name = TlString::from("Jane");
+    assert_eq!(name.to_str(), Ok("Jane"));
+    assert_eq!(to_bytes(&name).unwrap(), to_bytes(&"Jane").unwrap());
+
+    let boxed = to_bytes(&Boxed::new(name.clone())).unwrap();
+    assert_eq!(boxed, to_bytes(&Boxed::new("Jane".to_owned())).unwrap());
+    assert_eq!(from_bytes::<Boxed<TlString>>(&boxed, None).unwrap().into_inner(), name);
+}
+
+#[test]
+fn test_config_lossy_strings() {
+    let bytes = to_bytes(&TlString::new(vec![b'a', 0xc0, b'b'])).unwrap();
+
+    match *from_bytes::<String>(&bytes, None).unwrap_err().kind() {
+        ErrorKind::InvalidUtf8(_) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    let config = Config::new().lossy_strings(true);
+    assert_eq!(config.from_bytes::<String>(&bytes, None).unwrap(), "a\u{fffd}b");
+}
+
+
+#[test]
+fn test_config_nested_values() {
+    let int = Config::new().char_encoding(CharEncoding::Int);
+    let char_bytes = int.to_bytes(&'я').unwrap();
+
+    // Packed values follow the config of the enclosing value
+    assert!(to_bytes(&Packed::new('я')).is_err());
+    let bytes = int.to_bytes(&Packed::new('я')).unwrap();
+    assert_eq!(bytes, to_bytes(&ByteBuf::from(char_bytes.clone())).unwrap());
+    assert_eq!(int.from_bytes::<Packed<char>>(&bytes, None).unwrap().into_inner(), 'я');
+    assert!(from_bytes::<Packed<char>>(&bytes, None).is_err());
+
+    let raw = Raw::with_config(&'я', int).unwrap();
+    assert_eq!(raw.as_bytes(), &char_bytes[..]);
+    assert_eq!(raw.decode_with_config(None, int).unwrap(), 'я');
+    assert!(raw.decode(None).is_err());
+    assert_eq!(WithRaw::with_config('я', int).unwrap().raw_bytes(), &char_bytes[..]);
+
+    let mut sized = vec![4, 0, 0, 0];
+    sized.extend_from_slice(&char_bytes);
+    assert!(to_bytes_with_size(&'я').is_err());
+    assert_eq!(int.to_bytes_with_size(&'я').unwrap(), sized);
+
+    let mut decoder = Decoder::<char>::with_config(None, int);
+    assert_eq!(decoder.feed(&char_bytes[..2]).unwrap(), DecodeStatus::Incomplete { needed: None });
+    assert_eq!(decoder.feed(&char_bytes[2..]).unwrap(), DecodeStatus::Complete('я', 2));
+}
+
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, MtProtoSized)]
+struct DcOption {
+    #[serde(with = "serde_mtproto_other_name::with::tl_bool")]
+    #[mtproto_sized(with = "serde_mtproto_other_name::with::tl_bool")]
+    ipv6: bool,
+    #[serde(with = "serde_mtproto_other_name::with::ipv4")]
+    #[mtproto_sized(with = "serde_mtproto_other_name::with::ipv4")]
+    ip_address: Ipv4Addr,
+    #[serde(with = "serde_mtproto_other_name::with::ipv6")]
+    #[mtproto_sized(with = "serde_mtproto_other_name::with::ipv6")]
+    ipv6_address: Ipv6Addr,
+    #[serde(with = "serde_mtproto_other_name::with::unix_time")]
+    #[mtproto_sized(with = "serde_mtproto_other_name::with::unix_time")]
+    expires: SystemTime,
+    #[serde(with = "serde_mtproto_other_name::with::msg_id")]
+    #[mtproto_sized(with = "serde_mtproto_other_name::with::msg_id")]
+    msg_id: MsgId,
+}
+
+#[test]
+fn test_with_adapters() {
+    let dc_option = DcOption {
+        ipv6: false,
+        ip_address: Ipv4Addr::new(149, 154, 167, 51),
+        ipv6_address: "2001:67c:4e8:f002::a".parse().unwrap(),
+        expires: UNIX_EPOCH + Duration::from_millis(1_500_000_000_999),
+        msg_id: MsgId::new(0x5953_2f00_0000_0004),
+    };
+
+    let bytes = to_bytes(&dc_option).unwrap();
+    assert_eq!(bytes, vec![
+        0x37, 0x97, 0x79, 0xbc,
+        0x33, 0xa7, 0x9a, 0x95,
+        16, 0x20, 0x01, 0x06, 0x7c, 0x04, 0xe8, 0xf0, 0x02, 0, 0, 0, 0, 0, 0, 0, 0x0a, 0, 0, 0,
+        0x00, 0x2f, 0x68, 0x59,
+        0x04, 0, 0, 0, 0x00, 0x2f, 0x53, 0x59,
+    ]);
+    assert_size_hint_matches(&dc_option);
+
+    // Fractions of a second are lost
+    let expected = DcOption { expires: UNIX_EPOCH + Duration::from_secs(1_500_000_000), ..dc_option };
+    assert_eq!(from_bytes::<DcOption>(&bytes, None).unwrap(), expected);
+    assert_eq!(expected.msg_id.unix_time(), 0x5953_2f00);
+
+    // Times before the epoch are rounded down too
+    let early = to_bytes(&UnixTime(UNIX_EPOCH - Duration::from_millis(1500))).unwrap();
+    assert_eq!(early, [0xfe, 0xff, 0xff, 0xff]);
+    assert_eq!(from_bytes::<UnixTime>(&early, None).unwrap(), UnixTime(UNIX_EPOCH - Duration::from_secs(2)));
+
+    assert!(to_bytes(&UnixTime(UNIX_EPOCH + Duration::from_secs(1 << 31))).is_err());
+    assert!(from_bytes::<DcOption>(&[0, 0, 0, 0], None).is_err());
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct UnixTime(#[serde(with = "serde_mtproto_other_name::with::unix_time")] SystemTime);
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_with_chrono_unix_time() {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Date(#[serde(with = "serde_mtproto_other_name::with::chrono_unix_time")] DateTime<Utc>);
+
+    let date = Date(Utc.timestamp_opt(1_500_000_000, 0).single().unwrap());
+    let bytes = to_bytes(&date).unwrap();
+
+    assert_eq!(bytes, [0x00, 0x2f, 0x68, 0x59]);
+    assert_eq!(from_bytes::<Date>(&bytes, None).unwrap(), date);
+    assert!(to_bytes(&Date(Utc.timestamp_opt(1 << 32, 0).single().unwrap())).is_err());
+}
+
+#[cfg(feature = "num-bigint")]
+#[test]
+fn test_with_big_uint() {
+    use num_bigint::BigUint;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct ServerDhInner {
+        g: u32,
+        #[serde(with = "serde_mtproto_other_name::with::big_uint")]
+        dh_prime: BigUint,
+    }
+
+    impl MtProtoSized for ServerDhInner {
+        fn size_hint(&self) -> serde_mtproto_other_name::Result<usize> {
+            Ok(self.g.size_hint()? + serde_mtproto_other_name::with::big_uint::size_hint(&self.dh_prime)?)
+        }
+    }
+
+    for dh_prime in vec![BigUint::from(0u32), BigUint::from(0x01_0203u32), BigUint::from(1u32) << 2047] {
+        let value = ServerDhInner { g: 3, dh_prime: dh_prime };
+        let bytes = to_bytes(&value).unwrap();
+
+        assert_size_hint_matches(&value);
+        assert_eq!(from_bytes::<ServerDhInner>(&bytes, None).unwrap(), value);
+    }
+
+    let value = ServerDhInner { g: 3, dh_prime: BigUint::from(0x01_0203u32) };
+    assert_eq!(to_bytes(&value).unwrap(), [3, 0, 0, 0, 3, 1, 2, 3]);
+}
+
+
+fn serialize_as_u8<S>(value: &u32, serializer: S) -> Result<S::Ok, S::Error>
+    where S: serde::Serializer
+{
+    serializer.serialize_u8(*value as u8)
+}
+
+fn u8_size_hint(_value: &u32) -> serde_mtproto_other_name::Result<usize> {
+    Ok(4)
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+mod as_long {
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(u64::from(*value))
+    }
+
+    pub fn size_hint(_value: &u32) -> ::serde_mtproto_other_name::Result<usize> {
+        Ok(8)
+    }
+}
+
+#[derive(Serialize, MtProtoSized)]
+struct SizedBySerdeAttrs {
+    #[serde(skip_serializing)]
+    cached: Vec<u8>,
+    #[serde(serialize_with = "serialize_as_u8")]
+    #[mtproto_sized(size_with = "u8_size_hint")]
+    small: u32,
+    #[serde(skip_serializing_if = "is_zero")]
+    optional: u64,
+    #[serde(serialize_with = "as_long::serialize")]
+    #[mtproto_sized(with = "as_long")]
+    long: u32,
+}
+
+#[derive(Serialize, MtProtoSized)]
+enum SizedBySerdeAttrsEnum {
+    Struct {
+        #[serde(skip)]
+        cached: String,
+        #[serde(with = "serde_mtproto_other_name::with::ipv6")]
+        #[mtproto_sized(with = "serde_mtproto_other_name::with::ipv6")]
+        addr: Ipv6Addr,
+    },
+    Tuple(#[serde(skip)] String, #[serde(skip_serializing_if = "is_zero")] u64, bool),
+}
+
+#[test]
+fn test_derive_mt_proto_sized_with_serde_attrs() {
+    let values = vec![
+        SizedBySerdeAttrs { cached: vec![1, 2, 3], small: 5, optional: 0, long: 7 },
+        SizedBySerdeAttrs { cached: vec![], small: 5, optional: 6, long: 7 },
+    ];
+
+    for value in &values {
+        assert_size_hint_matches(value);
+    }
+    assert_eq!(values[0].size_hint().unwrap(), 12);
+
+    let values = vec![
+        SizedBySerdeAttrsEnum::Struct { cached: "cached".to_owned(), addr: Ipv6Addr::new(1, 2, 3, 4, 5, 6, 7, 8) },
+        SizedBySerdeAttrsEnum::Tuple("cached".to_owned(), 0, true),
+        SizedBySerdeAttrsEnum::Tuple("cached".to_owned(), 1, true),
+    ];
+
+    for value in &values {
+        assert_size_hint_matches(value);
+    }
+    assert_eq!(values[1].size_hint().unwrap(), 4);
+}
+
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSerialize, MtProtoDeserialize, TlDescribe)]
+enum NativeMedia {
+    #[id = 0x3ded6320]
+    Empty,
+    #[id = "0x7912b71f"]
+    Geo {
+        lat: f64,
+        long: f64,
+    },
+    #[id = 0xa32dd600]
+    Photo(u64, #[mtproto(bare)] Vec<i32>),
+    #[mtproto(other)]
+    Unknown {
+        id: u32,
+        raw: Vec<u8>,
+    },
+}
+
+#[derive(Debug, PartialEq, MtProtoSerialize, MtProtoDeserialize)]
+#[id = 0x44f9b43d]
+struct NativeMessage<T> {
+    id: T,
+    flag: bool,
+    media: NativeMedia,
+    medias: Vec<NativeMedia>,
+    #[mtproto(bare)]
+    entities: Vec<(u32, String)>,
+    #[mtproto(serde)]
+    extra: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, MtProtoSerialize, MtProtoDeserialize)]
+#[id = 0x5c4d7a1b]
+struct NativeBareOnly(i32, String, ByteBuf);
+
+#[test]
+fn test_native_derives() {
+    let message = NativeMessage {
+        id: 7u64,
+        flag: true,
+        media: NativeMedia::Empty,
+        medias: vec![
+            NativeMedia::Geo { lat: 1.5, long: -2.0 },
+            NativeMedia::Photo(0xdead, vec![-1, 2]),
+        ],
+        entities: vec![(1, "a".to_owned())],
+        extra: btreemap!{ "b".to_owned() => 2 },
+    };
+
+    let bytes = serde_mtproto_other_name::native::to_bytes(&message).unwrap();
+    assert_eq!(&bytes[..16], &[
+        0x3d, 0xb4, 0xf9, 0x44,  // NativeMessage id
+        7, 0, 0, 0, 0, 0, 0, 0,  // id
+        0xb5, 0x75, 0x72, 0x99,  // boolTrue
+    ]);
+    assert_eq!(&bytes[16..20], &[0x20, 0x63, 0xed, 0x3d]);  // NativeMedia::Empty id
+    assert_eq!(&bytes[20..24], &[0x15, 0xc4, 0xb5, 0x1c]);  // Vector id
+    assert_eq!(&bytes[bytes.len() - 24..], &[
+        1, 0, 0, 0, 1, 0, 0, 0, 1, 97, 0, 0,  // entities, bare vector has no id
+        1, 0, 0, 0, 1, 98, 0, 0, 2, 0, 0, 0,  // extra
+    ]);
+
+    let decoded: NativeMessage<u64> = serde_mtproto_other_name::native::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded, message);
+
+    // Serde gets the same bytes when boxing the enum explicitly
+    let media = NativeMedia::Photo(0xdead, vec![-1, 2]);
+    assert_eq!(serde_mtproto_other_name::native::to_bytes(&media).unwrap(),
+               to_bytes(&Boxed::new(NativeMedia::Photo(0xdead, vec![-1, 2]))).unwrap());
+
+    // Structs of built-in types are encoded exactly like by Serde
+    let bare = NativeBareOnly(-3, "hi".to_owned(), ByteBuf::from(vec![1, 2, 3]));
+    let mut ser = Serializer::new(Vec::new());
+    bare.serialize_bare(&mut ser).unwrap();
+    assert_eq!(ser.into_writer(), to_bytes(&bare).unwrap());
+}
+
+#[test]
+fn test_native_derives_ids() {
+    let err = serde_mtproto_other_name::native::from_bytes::<NativeBareOnly>(&[1, 2, 3, 4]).unwrap_err();
+    match *err.kind() {
+        ErrorKind::InvalidTypeId { found: 0x04030201, expected } => assert_eq!(expected, &[0x5c4d7a1b]),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    // Unknown constructors are kept by the `#[mtproto(other)]` variant within a declared size
+    // and written back as is
+    let bytes = [0xef, 0xbe, 0xad, 0xde, 1, 2, 3, 4, 0x2a, 0, 0, 0];
+    let mut de = MtProtoDeserializer::new(&bytes[..], None);
+    let media = de.with_declared_size(8, NativeMedia::deserialize_boxed).unwrap();
+    assert_eq!(media, NativeMedia::Unknown { id: 0xdeadbeef, raw: vec![1, 2, 3, 4] });
+    assert_eq!(u32::deserialize_bare(&mut de).unwrap(), 42);
+    assert_eq!(serde_mtproto_other_name::native::to_bytes(&media).unwrap(), &bytes[..8]);
+
+    // Without one they can't be told apart from the following vector elements
+    let mut bytes = vec![0x15, 0xc4, 0xb5, 0x1c, 2, 0, 0, 0, 0xef, 0xbe, 0xad, 0xde, 1, 2, 3, 4];
+    bytes.extend_from_slice(&serde_mtproto_other_name::native::to_bytes(&NativeMedia::Empty).unwrap());
+    let err = serde_mtproto_other_name::native::from_bytes::<Vec<NativeMedia>>(&bytes).unwrap_err();
+    match *err.kind() {
+        ErrorKind::InvalidTypeId { found: 0xdeadbeef, .. } => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    // A bare enum can't tell which constructor it holds
+    let mut de = MtProtoDeserializer::new(&[0u8; 16][..], None);
+    assert!(NativeMedia::deserialize_bare(&mut de).is_err());
+
+    let err = serde_mtproto_other_name::native::from_bytes::<Vec<u32>>(&[0, 0, 0, 0]).unwrap_err();
+    assert!(err.is_data());
+}
+
+
+#[allow(dead_code)]
+#[derive(TlDescribe)]
+#[id = 0x5a3f01c2]
+#[tl(type_name = "Chat")]
+struct SchemaChat<T> {
+    #[tl(flags)]
+    flags: u32,
+    #[tl(flag = "flags.2")]
+    #[mtproto(bare)]
+    media: Option<NativeMedia>,
+    #[mtproto(bare)]
+    members: Vec<T>,
+    r#type: Box<u8>,
+    photos: Vec<Vec<ByteBuf>>,
+}
+
+#[allow(dead_code)]
+#[derive(TlDescribe)]
+#[id = 0x1e7f3c9a]
+#[tl(name = "messages.getChats", function, result = "Vec<SchemaChat<i32>>")]
+struct SchemaGetChats(#[mtproto(bare)] Vec<i64>, bool);
+
+#[test]
+fn test_tl_describe() {
+    // `#[mtproto(other)]` variants have no constructor in the schema
+    let names: Vec<_> = NativeMedia::tl_constructors().into_iter().map(|c| c.name).collect();
+    assert_eq!(names, ["nativeMediaEmpty", "nativeMediaGeo", "nativeMediaPhoto"]);
+    assert_eq!(NativeMedia::tl_constructors()[2].to_string(),
+               "nativeMediaPhoto#a32dd600 field0:long field1:vector<int> = NativeMedia;");
+
+    assert_eq!(SchemaChat::<u64>::tl_constructors()[0].to_string(),
+               "schemaChat#5a3f01c2 flags:# media:flags.2?%NativeMedia members:vector<long> \
+                type:int photos:Vector<Vector<bytes>> = Chat;");
+    assert_eq!(SchemaGetChats::tl_constructors()[0].to_string(),
+               "messages.getChats#1e7f3c9a field0:vector<long> field1:Bool = Vector<Chat>;");
+}
+
+#[test]
+fn test_schema() {
+    let schema = Schema::new()
+        .add::<SchemaGetChats>()
+        .add::<NativeMedia>()
+        .add::<Box<NativeMedia>>()
+        .add::<SchemaChat<i32>>()
+        .add::<Vec<u32>>();
+
+    assert_eq!(schema.constructors().len(), 4);
+    assert_eq!(schema.functions().len(), 1);
+    assert_eq!(schema.to_string(), "\
+nativeMediaEmpty#3ded6320 = NativeMedia;
+nativeMediaGeo#7912b71f lat:double long:double = NativeMedia;
+nativeMediaPhoto#a32dd600 field0:long field1:vector<int> = NativeMedia;
+schemaChat#5a3f01c2 flags:# media:flags.2?%NativeMedia members:vector<int> type:int photos:Vector<Vector<bytes>> = Chat;
+
+---functions---
+
+messages.getChats#1e7f3c9a field0:vector<long> field1:Bool = Vector<Chat>;
+");
+
+    assert_eq!(Schema::new().add::<NativeMedia>().to_string().lines().count(), 3);
+}
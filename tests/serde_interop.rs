@@ -16,7 +16,7 @@ use serde_bytes::ByteBuf;
 
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
-#[id = "7e298afc"]
+#[id = "0x7e298afc"]
 struct Data {
     id: u64,
     raw_data: ByteBuf,
@@ -24,7 +24,7 @@ struct Data {
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
-#[id = "b3185db0"]
+#[id = "0xb3185db0"]
 struct Metadata {
     username: String,
     encrypted: bool,
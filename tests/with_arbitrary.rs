@@ -0,0 +1,124 @@
+extern crate arbitrary;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_mtproto;
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+
+use arbitrary::{Arbitrary, Unstructured};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use serde_mtproto::{Boxed, BoxedWithSize, Identifiable, IntVector, MtProtoSized, PackedInts, Raw, WithRaw, WithSize};
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[mtproto_identifiable(arbitrary)]
+#[id = "0xa821f7fe"]
+struct SimpleStruct {
+    field1: bool,
+    field2: String,
+    field3: Boxed<Point>,
+    field4: WithSize<(i64, u16)>,
+    field5: Vec<BoxedWithSize<Point>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[mtproto_identifiable(arbitrary)]
+#[id = "0x341b1c93"]
+struct Point(i32, i32);
+
+#[cfg_attr(feature = "cargo-clippy", allow(enum_variant_names))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[mtproto_identifiable(arbitrary)]
+enum SimpleEnum {
+    #[id = "0x2d893c40"]
+    Variant1,
+    #[id = "0xb5a92a28"]
+    Variant2 {
+        variant2_field1: Vec<u8>,
+        variant2_field2: BoxedWithSize<u32>,
+    },
+    #[id = "0x8d72c9e1"]
+    Variant3(u64, i32, f64),
+}
+
+
+/// Fill a buffer with deterministic pseudo-random bytes so that failures are reproducible.
+fn raw_data(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9) | 1;
+
+    (0..len).map(|_| {
+        // xorshift32
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        (state >> 24) as u8
+    }).collect()
+}
+
+/// Generate values from many inputs and check them with `check`.
+fn for_arbitrary_values<T, F>(check: F)
+    where T: for<'a> Arbitrary<'a>,
+          F: Fn(T)
+{
+    for seed in 0..256 {
+        let data = raw_data(seed, 1024);
+        let mut u = Unstructured::new(&data);
+
+        if let Ok(value) = T::arbitrary(&mut u) {
+            check(value);
+        }
+    }
+}
+
+fn assert_round_trip<T>(value: &T)
+    where T: PartialEq + ::std::fmt::Debug + Serialize + DeserializeOwned
+{
+    let serialized = serde_mtproto::to_bytes(value).unwrap();
+    let deserialized: T = serde_mtproto::from_bytes(&serialized, None).unwrap();
+
+    assert_eq!(deserialized, *value);
+}
+
+
+#[test]
+fn ser_de_reversible() {
+    for_arbitrary_values(|data: SimpleStruct| {
+        assert_round_trip(&data);
+        assert_eq!(data.size_hint().unwrap(), serde_mtproto::to_bytes(&data).unwrap().len());
+    });
+}
+
+#[test]
+fn generated_ids_are_valid() {
+    for_arbitrary_values(|data: SimpleEnum| {
+        assert!(SimpleEnum::all_type_ids().contains(&data.type_id()));
+    });
+}
+
+#[test]
+fn wrappers_ser_de_reversible() {
+    for_arbitrary_values(|data: Boxed<SimpleStruct>| assert_round_trip(&data));
+    for_arbitrary_values(|data: WithSize<SimpleStruct>| assert_round_trip(&data));
+    for_arbitrary_values(|data: BoxedWithSize<SimpleStruct>| assert_round_trip(&data));
+    for_arbitrary_values(|data: WithRaw<SimpleStruct>| assert_round_trip(&data));
+    for_arbitrary_values(|data: Raw<Boxed<Point>>| {
+        assert_round_trip(&data);
+        assert!(data.decode(None).is_ok());
+    });
+}
+
+#[test]
+fn packed_ints_ser_de_reversible() {
+    for_arbitrary_values(|data: IntVector<i64>| {
+        assert_round_trip(&data);
+        assert_eq!(data.size_hint().unwrap(), serde_mtproto::to_bytes(&data).unwrap().len());
+    });
+    for_arbitrary_values(|data: PackedInts<u32, 8>| {
+        assert_round_trip(&data);
+        assert_eq!(data.size_hint().unwrap(), 32);
+    });
+    for_arbitrary_values(|data: (i128, u128)| assert_round_trip(&data));
+}
@@ -0,0 +1,72 @@
+#[macro_use]
+extern crate proptest;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_mtproto;
+#[macro_use]
+extern crate serde_mtproto_derive;
+
+
+use serde_mtproto::{Boxed, BoxedWithSize, Identifiable, IntVector, PackedInts, WithSize};
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[mtproto_identifiable(proptest)]
+#[id = "0xa821f7fe"]
+struct SimpleStruct {
+    field1: bool,
+    field2: String,
+    field3: Boxed<Point>,
+    field4: WithSize<(i64, u16)>,
+    field5: Vec<BoxedWithSize<Point>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[mtproto_identifiable(proptest)]
+#[id = "0x341b1c93"]
+struct Point(i32, i32);
+
+#[cfg_attr(feature = "cargo-clippy", allow(enum_variant_names))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MtProtoIdentifiable, MtProtoSized)]
+#[mtproto_identifiable(proptest)]
+enum SimpleEnum {
+    #[id = "0x2d893c40"]
+    Variant1,
+    #[id = "0xb5a92a28"]
+    Variant2 {
+        variant2_field1: Vec<u8>,
+        variant2_field2: BoxedWithSize<u32>,
+    },
+    #[id = "0x8d72c9e1"]
+    Variant3(u64, i32, f64),
+}
+
+
+proptest! {
+    #[test]
+    fn ser_de_reversible(data: SimpleStruct) {
+        let ser = serde_mtproto::to_bytes(&data).unwrap();
+        let de = serde_mtproto::from_bytes::<SimpleStruct>(&ser, None).unwrap();
+
+        prop_assert_eq!(de, data);
+    }
+
+    #[test]
+    fn generated_ids_are_valid(data: SimpleEnum) {
+        prop_assert!(SimpleEnum::all_type_ids().contains(&data.type_id()));
+    }
+
+    #[test]
+    fn packed_ints_ser_de_reversible(ids: IntVector<i64>, nonce: PackedInts<u32, 8>) {
+        let ser = serde_mtproto::to_bytes(&(&ids, &nonce)).unwrap();
+        let de = serde_mtproto::from_bytes::<(IntVector<i64>, PackedInts<u32, 8>)>(&ser, None).unwrap();
+
+        prop_assert_eq!(de, (ids, nonce));
+    }
+
+    #[test]
+    fn from_bytes_never_panics(bytes: Vec<u8>) {
+        let _ = serde_mtproto::from_bytes::<SimpleStruct>(&bytes, None);
+    }
+}